@@ -1,18 +1,60 @@
+use crate::automation::{Automation, Breakpoint};
+use crate::envelope::{Envelope, Envelopes};
+use crate::groove::{Groove, Grooves};
+use crate::history::{Edit, History};
+use crate::metronome::Metronome;
+use crate::instruction::{InstructionKind, Status, MAX_VELOCITY};
 use crate::instruction_handler::InstructionHandler;
 use crate::instrument::oscillator::{Oscillator};
 use crate::instrument::synth::Synth;
-use crate::instrument::Instrument;
+use crate::instrument::smoothed::Smoothed;
+use crate::instrument::{param, Instrument};
+use crate::looping::Loop;
+use crate::tempo::{check_signature, TempoMap, MAX_BPM, MIN_BPM};
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 pub struct App {
     // FIXME: temporary pubs
     pub instruments: Vec<Box<dyn Instrument>>,
     pub instructions: InstructionHandler,
+    pub automation: Automation,
+    pub envelopes: Envelopes,
+    pub tempo: TempoMap,
+    pub grooves: Grooves,
+    pub looping: Option<Loop>,
+    /// Bounds of the pass playback is in, with the loop and version of the tempo map they're for
+    loop_bounds: Option<(Loop, u64, (u128, u128))>,
+    pub metronome: Metronome,
+    /// Whether playback starts with a bar of clicks
+    pub count_in: bool,
+    /// Ticks of the count-in played so far, while counting in
+    counting: Option<u128>,
+    /// Whether instruments keep sounding while paused, to audition them
+    jamming: bool,
+    /// Set while rendering to a file, which leaves the metronome out unless it's wanted there
+    pub rendering: bool,
+    history: History,
+    /// The grooved row each instrument last played in, so playback doesn't look it up every sample
+    grooved_rows: RefCell<HashMap<u128, GroovedRow>>,
+    /// Level of the mix, from 0 to 1
+    master: Smoothed,
     sample_rate: f32,
-    delay: u16,
     tick: u128,
     playing: bool
 }
 
+/// Where a row and the next one start, on the grid and once a groove moves them
+#[derive(Copy, Clone)]
+struct GroovedRow {
+    /// Version of the tempo map it was worked out with
+    tempo: u64,
+    row: u128,
+    offsets: (f32, f32),
+    grid: (u128, u128),
+    moved: (u128, u128),
+}
+
 impl App {
     pub fn new() -> Self {
         Self {
@@ -21,77 +63,522 @@ impl App {
                 Box::new(Oscillator::default())
             ],
             instructions: InstructionHandler::new(),
+            automation: Automation::new(),
+            envelopes: Envelopes::new(),
+            tempo: TempoMap::new(),
+            grooves: Grooves::new(),
+            looping: None,
+            loop_bounds: None,
+            metronome: Metronome::new(),
+            count_in: false,
+            counting: None,
+            jamming: false,
+            rendering: false,
+            history: History::new(),
+            grooved_rows: RefCell::new(HashMap::new()),
+            master: Smoothed::new(1.0),
             sample_rate: 0.0,
-            delay: 125,
             tick: 0,
             playing: false,
         }
     }
 
 
-    pub fn get_delay(&self) -> u16 {
-        self.delay
-    }
-
-    pub fn set_delay(&mut self, delay: u16) {
-        self.delay = delay;
-    }
-
+    /// Tempo before the first tempo change
     pub fn get_bpm(&self) -> u16 {
-        return 15000 / self.delay;
+        self.tempo.initial_bpm() as u16
     }
 
     pub fn set_bpm(&mut self, bpm: u16) {
-        self.delay = 15000 / bpm
+        self.set_initial_bpm(bpm as f32)
+    }
+
+    /// Current playback position, in ticks
+    pub fn position(&self) -> u128 {
+        self.tick
     }
 
     pub fn set_sample_rates(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate;
+        self.master.set_sample_rate(sample_rate);
+        self.tempo.set_sample_rate(sample_rate);
+        self.metronome.set_sample_rate(sample_rate);
         self.instruments.iter_mut().for_each(|it| {
             it.set_sample_rate(sample_rate);
         })
     }
 
-    pub fn get_sample_rate(&mut self) -> f32 {
+    pub fn get_sample_rate(&self) -> f32 {
         self.sample_rate
     }
 
+    pub fn master_volume(&self) -> f32 {
+        self.master.target()
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master.set(volume.clamp(0.0, 1.0))
+    }
+
     pub fn play(&mut self) {
+        if !self.playing && self.count_in {
+            self.counting = Some(0);
+        }
         self.playing = true
     }
 
+    pub fn is_counting_in(&self) -> bool {
+        self.counting.is_some()
+    }
+
     pub fn pause(&mut self) {
         self.playing = false
     }
 
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
     pub fn reset(&mut self) {
         self.tick = 0
     }
 
+    /// Moves playback to `tick`, bringing every instrument into the state it would have had
+    /// if playback had started from the beginning. Sounding notes are cut, and no new notes
+    /// are triggered.
+    pub fn seek(&mut self, tick: u128) {
+        self.tick = tick;
+        self.envelopes.stop_all();
+        self.metronome.reset();
+        for inst in self.instruments.iter_mut() {
+            inst.stop();
+        }
+        self.chase();
+    }
+
+    /// Brings every instrument into the state the song has it in at the playhead, leaving
+    /// sounding notes alone, for when the song changes under it
+    pub fn chase(&mut self) {
+        let tick = self.tick;
+        let grid_ticks = (0..self.instruments.len() as u128).map(|i| self.ungrooved_tick(i, tick)).collect::<Vec<_>>();
+        for (i, inst) in self.instruments.iter_mut().enumerate() {
+            for instruction in self.instructions.chase(i as u128, grid_ticks[i]) {
+                // Chased instructions were accepted when they were added, so errors are ignored
+                let _ = inst.apply_instruction(instruction);
+            }
+            for (name, lane) in self.automation.lanes(i as u128) {
+                if let Some(value) = lane.value_at(tick) {
+                    let _ = inst.set_param(name, value);
+                }
+            }
+        }
+    }
+
+    /// Where a row starts once a groove moves it
+    fn moved_row_tick(&self, groove: &Groove, row: u128, start: u128, end: u128) -> u128 {
+        (start as f64 + groove.offset(row) as f64 * (end - start) as f64).max(0.0).round() as u128
+    }
+
+    fn grooved_row(&self, groove: &Groove, row: u128) -> GroovedRow {
+        let grid = (self.tempo.row_tick(row), self.tempo.row_tick(row + 1));
+        let after = self.tempo.row_tick(row + 2);
+        GroovedRow {
+            tempo: self.tempo.version(),
+            row,
+            offsets: (groove.offset(row), groove.offset(row + 1)),
+            grid,
+            moved: (self.moved_row_tick(groove, row, grid.0, grid.1), self.moved_row_tick(groove, row + 1, grid.1, after)),
+        }
+    }
+
+    /// The grooved row of `target` that plays `tick`. Kept until playback leaves it or the
+    /// tempo or groove changes, trying the next row before searching
+    fn grooved_row_at(&self, target: u128, groove: &Groove, tick: u128) -> GroovedRow {
+        let fits = |r: &GroovedRow| {
+            r.tempo == self.tempo.version()
+                && r.offsets == (groove.offset(r.row), groove.offset(r.row + 1))
+                && r.moved.0 < tick
+                && tick <= r.moved.1
+        };
+        let cached = self.grooved_rows.borrow().get(&target).copied();
+        if let Some(row) = cached.filter(fits) {
+            return row;
+        }
+        let next = cached.filter(|r| r.tempo == self.tempo.version()).map(|r| self.grooved_row(groove, r.row + 1));
+        let row = match next.filter(fits) {
+            Some(row) => row,
+            None => {
+                // Rows move less than half a row, so the row that plays `tick` is at most one off
+                let mut row = self.grooved_row(groove, self.tempo.row_at(tick).saturating_sub(1));
+                while row.moved.1 < tick {
+                    row = self.grooved_row(groove, row.row + 1);
+                }
+                row
+            }
+        };
+        self.grooved_rows.borrow_mut().insert(target, row);
+        row
+    }
+
+    /// The first tick on the grid that the groove of `target` plays at `tick` or later. Ticks
+    /// within a row are stretched evenly between where the row and the next one are moved to
+    fn ungrooved_tick(&self, target: u128, tick: u128) -> u128 {
+        let groove = match self.grooves.get(target) {
+            Some(groove) if self.sample_rate > 0.0 && groove.moves() => groove,
+            _ => return tick,
+        };
+        let GroovedRow { grid: (start, end), moved: (from, to), .. } = self.grooved_row_at(target, groove, tick);
+        if tick <= from {
+            return start;
+        }
+        start + ((tick - from) * (end - start)).div_ceil(to - from)
+    }
+
+    /// Instructions `target` plays at `tick`, moved and accented by its groove
+    pub fn grooved_instructions(&self, target: u128, tick: u128) -> Vec<InstructionKind> {
+        let Some(groove) = self.grooves.get(target) else {
+            return self.instructions.get(target, tick);
+        };
+        let range = self.instructions.range(target, self.ungrooved_tick(target, tick), self.ungrooved_tick(target, tick + 1));
+        range.into_iter().map(|(time, kind)| match kind {
+            InstructionKind::State { status: Status::On, velocity } => {
+                let accent = groove.accent(self.tempo.row_at(time));
+                let velocity = (velocity as f32 * accent).round().min(MAX_VELOCITY as f32) as u8;
+                InstructionKind::State { status: Status::On, velocity }
+            }
+            kind => kind,
+        }).collect()
+    }
+
+    /// Lets instruments sound while paused so notes can be auditioned, or silences them again
+    pub fn set_jamming(&mut self, jamming: bool) {
+        if self.jamming && !jamming && !self.playing {
+            // Whatever was auditioned would otherwise pick up where it was when playback starts
+            self.envelopes.stop_all();
+            self.instruments.iter_mut().for_each(|inst| inst.stop());
+        }
+        self.jamming = jamming;
+    }
+
+    /// Plays instructions on an instrument right away, without adding them to the song
+    pub fn audition(&mut self, target: u128, kinds: &[InstructionKind]) {
+        let Some(inst) = self.instruments.get_mut(target as usize) else {
+            return;
+        };
+        for kind in kinds {
+            if let InstructionKind::State { status, .. } = kind {
+                self.envelopes.trigger(target, *status == Status::On);
+            }
+            let _ = inst.apply_instruction(*kind);
+        }
+    }
+
+    /// The tick a note played live right now is recorded at: the playhead, or with `quantize`
+    /// the start of the row being played. Either is on the grid the groove of `target` plays from
+    pub fn record_tick(&self, target: u128, quantize: bool) -> u128 {
+        let tick = self.ungrooved_tick(target, self.tick);
+        match quantize {
+            true => self.tempo.row_tick(self.tempo.row_at(tick)),
+            false => tick,
+        }
+    }
+
+    /// Writes instructions played live into the song, and plays them right away unless the
+    /// playhead is about to
+    pub fn record(&mut self, target: u128, tick: u128, kinds: &[InstructionKind]) {
+        for kind in kinds {
+            self.insert_instruction(target, tick, *kind);
+        }
+        if tick < self.ungrooved_tick(target, self.tick) {
+            self.audition(target, kinds);
+        }
+    }
+
+    /// Applies an edit and records it so it can be undone
+    pub fn edit(&mut self, edit: Edit) {
+        let inverse = edit.apply(self);
+        self.history.record(inverse);
+    }
+
+    pub fn insert_instruction(&mut self, target: u128, time: u128, kind: InstructionKind) {
+        let before = self.instructions.get_type(target, time, kind);
+        self.edit(Edit::Instruction { target, time, before, after: Some(kind) });
+    }
+
+    pub fn remove_instruction(&mut self, target: u128, time: u128, kind: InstructionKind) {
+        if let Some(before) = self.instructions.get_type(target, time, kind) {
+            self.edit(Edit::Instruction { target, time, before: Some(before), after: None });
+        }
+    }
+
+    /// Sets a breakpoint on the automation lane of a parameter, clamping its value to the range
+    /// of the parameter
+    pub fn set_breakpoint(&mut self, target: u128, name: &'static str, time: u128, mut point: Breakpoint) -> Result<(), &'static str> {
+        let inst = self.instruments.get(target as usize).ok_or("No such instrument")?;
+        let desc = param::find(inst.params(), name).ok_or("The instrument has no such parameter")?;
+        point.value = desc.clamp(point.value);
+        let before = self.automation.lane(target, name).and_then(|lane| lane.get(time));
+        self.edit(Edit::Breakpoint { target, name, time, before, after: Some(point) });
+        Ok(())
+    }
+
+    pub fn remove_breakpoint(&mut self, target: u128, name: &'static str, time: u128) {
+        if let Some(before) = self.automation.lane(target, name).and_then(|lane| lane.get(time)) {
+            self.edit(Edit::Breakpoint { target, name, time, before: Some(before), after: None });
+        }
+    }
+
+    /// Changes the tempo from `time` on. The curve decides how it gets to the next change
+    pub fn set_tempo(&mut self, time: u128, change: Breakpoint) -> Result<(), &'static str> {
+        if !(MIN_BPM..=MAX_BPM).contains(&change.value) {
+            return Err("Tempo must be from 1 to 999 BPM");
+        }
+        let before = self.tempo.changes().get(time);
+        self.edit(Edit::Tempo { time, before, after: Some(change) });
+        Ok(())
+    }
+
+    pub fn remove_tempo(&mut self, time: u128) {
+        if let Some(before) = self.tempo.changes().get(time) {
+            self.edit(Edit::Tempo { time, before: Some(before), after: None });
+        }
+    }
+
+    /// Changes the tempo before the first tempo change, clamped to the tempos there can be
+    pub fn set_initial_bpm(&mut self, bpm: f32) {
+        self.set_timing(bpm.clamp(MIN_BPM, MAX_BPM), self.tempo.signature());
+    }
+
+    pub fn set_signature(&mut self, clicks: u32, unit: u32) -> Result<(), &'static str> {
+        check_signature(clicks, unit)?;
+        self.set_timing(self.tempo.initial_bpm(), (clicks, unit));
+        Ok(())
+    }
+
+    fn set_timing(&mut self, bpm: f32, signature: (u32, u32)) {
+        let before = (self.tempo.initial_bpm(), self.tempo.signature());
+        if before != (bpm, signature) {
+            self.edit(Edit::Timing { before, after: (bpm, signature) });
+        }
+    }
+
+    /// Gives `target` a groove of its own, or with `None` leaves it to the song's
+    pub fn set_groove(&mut self, target: u128, groove: Option<Groove>) {
+        let before = self.grooves.target(target).cloned();
+        if before != groove {
+            self.edit(Edit::Groove { target: Some(target), before, after: groove });
+        }
+    }
+
+    pub fn set_song_groove(&mut self, groove: Option<Groove>) {
+        let before = self.grooves.song.clone();
+        if before != groove {
+            self.edit(Edit::Groove { target: None, before, after: groove });
+        }
+    }
+
+    /// Replaces the envelope of a parameter, or removes it with `None`
+    pub fn set_envelope(&mut self, target: u128, name: &'static str, envelope: Option<Envelope>) -> Result<(), &'static str> {
+        let inst = self.instruments.get(target as usize).ok_or("No such instrument")?;
+        param::find(inst.params(), name).ok_or("The instrument has no such parameter")?;
+        let before = self.envelopes.get(target, name).cloned();
+        if before.is_none() && envelope.is_none() {
+            return Ok(());
+        }
+        self.edit(Edit::Envelope { target, name, before, after: envelope });
+        Ok(())
+    }
+
+    /// Changes the current settings of an instrument directly, without adding to the song
+    pub fn apply_settings(&mut self, target: u128, kind: InstructionKind) -> Result<(), &'static str> {
+        if kind.is_trigger() {
+            return Err("Notes can't be played as a setting");
+        }
+        let inst = self.instruments.get_mut(target as usize).ok_or("No such instrument")?;
+        let before = inst.settings();
+        inst.apply_instruction(kind)?;
+        self.history.record(Edit::Settings { target, after: before });
+        Ok(())
+    }
+
+    pub fn add_instrument(&mut self, mut instrument: Box<dyn Instrument>) {
+        instrument.set_sample_rate(self.sample_rate);
+        let index = self.instruments.len();
+        self.edit(Edit::InsertInstrument {
+            index,
+            instrument,
+            instructions: vec![],
+            lanes: Default::default(),
+            envelopes: Default::default(),
+            groove: None,
+        });
+    }
+
+    pub fn remove_instrument(&mut self, index: usize) {
+        if index < self.instruments.len() {
+            self.edit(Edit::RemoveInstrument { index });
+        }
+    }
+
+    /// Groups every edit until the matching [`App::end_group`] into one undo step
+    pub fn begin_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_group(&mut self) {
+        self.history.end_group();
+    }
+
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop_undo() {
+            Some(edit) => {
+                let inverse = edit.apply(self);
+                self.history.push_redo(inverse);
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.history.pop_redo() {
+            Some(edit) => {
+                let inverse = edit.apply(self);
+                self.history.push_undo(inverse);
+                true
+            }
+            None => false
+        }
+    }
+
     pub fn tick_all(&mut self) -> (f32, f32) {
-        // Temporary pausing
-        if !self.playing {
+        self.tick_mix(None)
+    }
+
+    /// Like [`App::tick_all`], also giving what each instrument played into `stems`, straight
+    /// out of it or with `post_mixer` at the level it goes into the mix. Empty while silent
+    pub fn tick_stems(&mut self, stems: &mut Vec<(f32, f32)>, post_mixer: bool) -> (f32, f32) {
+        stems.clear();
+        self.tick_mix(Some((stems, post_mixer)))
+    }
+
+    fn tick_mix(&mut self, mut stems: Option<(&mut Vec<(f32, f32)>, bool)>) -> (f32, f32) {
+        // Temporary pausing. While jamming the instruments keep sounding, but the song stands still
+        if !self.playing && !self.jamming {
             return (0.0, 0.0);
         }
+        if let Some(elapsed) = self.counting.filter(|_| self.playing) {
+            let click = self.count_in_tick(elapsed);
+            return (click, click);
+        }
 
         // Instruction handling
         // TODO: What if illegal instruction?
         // TODO: maybe give instruments a unique UUID??
+        let step = match self.sample_rate > 0.0 {
+            true => 1.0 / self.sample_rate as f64,
+            false => 0.0,
+        };
+        // Synced envelopes move at the tempo of the moment, so they follow tempo changes too
+        let beat_step = step * self.tempo.bpm_at(self.tick) as f64 / 60.0;
         for i in 0..self.instruments.len() {
-            for instruction in self.instructions.get(i as u128, self.tick) {
-                self.instruments.get_mut(i).unwrap().apply_instruction(instruction);
+            // Paused while jamming, only auditioned notes play, with their envelopes
+            if self.playing {
+                for instruction in self.grooved_instructions(i as u128, self.tick) {
+                    if let InstructionKind::State { status, .. } = instruction {
+                        self.envelopes.trigger(i as u128, status == Status::On);
+                    }
+                    self.instruments.get_mut(i).unwrap().apply_instruction(instruction);
+                }
+                // Lanes are evaluated every sample and win over instructions at the same tick
+                for (name, lane) in self.automation.lanes(i as u128) {
+                    if let Some(value) = lane.value_at(self.tick) {
+                        let _ = self.instruments[i].set_param(name, value);
+                    }
+                }
+            }
+            // Envelopes come last, so they can shape parameters the song also sets
+            for (name, envelope) in self.envelopes.target_mut(i as u128) {
+                if let Some(value) = envelope.tick(if envelope.synced { beat_step } else { step }) {
+                    let _ = self.instruments[i].set_param(name, value);
+                }
             }
         }
-        self.tick = self.tick.checked_add(1).unwrap_or(u128::MAX);
+        let click = match self.playing {
+            true => self.advance(),
+            false => 0.0,
+        };
 
         // Audio handling
+        // TODO: find cleaner way to handle amplitude
+        let master = self.master.tick() / 8.0;
         let (mut left, mut right) = (0.0, 0.0);
         for inst in self.instruments.iter_mut() {
             let (l,r) = inst.tick();
             left += l;
             right += r;
+            if let Some((stems, post_mixer)) = stems.as_mut() {
+                let level = if *post_mixer { master } else { 1.0 };
+                stems.push((l * level, r * level));
+            }
         }
-        // TODO: find cleaner way to handle amplitude
-        (left / 8.0, right / 8.0)
+        (left * master + click, right * master + click)
+    }
+
+    /// Moves the playhead on by a tick, returning the metronome's click
+    fn advance(&mut self) -> f32 {
+        // Clicks are followed even when muted, so turning the metronome on needs no catching up
+        self.metronome.follow(&self.tempo, self.tick);
+        let click = self.metronome.tick();
+        let click = match self.metronome.on && (!self.rendering || self.metronome.in_renders) {
+            true => click,
+            false => 0.0,
+        };
+        self.tick = self.tick.checked_add(1).unwrap_or(u128::MAX);
+        // Only reaching the end wraps around, so playback can still go past it after a seek
+        if let Some(looping) = self.looping {
+            let (start, end) = self.loop_bounds(looping, self.tick - 1);
+            if self.tick == end {
+                self.wrap(start);
+            }
+        }
+        click
+    }
+
+    /// Bounds of the pass of `looping` that `tick` is in, only worked out again once playback
+    /// leaves it or the loop or tempo changes
+    fn loop_bounds(&mut self, looping: Loop, tick: u128) -> (u128, u128) {
+        let version = self.tempo.version();
+        let cached = self.loop_bounds.filter(|(l, v, (start, end))| *l == looping && *v == version && (*start..*end).contains(&tick));
+        if let Some((_, _, bounds)) = cached {
+            return bounds;
+        }
+        let bounds = looping.bounds(&self.tempo, tick);
+        self.loop_bounds = Some((looping, version, bounds));
+        bounds
+    }
+
+    /// Goes back to the start of the loop. Unlike a seek, sounding notes are released rather
+    /// than cut off, so passes don't click
+    fn wrap(&mut self, start: u128) {
+        self.tick = start;
+        self.metronome.reset();
+        for (i, inst) in self.instruments.iter_mut().enumerate() {
+            let _ = inst.apply_instruction(InstructionKind::State { status: Status::Off, velocity: 0 });
+            self.envelopes.trigger(i as u128, false);
+        }
+        self.chase();
+    }
+
+    /// Plays one tick of the count-in, a bar of clicks at the tempo playback starts at
+    fn count_in_tick(&mut self, elapsed: u128) -> f32 {
+        let seconds = 60.0 / self.tempo.bpm_at(self.tick) as f64 * self.tempo.click_beats();
+        let click = ((seconds * self.sample_rate as f64) as u128).max(1);
+        if elapsed.is_multiple_of(click) {
+            self.metronome.trigger(elapsed == 0);
+        }
+        let clicks = self.tempo.signature().0 as u128;
+        self.counting = (elapsed + 1 < click * clicks).then_some(elapsed + 1);
+        self.metronome.tick()
     }
 }
\ No newline at end of file
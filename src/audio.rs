@@ -71,7 +71,10 @@ where
     SampleType: Sample + FromSample<f32>,
 {
     for frame in output.chunks_mut(num_channels) {
-        let (l,r) = app.lock().unwrap().tick_all();
+        let mut app = app.lock().unwrap();
+        // A render plays the song itself between its chunks, so it mustn't be moved on here
+        let (l,r) = if app.rendering { (0.0, 0.0) } else { app.tick_all() };
+        drop(app);
         let left: SampleType = SampleType::from_sample(l);
         let right: SampleType = SampleType::from_sample(r);
 
@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+/// How a lane moves from one breakpoint to the next
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Curve {
+    Linear,
+    /// Constant ratio per tick, which sounds even for frequencies and levels. Falls back to
+    /// linear when the values don't share a sign
+    Exponential,
+    /// Holds the value until the next breakpoint
+    Step,
+}
+
+pub const CURVES: [(&str, Curve); 3] = [
+    ("lin", Curve::Linear),
+    ("exp", Curve::Exponential),
+    ("step", Curve::Step),
+];
+
+impl Curve {
+    /// The curve after this one, for cycling through them
+    pub fn next(&self) -> Curve {
+        let i = CURVES.iter().position(|(_, c)| c == self).unwrap();
+        CURVES[(i + 1) % CURVES.len()].1
+    }
+
+    /// Value between `from` and `to`, `x` going from 0 to 1
+    pub fn interpolate(&self, from: f32, to: f32, x: f64) -> f32 {
+        let (from, to) = (from as f64, to as f64);
+        let value = match self {
+            Curve::Exponential if from * to > 0.0 => from * (to / from).powf(x),
+            Curve::Linear | Curve::Exponential => from + (to - from) * x,
+            Curve::Step => from,
+        };
+        value as f32
+    }
+}
+
+impl Display for Curve {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = CURVES.iter().find(|(_, c)| c == self).unwrap().0;
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Breakpoint {
+    pub value: f32,
+    /// How the lane gets from this breakpoint to the next one
+    pub curve: Curve,
+}
+
+/// Breakpoints of one parameter, by tick
+#[derive(Clone, Default, Debug)]
+pub struct Lane {
+    points: BTreeMap<u128, Breakpoint>,
+}
+
+impl Lane {
+    pub fn insert(&mut self, time: u128, point: Breakpoint) -> Option<Breakpoint> {
+        self.points.insert(time, point)
+    }
+
+    pub fn remove(&mut self, time: u128) -> Option<Breakpoint> {
+        self.points.remove(&time)
+    }
+
+    pub fn get(&self, time: u128) -> Option<Breakpoint> {
+        self.points.get(&time).copied()
+    }
+
+    pub fn points(&self) -> impl Iterator<Item = (u128, Breakpoint)> + '_ {
+        self.points.iter().map(|(time, point)| (*time, *point))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// The value of the lane at `tick`. `None` before the first breakpoint, where the lane
+    /// leaves the parameter alone, and the last value is held after the last breakpoint
+    pub fn value_at(&self, tick: u128) -> Option<f32> {
+        let (start, from) = self.points.range(..=tick).next_back()?;
+        let Some((end, to)) = self.points.range(tick + 1..).next() else {
+            return Some(from.value);
+        };
+        let x = (tick - start) as f64 / (end - start) as f64;
+        Some(from.curve.interpolate(from.value, to.value, x))
+    }
+}
+
+/// Automation lanes of every instrument, by instrument index and parameter name
+pub struct Automation {
+    lanes: HashMap<u128, BTreeMap<&'static str, Lane>>,
+}
+
+impl Automation {
+    pub fn new() -> Self {
+        Self { lanes: HashMap::new() }
+    }
+
+    pub fn lane(&self, target: u128, name: &str) -> Option<&Lane> {
+        self.lanes.get(&target)?.get(name)
+    }
+
+    /// Every lane of `target`, by parameter name
+    pub fn lanes(&self, target: u128) -> impl Iterator<Item = (&'static str, &Lane)> {
+        self.lanes.get(&target).into_iter().flat_map(|lanes| lanes.iter().map(|(name, lane)| (*name, lane)))
+    }
+
+    /// Sets a breakpoint, returning the one it replaced
+    pub fn insert(&mut self, target: u128, name: &'static str, time: u128, point: Breakpoint) -> Option<Breakpoint> {
+        self.lanes.entry(target).or_default().entry(name).or_default().insert(time, point)
+    }
+
+    pub fn remove(&mut self, target: u128, name: &str, time: u128) -> Option<Breakpoint> {
+        let lanes = self.lanes.get_mut(&target)?;
+        let lane = lanes.get_mut(name)?;
+        let removed = lane.remove(time);
+        if lane.is_empty() {
+            lanes.remove(name);
+        }
+        if lanes.is_empty() {
+            self.lanes.remove(&target);
+        }
+        removed
+    }
+
+    /// Removes and returns every lane of `target`
+    pub fn take_target(&mut self, target: u128) -> BTreeMap<&'static str, Lane> {
+        self.lanes.remove(&target).unwrap_or_default()
+    }
+
+    pub fn restore_target(&mut self, target: u128, lanes: BTreeMap<&'static str, Lane>) {
+        if !lanes.is_empty() {
+            self.lanes.insert(target, lanes);
+        }
+    }
+
+    /// Moves the lanes of every target from `from` and up one step up or down, like
+    /// [`crate::instruction_handler::InstructionHandler::shift_targets`]
+    pub fn shift_targets(&mut self, from: u128, up: bool) {
+        let shifted = self.lanes.keys().copied().filter(|t| *t >= from).collect::<Vec<_>>();
+        // Everything is taken out before putting it back, so nothing gets overwritten
+        let moved = shifted.into_iter().map(|t| (t, self.lanes.remove(&t).unwrap())).collect::<Vec<_>>();
+        for (target, lanes) in moved {
+            self.lanes.insert(if up { target + 1 } else { target - 1 }, lanes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::automation::{Breakpoint, Curve, Lane};
+
+    #[test]
+    fn interpolates_between_breakpoints() {
+        let mut lane = Lane::default();
+        assert_eq!(lane.value_at(0), None);
+        lane.insert(10, Breakpoint { value: 1.0, curve: Curve::Linear });
+        lane.insert(20, Breakpoint { value: 3.0, curve: Curve::Exponential });
+        lane.insert(30, Breakpoint { value: 12.0, curve: Curve::Step });
+        lane.insert(40, Breakpoint { value: 0.0, curve: Curve::Linear });
+
+        assert_eq!(lane.value_at(9), None);
+        assert_eq!(lane.value_at(10), Some(1.0));
+        assert_eq!(lane.value_at(15), Some(2.0));
+        assert_eq!(lane.value_at(25), Some(6.0));
+        assert_eq!(lane.value_at(39), Some(12.0));
+        assert_eq!(lane.value_at(40), Some(0.0));
+        assert_eq!(lane.value_at(1000), Some(0.0));
+    }
+
+    #[test]
+    fn lanes_drive_parameters_while_playing() {
+        let mut app = App::new();
+        app.set_breakpoint(0, "volume", 0, Breakpoint { value: 0.0, curve: Curve::Linear }).unwrap();
+        app.set_breakpoint(0, "volume", 100, Breakpoint { value: 1.0, curve: Curve::Linear }).unwrap();
+        assert!(app.set_breakpoint(1, "volume", 0, Breakpoint { value: 0.0, curve: Curve::Linear }).is_err());
+
+        app.play();
+        for _ in 0..=50 {
+            app.tick_all();
+        }
+        assert_eq!(app.instruments[0].get_param("volume"), Some(0.5));
+
+        app.seek(10);
+        assert_eq!(app.instruments[0].get_param("volume"), Some(0.1));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::automation::Curve;
+use crate::command::args::{Args, ParseError};
+use crate::instruction::{InstructionKind, Status};
+use crate::render::Tap;
+
+pub mod args;
+pub mod complete;
+pub mod registry;
+
+/// Everything that can be typed into the command bar
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    Quit,
+    Clear,
+    Play,
+    Pause,
+    Reset,
+    /// Seconds, or the target tick when left out
+    Seek(Option<f32>),
+    /// Sets the target tick, in seconds
+    Time(f32),
+    Inst(u128),
+    InstAdd(String),
+    InstDel,
+    Undo,
+    Redo,
+    /// Start and end, in seconds
+    Yank(f32, f32),
+    Paste,
+    Remove(InstructionKind),
+    Set(InstructionKind),
+    Instruction(InstructionKind),
+    /// Opens help, on a topic or an overview
+    Help(Option<String>),
+    /// Lists the parameters of the target instrument
+    Params,
+    /// Adds a breakpoint to the automation lane of a parameter at the target tick
+    Auto { name: &'static str, value: f32, curve: Curve },
+    AutoDel(&'static str),
+    /// Opens the lane view for a parameter
+    Lane(&'static str),
+    /// Opens the pattern view of the target instrument
+    Pattern,
+    /// Adds a point to the envelope of a parameter, at a time since the note started
+    Env { name: &'static str, time: f32, value: f32, curve: Curve },
+    /// Removes a point of an envelope by index, or the whole envelope
+    EnvDel(&'static str, Option<u128>),
+    /// Sets or clears the sustain point of an envelope
+    EnvSustain(&'static str, Option<u128>),
+    /// Sets the loop of an envelope from a point to another or the last one, or clears it
+    EnvLoop(&'static str, Option<(u128, Option<u128>)>),
+    EnvSync(&'static str, Status),
+    EnvShow(&'static str),
+    /// Sets the level of the whole mix, or shows it
+    Master(Option<f32>),
+    /// Sets the tempo the song starts at, or shows it
+    Bpm(Option<u128>),
+    /// Changes the tempo at the target time, with the curve leading to the next change
+    Tempo { bpm: f32, curve: Curve },
+    TempoDel,
+    /// Lists the tempo changes
+    Tempos,
+    /// Sets the target time to a beat, counting from 0 and following the tempo map
+    Beat(f32),
+    /// Sets the groove of the whole song, or shows it
+    Groove(Option<String>),
+    /// Sets the groove of the target instrument's pattern, which wins over the song's
+    GrooveInst(String),
+    GrooveInstDel,
+    /// Swings the whole song by a percentage
+    Swing(f32),
+    /// Lists the grooves there are
+    Grooves,
+    ProjectLoad(String),
+    ProjectSave(String),
+    /// Replaces the song with one from a text file
+    SongLoad(String),
+    /// Writes the song to a text file, a line per row
+    SongSave(String),
+    /// Whether the song and project files loaded or saved last are read again when they change
+    Watch(Status),
+    /// Adds a Standard MIDI File to the song
+    ImportMidi(String),
+    /// Writes the song to a Standard MIDI File
+    ExportMidi(String),
+    /// Adds a ProTracker or FastTracker 2 module to the song
+    ImportModule(String),
+    /// Adds a file of Music Macro Language to the song
+    ImportMml(String),
+    /// Adds a file to the song with the importer its extension calls for
+    Import(String),
+    /// Loops playback between two times, in seconds
+    Loop(f32, f32),
+    /// Loops playback between two beats, following the tempo map
+    LoopBeats(f32, f32),
+    /// Moves the start or end of the loop to the target time
+    LoopStart,
+    LoopEnd,
+    /// Loops whichever pattern is playing, patterns being a number of rows long
+    LoopPattern(Option<u128>),
+    LoopOff,
+    /// Records notes played on the keyboard into the target instrument
+    Record,
+    /// Plays notes on the keyboard on the target instrument without recording them
+    Jam,
+    /// Whether recorded notes snap to the start of the row they're played in
+    RecordQuantize(Status),
+    RecordVelocity(u8),
+    Metronome(Status),
+    MetronomeVolume(f32),
+    /// Renders the song to a WAV file, and with stems each instrument to one of its own
+    Render { path: String, stems: Option<Tap> },
+    /// Whether renders include the metronome
+    MetronomeRender(Status),
+    /// Sets the time signature, as clicks in a bar and the note value of a click
+    Signature(u128, u128),
+    CountIn(Status),
+}
+
+impl Command {
+    /// Whether the command leaves the song, its instruments and playback alone, so it can run
+    /// while a render plays the song
+    pub fn is_passive(&self) -> bool {
+        matches!(
+            self,
+            Command::Quit | Command::Clear | Command::Time(_) | Command::Inst(_) | Command::Yank(..)
+                | Command::Help(_) | Command::Params | Command::EnvShow(_) | Command::Master(None) | Command::Bpm(None)
+                | Command::Tempos | Command::Beat(_) | Command::Grooves | Command::ProjectSave(_) | Command::SongSave(_)
+                | Command::ExportMidi(_) | Command::Watch(_)
+        )
+    }
+
+    /// Parses a command, or an instruction to add to the song. See [`registry::COMMANDS`]
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let mut args = Args::new(s);
+        let words = args.remaining().iter().map(|t| t.text).collect::<Vec<_>>();
+        let command = match registry::find(&registry::COMMANDS, &words) {
+            Some(_) => registry::parse_spec(&registry::COMMANDS, &mut args, "command")?,
+            None => Command::Instruction(registry::parse_spec(&registry::INSTRUCTIONS, &mut args, "command or instruction")?),
+        };
+        args.finish()?;
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::automation::Curve;
+    use crate::command::args::Span;
+    use crate::command::Command;
+    use crate::instruction::InstructionKind;
+    use crate::render::Tap;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse("q"), Ok(Command::Quit));
+        assert_eq!(Command::parse("seek"), Ok(Command::Seek(None)));
+        assert_eq!(Command::parse("seek 500ms"), Ok(Command::Seek(Some(0.5))));
+        // Only what leaves the song and playhead alone runs during a render
+        assert!(!Command::parse("seek").unwrap().is_passive());
+        assert!(Command::parse("bpm").unwrap().is_passive());
+        assert!(!Command::parse("bpm 90").unwrap().is_passive());
+        assert_eq!(Command::parse("inst add synth"), Ok(Command::InstAdd(String::from("synth"))));
+        assert_eq!(Command::parse("inst 2"), Ok(Command::Inst(2)));
+        assert_eq!(Command::parse("set note A-4"), Ok(Command::Set(InstructionKind::Note(60))));
+        assert_eq!(Command::parse("note 60"), Ok(Command::Instruction(InstructionKind::Note(60))));
+        assert_eq!(Command::parse("auto freq 1000 exp"), Ok(Command::Auto { name: "freq", value: 1000.0, curve: Curve::Exponential }));
+        assert_eq!(Command::parse("auto del freq"), Ok(Command::AutoDel("freq")));
+        assert_eq!(Command::parse("env loop pitch 1"), Ok(Command::EnvLoop("pitch", Some((1, None)))));
+        assert_eq!(Command::parse("env del pitch"), Ok(Command::EnvDel("pitch", None)));
+        assert_eq!(Command::parse("render --stems pre mix.wav"), Ok(Command::Render { path: String::from("mix.wav"), stems: Some(Tap::Pre) }));
+    }
+
+    #[test]
+    fn errors_point_at_bad_token() {
+        assert_eq!(Command::parse("play now").unwrap_err().span, Span { start: 5, end: 8 });
+        assert_eq!(Command::parse("inst x").unwrap_err().span, Span { start: 5, end: 6 });
+        assert_eq!(Command::parse("yank 1").unwrap_err().span, Span { start: 6, end: 7 });
+    }
+}
@@ -0,0 +1,295 @@
+use crate::instruction::{Status, MIDI_OFFSET};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Byte range of a token in the parsed input
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Splits the input on whitespace, keeping track of where every token came from
+pub fn tokenize(src: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, c) in src.char_indices().chain(std::iter::once((src.len(), ' '))) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                tokens.push(Token { text: &src[s..i], span: Span { start: s, end: i } });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+const NOTE_NAMES: [&str; 12] = ["C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-"];
+
+/// Parses a note name like `A-4`, `c4`, `F#3` or `Bb2` into a note number, `A-4` being 60
+pub fn parse_note_name(s: &str) -> Option<u16> {
+    let mut chars = s.chars();
+    let semitone: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next()? {
+        '#' => (1, &rest[1..]),
+        'b' => (-1, &rest[1..]),
+        '-' => (0, &rest[1..]),
+        _ => (0, rest),
+    };
+    if octave.is_empty() || !octave.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let octave = octave.parse::<i32>().ok()?;
+    u16::try_from((octave + 1) * 12 + semitone + accidental - MIDI_OFFSET as i32).ok()
+}
+
+/// Formats a note number tracker style, like `A-4` for 60 or `F#3`.
+/// Notes outside of octaves 0 to 9 are formatted as plain numbers
+pub fn note_name(note: u16) -> String {
+    let key = note as u32 + MIDI_OFFSET as u32;
+    if !(12..132).contains(&key) {
+        return note.to_string();
+    }
+    format!("{}{}", NOTE_NAMES[key as usize % 12], key / 12 - 1)
+}
+
+/// Splits a token like `200ms` into its number and its unit
+fn split_unit(s: &str) -> (&str, &str) {
+    let i = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(s.len());
+    s.split_at(i)
+}
+
+/// Cursor over the tokens of one command
+pub struct Args<'a> {
+    src: &'a str,
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            tokens: tokenize(src),
+            pos: 0,
+        }
+    }
+
+    pub fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    pub fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// The tokens not taken yet
+    pub fn remaining(&self) -> &[Token<'a>] {
+        &self.tokens[self.pos.min(self.tokens.len())..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    /// Error for when the input ends before a required argument
+    pub fn missing(&self, what: &str) -> ParseError {
+        let end = self.src.trim_end().len();
+        ParseError::new(format!("Missing {}", what), Span { start: end, end: end + 1 })
+    }
+
+    /// Takes the next token, whatever it is
+    pub fn word(&mut self, what: &str) -> Result<Token<'a>, ParseError> {
+        self.next().ok_or_else(|| self.missing(what))
+    }
+
+    /// Takes the rest of the input verbatim, such as a file path with spaces in it
+    pub fn rest(&mut self, what: &str) -> Result<&'a str, ParseError> {
+        let first = self.word(what)?;
+        self.pos = self.tokens.len();
+        Ok(self.src[first.span.start..].trim_end())
+    }
+
+    /// Takes one of the given words, returning the value paired with it
+    pub fn keyword<T: Copy>(&mut self, what: &str, options: &[(&str, T)]) -> Result<T, ParseError> {
+        let token = self.word(what)?;
+        options
+            .iter()
+            .find(|(name, _)| *name == token.text)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                let names = options.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+                ParseError::new(
+                    format!("Expected {} ({}), found '{}'", what, names.join(", "), token.text),
+                    token.span,
+                )
+            })
+    }
+
+    pub fn status(&mut self, what: &str) -> Result<Status, ParseError> {
+        self.keyword(what, &[("on", Status::On), ("off", Status::Off)])
+    }
+
+    /// Takes a plain number without a unit. `nan` and `inf` aren't numbers here
+    pub fn number<T: FromStr>(&mut self, what: &str) -> Result<T, ParseError> {
+        let token = self.word(what)?;
+        let infinite = token.text.parse::<f32>().is_ok_and(|v| !v.is_finite());
+        token.text.parse::<T>().ok().filter(|_| !infinite).ok_or_else(|| {
+            ParseError::new(format!("Expected {} as a number, found '{}'", what, token.text), token.span)
+        })
+    }
+
+    /// Takes a number with an optional unit, scaled by the factor of that unit
+    fn with_unit(&mut self, what: &str, units: &[(&str, f64)]) -> Result<f32, ParseError> {
+        let token = self.word(what)?;
+        let (number, unit) = split_unit(token.text);
+        // Scaled in f64, so that `10ms` comes out as exactly `0.01`
+        let Ok(value) = number.parse::<f64>() else {
+            return Err(ParseError::new(format!("Expected {}, found '{}'", what, token.text), token.span));
+        };
+        match units.iter().find(|(name, _)| name.eq_ignore_ascii_case(unit)) {
+            Some((_, factor)) if !((value * factor) as f32).is_finite() => {
+                Err(ParseError::new(format!("{} is too large, found '{}'", what, token.text), token.span))
+            }
+            Some((_, factor)) => Ok((value * factor) as f32),
+            None => {
+                let unit_span = Span { start: token.span.start + number.len(), end: token.span.end };
+                let names = units.iter().filter(|(name, _)| !name.is_empty()).map(|(name, _)| *name).collect::<Vec<_>>();
+                Err(ParseError::new(format!("Unknown unit '{}' for {}, expected {}", unit, what, names.join(" or ")), unit_span))
+            }
+        }
+    }
+
+    /// Takes a duration in seconds, written as `1.5`, `1.5s` or `200ms`
+    pub fn time(&mut self, what: &str) -> Result<f32, ParseError> {
+        let value = self.with_unit(what, &[("", 1.0), ("s", 1.0), ("ms", 0.001)])?;
+        self.non_negative(what, value)
+    }
+
+    /// Takes a frequency in Hz, written as `440`, `440Hz`, `1.2kHz` or as a note name like `A-4`
+    pub fn frequency(&mut self, what: &str) -> Result<f32, ParseError> {
+        if let Some(note) = self.peek().and_then(|t| parse_note_name(t.text)) {
+            self.next();
+            return Ok(crate::instruction::note_to_frequency(note));
+        }
+        let value = self.with_unit(what, &[("", 1.0), ("hz", 1.0), ("khz", 1000.0)])?;
+        self.non_negative(what, value)
+    }
+
+    /// Takes a MIDI note, written as a number or a note name like `C#4`
+    pub fn note(&mut self, what: &str) -> Result<u16, ParseError> {
+        let token = self.word(what)?;
+        parse_note_name(token.text)
+            .or_else(|| token.text.parse::<u16>().ok())
+            .ok_or_else(|| ParseError::new(format!("Expected {} as a note name or number, found '{}'", what, token.text), token.span))
+    }
+
+    fn non_negative(&self, what: &str, value: f32) -> Result<f32, ParseError> {
+        if value >= 0.0 {
+            return Ok(value);
+        }
+        let span = self.tokens[self.pos - 1].span;
+        Err(ParseError::new(format!("{} can't be negative", what), span))
+    }
+
+    /// Fails if there are tokens left over
+    pub fn finish(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(token) => Err(ParseError::new(format!("Unexpected argument '{}'", token.text), token.span)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::args::{note_name, parse_note_name, tokenize, Args, Span};
+
+    #[test]
+    fn tokens_have_spans() {
+        let tokens = tokenize("  vib  opt 6 ");
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[1].text, "opt");
+        assert_eq!(tokens[1].span, Span { start: 7, end: 10 });
+    }
+
+    #[test]
+    fn note_names() {
+        assert_eq!(parse_note_name("A-4"), Some(60));
+        assert_eq!(parse_note_name("a4"), Some(60));
+        assert_eq!(parse_note_name("C#4"), Some(52));
+        assert_eq!(parse_note_name("Db4"), Some(52));
+        assert_eq!(parse_note_name("C-0"), Some(3));
+        assert_eq!(parse_note_name("H4"), None);
+        assert_eq!(parse_note_name("C"), None);
+        assert_eq!(note_name(60), "A-4");
+        for n in 3..123 {
+            assert_eq!(parse_note_name(&note_name(n)), Some(n));
+        }
+    }
+
+    #[test]
+    fn units() {
+        let mut args = Args::new("200ms 1.5s 2 1kHz A-4 3parsecs");
+        assert_eq!(args.time("time").unwrap(), 0.2);
+        assert_eq!(args.time("time").unwrap(), 1.5);
+        assert_eq!(args.time("time").unwrap(), 2.0);
+        assert_eq!(args.frequency("freq").unwrap(), 1000.0);
+        assert_eq!(args.frequency("freq").unwrap(), 440.0);
+        let err = args.time("time").unwrap_err();
+        assert_eq!(err.span, Span { start: 23, end: 30 });
+
+        let mut args = Args::new("nan inf 1e40");
+        assert!(args.number::<f32>("level").is_err());
+        assert!(args.number::<f32>("level").is_err());
+        assert!(args.number::<f32>("level").is_err());
+        assert!(Args::new("9".repeat(40).as_str()).time("time").is_err());
+    }
+}
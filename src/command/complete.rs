@@ -0,0 +1,134 @@
+use crate::command::args::{tokenize, Span};
+use crate::command::registry::{ArgKind, Spec, COMMANDS, INSTRUCTIONS};
+use std::path::Path;
+
+/// Finds what the word ending at `cursor` could be completed to.
+///
+/// Returns the span of the word being completed, and the candidates for replacing it
+pub fn complete(input: &str, cursor: usize) -> (Span, Vec<String>) {
+    let before = &input[..cursor];
+    let tokens = tokenize(before);
+    // The cursor is either at the end of the last token, or starts a new one after whitespace
+    let (span, previous) = match tokens.last() {
+        Some(last) if last.span.end == cursor => (last.span, &tokens[..tokens.len() - 1]),
+        _ => (Span { start: cursor, end: cursor }, &tokens[..]),
+    };
+    let prefix = &input[span.start..cursor];
+    let previous = previous.iter().map(|t| t.text).collect::<Vec<_>>();
+
+    let words = candidates(&COMMANDS, &previous, prefix)
+        .into_iter()
+        .chain(candidates(&INSTRUCTIONS, &previous, prefix));
+    let mut candidates = words
+        .filter(|w| w.starts_with(prefix))
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates.dedup();
+    (span, candidates)
+}
+
+/// Words that could follow `previous` according to the specs, either the rest of a spec name or
+/// a value for the next argument
+fn candidates<T>(specs: &[Spec<T>], previous: &[&str], prefix: &str) -> Vec<String> {
+    let mut words = vec![];
+    for spec in specs {
+        for name in std::iter::once(spec.name).chain(spec.aliases.iter().copied()) {
+            let name_words = name.split(' ').collect::<Vec<_>>();
+            let shared = previous.len().min(name_words.len());
+            if previous[..shared] != name_words[..shared] {
+                continue;
+            }
+            if previous.len() < name_words.len() {
+                words.push(name_words[previous.len()].to_string());
+                continue;
+            }
+            let given = &previous[name_words.len()..];
+            for (i, arg) in spec.args.iter().enumerate() {
+                match arg.kind {
+                    // Nested instructions take the rest of the line
+                    ArgKind::Instruction if i <= given.len() => {
+                        words.extend(candidates(&INSTRUCTIONS, &given[i..], prefix));
+                        break;
+                    }
+                    ArgKind::Path if i == given.len() => words.extend(complete_path(prefix)),
+                    kind if i == given.len() => words.extend(kind.words().into_iter().map(String::from)),
+                    _ => {}
+                }
+            }
+        }
+    }
+    words
+}
+
+/// Lists the files and directories starting with `prefix`. Directories end with a `/`
+fn complete_path(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+        None => ("", prefix),
+    };
+    let Ok(entries) = std::fs::read_dir(if dir.is_empty() { Path::new(".") } else { Path::new(dir) }) else {
+        return vec![];
+    };
+    let mut candidates = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            // Hidden files are only listed when asked for
+            if !file_name.starts_with(name) || (file_name.starts_with('.') && !name.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, file_name, slash))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates
+}
+
+/// The longest prefix shared by every candidate
+pub fn common_prefix(candidates: &[String]) -> &str {
+    let Some(first) = candidates.first() else {
+        return "";
+    };
+    let mut len = first.len();
+    for other in candidates[1..].iter() {
+        len = first
+            .char_indices()
+            .zip(other.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(len);
+    }
+    &first[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::args::Span;
+    use crate::command::complete::{common_prefix, complete};
+
+    #[test]
+    fn completes_by_context() {
+        assert_eq!(complete("pau", 3), (Span { start: 0, end: 3 }, vec![String::from("pause")]));
+        assert_eq!(complete("pa", 2).1, vec!["param", "params", "paste", "pattern", "pause"]);
+        assert_eq!(complete("form s", 6).1, vec!["saw", "sine", "square"]);
+        assert_eq!(complete("set form t", 10).1, vec!["tri"]);
+        assert_eq!(complete("inst add ", 9), (Span { start: 9, end: 9 }, vec![String::from("osc"), String::from("synth")]));
+        assert_eq!(complete("note ", 5).1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn completes_in_the_middle() {
+        assert_eq!(complete("vib o 1 2", 5).1, vec!["off", "on", "opt"]);
+    }
+
+    #[test]
+    fn common_prefixes() {
+        let candidates = vec![String::from("square"), String::from("saw"), String::from("sine")];
+        assert_eq!(common_prefix(&candidates), "s");
+        assert_eq!(common_prefix(&candidates[..1]), "square");
+        assert_eq!(common_prefix(&[]), "");
+    }
+}
@@ -0,0 +1,938 @@
+use crate::automation::{Curve, CURVES};
+use crate::command::args::{Args, ParseError};
+use crate::command::Command;
+use crate::groove;
+use crate::instruction::{InstructionKind, Status, MAX_VELOCITY};
+use crate::instrument;
+use crate::instrument::adsr::SHAPES;
+use crate::instrument::oscillator::{Waveform, WAVEFORMS};
+use crate::render::Tap;
+
+/// What kind of value an argument takes. Drives parsing, completion and help alike
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ArgKind {
+    Time,
+    Frequency,
+    Note,
+    Number,
+    Index,
+    Status,
+    Waveform,
+    Instrument,
+    Path,
+    Topic,
+    Instruction,
+    Param,
+    Curve,
+    Shape,
+    Velocity,
+    Groove,
+}
+
+pub const ARG_KINDS: [ArgKind; 16] = [
+    ArgKind::Time,
+    ArgKind::Frequency,
+    ArgKind::Note,
+    ArgKind::Number,
+    ArgKind::Index,
+    ArgKind::Status,
+    ArgKind::Waveform,
+    ArgKind::Instrument,
+    ArgKind::Path,
+    ArgKind::Topic,
+    ArgKind::Instruction,
+    ArgKind::Param,
+    ArgKind::Curve,
+    ArgKind::Shape,
+    ArgKind::Velocity,
+    ArgKind::Groove,
+];
+
+impl ArgKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArgKind::Time => "time",
+            ArgKind::Frequency => "frequency",
+            ArgKind::Note => "note",
+            ArgKind::Number => "number",
+            ArgKind::Index => "index",
+            ArgKind::Status => "state",
+            ArgKind::Waveform => "waveform",
+            ArgKind::Instrument => "instrument",
+            ArgKind::Path => "path",
+            ArgKind::Topic => "topic",
+            ArgKind::Instruction => "instruction",
+            ArgKind::Param => "param",
+            ArgKind::Curve => "curve",
+            ArgKind::Shape => "shape",
+            ArgKind::Velocity => "velocity",
+            ArgKind::Groove => "groove",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ArgKind::Time => String::from("seconds, or with a unit like 1.5s or 200ms"),
+            ArgKind::Frequency => String::from("Hz, or with a unit like 440Hz or 1.2kHz, or a note name like A-4"),
+            ArgKind::Note => String::from("a note name like C-4, C#4 or Db4, or a note number with A-4 at 60"),
+            ArgKind::Number => String::from("a number like 0.5"),
+            ArgKind::Index => String::from("a whole number, counting from 0"),
+            ArgKind::Velocity => format!("how hard a note is pressed, from 0 to {}", MAX_VELOCITY),
+            ArgKind::Groove => format!("a groove of the project, or one of {}", self.words().join(", ")),
+            ArgKind::Shape => String::from("lin, exp to start slow, log to start fast, or a tension from -1 to 1"),
+            ArgKind::Curve => format!("one of {}, for linear, exponential or holding until the next breakpoint", self.words().join(", ")),
+            ArgKind::Waveform | ArgKind::Status | ArgKind::Instrument | ArgKind::Param => {
+                format!("one of {}", self.words().join(", "))
+            }
+            ArgKind::Path => String::from("a file path, which may contain spaces"),
+            ArgKind::Topic => String::from("a command, instruction or argument type"),
+            ArgKind::Instruction => String::from("any instruction, like note C-4"),
+        }
+    }
+
+    /// The fixed set of words the argument accepts, empty if it isn't limited to a set
+    pub fn words(&self) -> Vec<&'static str> {
+        match self {
+            ArgKind::Status => vec!["on", "off"],
+            ArgKind::Waveform => WAVEFORMS.iter().map(|(name, _)| *name).collect(),
+            ArgKind::Instrument => instrument::NAMES.to_vec(),
+            ArgKind::Topic => topics(),
+            ArgKind::Param => instrument::param_names(),
+            ArgKind::Curve => CURVES.iter().map(|(name, _)| *name).collect(),
+            ArgKind::Shape => SHAPES.iter().map(|(name, _)| *name).collect(),
+            ArgKind::Groove => groove::BUILT_IN.iter().map(|(name, _, _)| *name).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// A valid value for the argument, as typed
+    pub fn example(&self) -> &'static str {
+        match self {
+            ArgKind::Time => "200ms",
+            ArgKind::Frequency => "440Hz",
+            ArgKind::Note => "C-4",
+            ArgKind::Number => "0.5",
+            ArgKind::Index => "0",
+            ArgKind::Status => "on",
+            ArgKind::Waveform => "saw",
+            ArgKind::Instrument => "synth",
+            ArgKind::Path => "song.txt",
+            ArgKind::Topic => "vib",
+            ArgKind::Instruction => "note C-4",
+            ArgKind::Param => "attack",
+            ArgKind::Curve => "exp",
+            ArgKind::Shape => "log",
+            ArgKind::Velocity => "100",
+            ArgKind::Groove => "shuffle",
+        }
+    }
+
+    fn parse(&self, args: &mut Args, what: &str) -> Result<Value, ParseError> {
+        Ok(match self {
+            ArgKind::Time => Value::Number(args.time(what)?),
+            ArgKind::Frequency => Value::Number(args.frequency(what)?),
+            ArgKind::Note => Value::Int(args.note(what)? as u128),
+            ArgKind::Number => Value::Number(args.number(what)?),
+            ArgKind::Index => Value::Int(args.number(what)?),
+            ArgKind::Status => Value::Status(args.status(what)?),
+            ArgKind::Waveform => Value::Waveform(args.keyword(what, &WAVEFORMS)?),
+            ArgKind::Instrument => {
+                let names = instrument::NAMES.map(|name| (name, name));
+                Value::Text(args.keyword(what, &names)?.to_string())
+            }
+            ArgKind::Path => Value::Text(args.rest(what)?.to_string()),
+            // Grooves from the project file aren't known until the command runs
+            ArgKind::Topic | ArgKind::Groove => Value::Text(args.word(what)?.text.to_string()),
+            ArgKind::Instruction => Value::Instruction(InstructionKind::parse_args(args)?),
+            ArgKind::Param => {
+                let names = instrument::param_names().into_iter().map(|name| (name, name)).collect::<Vec<_>>();
+                Value::Name(args.keyword(what, &names)?)
+            }
+            ArgKind::Curve => Value::Curve(args.keyword(what, &CURVES)?),
+            ArgKind::Velocity => {
+                let token = args.peek();
+                let velocity = args.number::<u8>(what)?;
+                if velocity > MAX_VELOCITY {
+                    let message = format!("Expected {} from 0 to {}, found '{}'", what, MAX_VELOCITY, velocity);
+                    return Err(ParseError::new(message, token.unwrap().span));
+                }
+                Value::Int(velocity as u128)
+            }
+            ArgKind::Shape => match args.peek().and_then(|t| SHAPES.iter().find(|(name, _)| *name == t.text)) {
+                Some((_, tension)) => {
+                    args.next();
+                    Value::Number(*tension)
+                }
+                None => Value::Number(args.number::<f32>(what)?.clamp(-1.0, 1.0)),
+            },
+        })
+    }
+}
+
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+const fn arg(name: &'static str, kind: ArgKind) -> Arg {
+    Arg { name, kind, optional: false }
+}
+
+const fn optional(name: &'static str, kind: ArgKind) -> Arg {
+    Arg { name, kind, optional: true }
+}
+
+/// A parsed argument. Which variant it is follows from the [`ArgKind`]
+pub enum Value {
+    Number(f32),
+    Int(u128),
+    Status(Status),
+    Waveform(Waveform),
+    Text(String),
+    Name(&'static str),
+    Instruction(InstructionKind),
+    Curve(Curve),
+}
+
+// The registry pairs every argument kind with the right accessor, so a mismatch is a bug here
+impl Value {
+    fn number(&self) -> f32 {
+        match self {
+            Value::Number(f) => *f,
+            _ => unreachable!("argument is not a number"),
+        }
+    }
+
+    fn int(&self) -> u128 {
+        match self {
+            Value::Int(u) => *u,
+            _ => unreachable!("argument is not a whole number"),
+        }
+    }
+
+    fn status(&self) -> Status {
+        match self {
+            Value::Status(s) => *s,
+            _ => unreachable!("argument is not a state"),
+        }
+    }
+
+    fn waveform(&self) -> Waveform {
+        match self {
+            Value::Waveform(w) => *w,
+            _ => unreachable!("argument is not a waveform"),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Value::Text(s) => s.clone(),
+            _ => unreachable!("argument is not text"),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Value::Name(s) => s,
+            _ => unreachable!("argument is not a name"),
+        }
+    }
+
+    fn instruction(&self) -> InstructionKind {
+        match self {
+            Value::Instruction(i) => *i,
+            _ => unreachable!("argument is not an instruction"),
+        }
+    }
+
+    fn curve(&self) -> Curve {
+        match self {
+            Value::Curve(c) => *c,
+            _ => unreachable!("argument is not a curve"),
+        }
+    }
+}
+
+/// Everything there is to know about one command or instruction
+pub struct Spec<T: 'static> {
+    /// One or more words, like `vib opt`
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub args: &'static [Arg],
+    pub summary: &'static str,
+    build: fn(&[Value]) -> T,
+}
+
+impl<T> Spec<T> {
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.to_string();
+        for arg in self.args {
+            match arg.optional {
+                true => usage += &format!(" [{}]", arg.name),
+                false => usage += &format!(" <{}>", arg.name),
+            }
+        }
+        usage
+    }
+
+    fn names(&self) -> impl Iterator<Item = &'static str> {
+        std::iter::once(self.name).chain(self.aliases.iter().copied())
+    }
+}
+
+pub static COMMANDS: [Spec<Command>; 67] = [
+    Spec { name: "quit", aliases: &["q"], args: &[], summary: "Quits fmangroove", build: |_| Command::Quit },
+    Spec { name: "clear", aliases: &["cls"], args: &[], summary: "Redraws the whole screen", build: |_| Command::Clear },
+    Spec { name: "play", aliases: &[], args: &[], summary: "Starts playback", build: |_| Command::Play },
+    Spec { name: "pause", aliases: &["stop"], args: &[], summary: "Pauses playback", build: |_| Command::Pause },
+    Spec { name: "reset", aliases: &[], args: &[], summary: "Moves playback back to the start", build: |_| Command::Reset },
+    Spec {
+        name: "seek",
+        aliases: &[],
+        args: &[optional("time", ArgKind::Time)],
+        summary: "Moves playback to a time, or to the target time, as if it had played from the start",
+        build: |v| Command::Seek(v.first().map(Value::number)),
+    },
+    Spec {
+        name: "time",
+        aliases: &[],
+        args: &[arg("time", ArgKind::Time)],
+        summary: "Sets the target time, where instructions are added",
+        build: |v| Command::Time(v[0].number()),
+    },
+    Spec {
+        name: "inst",
+        aliases: &[],
+        args: &[arg("index", ArgKind::Index)],
+        summary: "Sets the target instrument",
+        build: |v| Command::Inst(v[0].int()),
+    },
+    Spec {
+        name: "inst add",
+        aliases: &[],
+        args: &[arg("instrument", ArgKind::Instrument)],
+        summary: "Adds a new instrument after the last one",
+        build: |v| Command::InstAdd(v[0].text()),
+    },
+    Spec { name: "inst del", aliases: &[], args: &[], summary: "Removes the target instrument", build: |_| Command::InstDel },
+    Spec { name: "undo", aliases: &[], args: &[], summary: "Undoes the last edit", build: |_| Command::Undo },
+    Spec { name: "redo", aliases: &[], args: &[], summary: "Redoes the last undone edit", build: |_| Command::Redo },
+    Spec {
+        name: "yank",
+        aliases: &[],
+        args: &[arg("start", ArgKind::Time), arg("end", ArgKind::Time)],
+        summary: "Copies the instructions of the target instrument between two times",
+        build: |v| Command::Yank(v[0].number(), v[1].number()),
+    },
+    Spec { name: "paste", aliases: &[], args: &[], summary: "Pastes the yanked instructions at the target time", build: |_| Command::Paste },
+    Spec {
+        name: "rm",
+        aliases: &[],
+        args: &[arg("instruction", ArgKind::Instruction)],
+        summary: "Removes the instruction of the same kind at the target time",
+        build: |v| Command::Remove(v[0].instruction()),
+    },
+    Spec {
+        name: "set",
+        aliases: &[],
+        args: &[arg("instruction", ArgKind::Instruction)],
+        summary: "Changes a setting of the target instrument right away, without adding it to the song",
+        build: |v| Command::Set(v[0].instruction()),
+    },
+    Spec {
+        name: "params",
+        aliases: &[],
+        args: &[],
+        summary: "Lists the parameters of the target instrument and their current values",
+        build: |_| Command::Params,
+    },
+    Spec {
+        name: "auto",
+        aliases: &[],
+        args: &[arg("param", ArgKind::Param), arg("value", ArgKind::Number), optional("curve", ArgKind::Curve)],
+        summary: "Adds an automation breakpoint for a parameter of the target instrument at the target time, with the curve leading to the next one (lin by default)",
+        build: |v| Command::Auto { name: v[0].name(), value: v[1].number(), curve: v.get(2).map_or(Curve::Linear, Value::curve) },
+    },
+    Spec {
+        name: "auto del",
+        aliases: &[],
+        args: &[arg("param", ArgKind::Param)],
+        summary: "Removes the breakpoint of a parameter of the target instrument at the target time",
+        build: |v| Command::AutoDel(v[0].name()),
+    },
+    Spec {
+        name: "lane",
+        aliases: &[],
+        args: &[arg("param", ArgKind::Param)],
+        summary: "Opens the automation lane of a parameter of the target instrument for editing",
+        build: |v| Command::Lane(v[0].name()),
+    },
+    Spec {
+        name: "pattern",
+        aliases: &[],
+        args: &[],
+        summary: "Opens the notes of the target instrument as rows, one per step, for editing notes and velocities",
+        build: |_| Command::Pattern,
+    },
+    Spec {
+        name: "env",
+        aliases: &[],
+        args: &[
+            arg("param", ArgKind::Param),
+            arg("time", ArgKind::Time),
+            arg("value", ArgKind::Number),
+            optional("curve", ArgKind::Curve),
+        ],
+        summary: "Adds a point to the envelope of a parameter of the target instrument, which plays from every note on. The time is since the note started, in beats when synced",
+        build: |v| Command::Env {
+            name: v[0].name(),
+            time: v[1].number(),
+            value: v[2].number(),
+            curve: v.get(3).map_or(Curve::Linear, Value::curve),
+        },
+    },
+    Spec {
+        name: "env del",
+        aliases: &[],
+        args: &[arg("param", ArgKind::Param), optional("point", ArgKind::Index)],
+        summary: "Removes a point from an envelope, or the whole envelope",
+        build: |v| Command::EnvDel(v[0].name(), v.get(1).map(Value::int)),
+    },
+    Spec {
+        name: "env sustain",
+        aliases: &[],
+        args: &[arg("param", ArgKind::Param), optional("point", ArgKind::Index)],
+        summary: "Holds an envelope at a point while the note is held, or stops holding it",
+        build: |v| Command::EnvSustain(v[0].name(), v.get(1).map(Value::int)),
+    },
+    Spec {
+        name: "env loop",
+        aliases: &[],
+        args: &[arg("param", ArgKind::Param), optional("start", ArgKind::Index), optional("end", ArgKind::Index)],
+        summary: "Repeats an envelope between two points while the note is held, ending at the last point by default. Clears the loop without points",
+        build: |v| Command::EnvLoop(v[0].name(), v.get(1).map(|start| (start.int(), v.get(2).map(Value::int)))),
+    },
+    Spec {
+        name: "env sync",
+        aliases: &[],
+        args: &[arg("param", ArgKind::Param), arg("state", ArgKind::Status)],
+        summary: "Counts the times of an envelope in beats (on) or seconds (off)",
+        build: |v| Command::EnvSync(v[0].name(), v[1].status()),
+    },
+    Spec {
+        name: "env show",
+        aliases: &[],
+        args: &[arg("param", ArgKind::Param)],
+        summary: "Lists the points of an envelope",
+        build: |v| Command::EnvShow(v[0].name()),
+    },
+    Spec {
+        name: "master",
+        aliases: &[],
+        args: &[optional("level", ArgKind::Number)],
+        summary: "Sets the level of the whole mix from 0 to 1, or shows it",
+        build: |v| Command::Master(v.first().map(Value::number)),
+    },
+    Spec {
+        name: "bpm",
+        aliases: &[],
+        args: &[optional("bpm", ArgKind::Index)],
+        summary: "Sets the tempo the song starts at, before any tempo change, or shows it",
+        build: |v| Command::Bpm(v.first().map(Value::int)),
+    },
+    Spec {
+        name: "tempo",
+        aliases: &[],
+        args: &[arg("bpm", ArgKind::Number), optional("curve", ArgKind::Curve)],
+        summary: "Changes the tempo at the target time, with the curve leading to the next change (step by default, lin to glide)",
+        build: |v| Command::Tempo { bpm: v[0].number(), curve: v.get(1).map_or(Curve::Step, Value::curve) },
+    },
+    Spec {
+        name: "tempo del",
+        aliases: &[],
+        args: &[],
+        summary: "Removes the tempo change at the target time",
+        build: |_| Command::TempoDel,
+    },
+    Spec {
+        name: "tempos",
+        aliases: &[],
+        args: &[],
+        summary: "Lists the tempo changes of the song",
+        build: |_| Command::Tempos,
+    },
+    Spec {
+        name: "beat",
+        aliases: &[],
+        args: &[arg("beat", ArgKind::Number)],
+        summary: "Sets the target time to a beat, counting from 0, wherever the tempo map puts it",
+        build: |v| Command::Beat(v[0].number()),
+    },
+    Spec {
+        name: "groove",
+        aliases: &[],
+        args: &[optional("groove", ArgKind::Groove)],
+        summary: "Sets the groove the whole song is played with, or shows it",
+        build: |v| Command::Groove(v.first().map(Value::text)),
+    },
+    Spec {
+        name: "groove inst",
+        aliases: &[],
+        args: &[arg("groove", ArgKind::Groove)],
+        summary: "Sets the groove of the target instrument's pattern, played instead of the song's",
+        build: |v| Command::GrooveInst(v[0].text()),
+    },
+    Spec {
+        name: "groove inst del",
+        aliases: &[],
+        args: &[],
+        summary: "Lets the target instrument follow the groove of the song again",
+        build: |_| Command::GrooveInstDel,
+    },
+    Spec {
+        name: "swing",
+        aliases: &[],
+        args: &[arg("percent", ArgKind::Number)],
+        summary: "Swings the whole song, the first row of each pair taking this percentage of the pair (50 is straight)",
+        build: |v| Command::Swing(v[0].number()),
+    },
+    Spec {
+        name: "grooves",
+        aliases: &[],
+        args: &[],
+        summary: "Lists the built-in grooves and those of the project",
+        build: |_| Command::Grooves,
+    },
+    Spec {
+        name: "project load",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Loads the grooves and groove settings of a RON project file",
+        build: |v| Command::ProjectLoad(v[0].text()),
+    },
+    Spec {
+        name: "project save",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Saves the grooves and groove settings to a RON project file",
+        build: |v| Command::ProjectSave(v[0].text()),
+    },
+    Spec {
+        name: "song load",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Replaces the song with one from a text file, instruments, automation and rows included",
+        build: |v| Command::SongLoad(v[0].text()),
+    },
+    Spec {
+        name: "song save",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Writes the song to a text file with a line per row and a column per instrument",
+        build: |v| Command::SongSave(v[0].text()),
+    },
+    Spec {
+        name: "watch",
+        aliases: &[],
+        args: &[arg("state", ArgKind::Status)],
+        summary: "Turns reloading the song and project files on or off, reloading them as they change on disk",
+        build: |v| Command::Watch(v[0].status()),
+    },
+    Spec {
+        name: "import midi",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Adds the notes and tempo of a .mid file to the song, on new instruments",
+        build: |v| Command::ImportMidi(v[0].text()),
+    },
+    Spec {
+        name: "export midi",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Writes the notes of every instrument and the tempo to a .mid file",
+        build: |v| Command::ExportMidi(v[0].text()),
+    },
+    Spec {
+        name: "import module",
+        aliases: &["import mod", "import xm"],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Adds a .mod or .xm tracker module to the song, its samples on new sampler instruments",
+        build: |v| Command::ImportModule(v[0].text()),
+    },
+    Spec {
+        name: "import mml",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Adds the channels of an MML file to the song, like A: t120 o4 l8 cdefgab>c",
+        build: |v| Command::ImportMml(v[0].text()),
+    },
+    Spec {
+        name: "import",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Adds a .mid, .mod, .xm or .mml file to the song, going by its extension",
+        build: |v| Command::Import(v[0].text()),
+    },
+    Spec {
+        name: "loop",
+        aliases: &[],
+        args: &[arg("start", ArgKind::Time), arg("end", ArgKind::Time)],
+        summary: "Loops playback from a time back to another, chasing the state of the song at the start",
+        build: |v| Command::Loop(v[0].number(), v[1].number()),
+    },
+    Spec {
+        name: "loop beats",
+        aliases: &[],
+        args: &[arg("start", ArgKind::Number), arg("end", ArgKind::Number)],
+        summary: "Loops playback between two beats counting from 0, like 0 and 16 for the first four bars",
+        build: |v| Command::LoopBeats(v[0].number(), v[1].number()),
+    },
+    Spec {
+        name: "loop start",
+        aliases: &[],
+        args: &[],
+        summary: "Moves the start of the loop to the target time",
+        build: |_| Command::LoopStart,
+    },
+    Spec {
+        name: "loop end",
+        aliases: &[],
+        args: &[],
+        summary: "Moves the end of the loop to the target time",
+        build: |_| Command::LoopEnd,
+    },
+    Spec {
+        name: "loop pattern",
+        aliases: &[],
+        args: &[optional("rows", ArgKind::Index)],
+        summary: "Loops whichever pattern is playing, patterns being 64 rows long unless given",
+        build: |v| Command::LoopPattern(v.first().map(Value::int)),
+    },
+    Spec {
+        name: "loop off",
+        aliases: &[],
+        args: &[],
+        summary: "Stops looping",
+        build: |_| Command::LoopOff,
+    },
+    Spec {
+        name: "record",
+        aliases: &["rec"],
+        args: &[],
+        summary: "Plays and records notes played on the keyboard into the target instrument, from z and q up like a piano",
+        build: |_| Command::Record,
+    },
+    Spec {
+        name: "jam",
+        aliases: &["audition"],
+        args: &[],
+        summary: "Plays notes on the keyboard on the target instrument to try it out, even when paused, without recording them",
+        build: |_| Command::Jam,
+    },
+    Spec {
+        name: "record quantize",
+        aliases: &["rec quantize"],
+        args: &[arg("state", ArgKind::Status)],
+        summary: "Whether recorded notes snap to the start of the row they're played in, which they do by default",
+        build: |v| Command::RecordQuantize(v[0].status()),
+    },
+    Spec {
+        name: "record velocity",
+        aliases: &["rec velocity"],
+        args: &[arg("velocity", ArgKind::Velocity)],
+        summary: "Sets the velocity recorded notes get",
+        build: |v| Command::RecordVelocity(v[0].int() as u8),
+    },
+    Spec {
+        name: "metronome",
+        aliases: &["click"],
+        args: &[arg("state", ArgKind::Status)],
+        summary: "Turns the click track on or off, louder on the first beat of each bar",
+        build: |v| Command::Metronome(v[0].status()),
+    },
+    Spec {
+        name: "metronome volume",
+        aliases: &["click volume"],
+        args: &[arg("level", ArgKind::Number)],
+        summary: "Sets the level of the click track from 0 to 1",
+        build: |v| Command::MetronomeVolume(v[0].number()),
+    },
+    Spec {
+        name: "render",
+        aliases: &[],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Renders the whole song to a WAV file, as fast as it goes",
+        build: |v| Command::Render { path: v[0].text(), stems: None },
+    },
+    Spec {
+        name: "render stems",
+        aliases: &["render --stems"],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Renders the song to a WAV file and each instrument to its own next to it, at its level in the mix",
+        build: |v| Command::Render { path: v[0].text(), stems: Some(Tap::Post) },
+    },
+    Spec {
+        name: "render stems pre",
+        aliases: &["render --stems pre"],
+        args: &[arg("path", ArgKind::Path)],
+        summary: "Renders the song to a WAV file and each instrument to its own next to it, before the mixer",
+        build: |v| Command::Render { path: v[0].text(), stems: Some(Tap::Pre) },
+    },
+    Spec {
+        name: "metronome render",
+        aliases: &["click render"],
+        args: &[arg("state", ArgKind::Status)],
+        summary: "Whether renders include the click track, which they leave out by default",
+        build: |v| Command::MetronomeRender(v[0].status()),
+    },
+    Spec {
+        name: "signature",
+        aliases: &[],
+        args: &[arg("clicks", ArgKind::Index), arg("unit", ArgKind::Index)],
+        summary: "Sets the time signature, like 3 4 for three quarter notes or 6 8 for six eighth notes a bar",
+        build: |v| Command::Signature(v[0].int(), v[1].int()),
+    },
+    Spec {
+        name: "countin",
+        aliases: &[],
+        args: &[arg("state", ArgKind::Status)],
+        summary: "Whether playback starts with a bar of clicks",
+        build: |v| Command::CountIn(v[0].status()),
+    },
+    Spec {
+        name: "help",
+        aliases: &["h"],
+        args: &[optional("topic", ArgKind::Topic)],
+        summary: "Shows help for a command, instruction or argument type",
+        build: |v| Command::Help(v.first().map(Value::text)),
+    },
+];
+
+pub static INSTRUCTIONS: [Spec<InstructionKind>; 10] = [
+    Spec {
+        name: "form",
+        aliases: &[],
+        args: &[arg("waveform", ArgKind::Waveform)],
+        summary: "Sets the waveform of the oscillator",
+        build: |v| InstructionKind::Waveform(v[0].waveform()),
+    },
+    Spec {
+        name: "freq",
+        aliases: &[],
+        args: &[arg("frequency", ArgKind::Frequency)],
+        summary: "Sets the frequency of the oscillator",
+        build: |v| InstructionKind::Frequency(v[0].number()),
+    },
+    Spec {
+        name: "note",
+        aliases: &[],
+        args: &[arg("note", ArgKind::Note)],
+        summary: "Sets the frequency of the oscillator to that of a note",
+        build: |v| InstructionKind::Note(v[0].int() as u16),
+    },
+    Spec {
+        name: "state",
+        aliases: &[],
+        args: &[arg("state", ArgKind::Status), optional("velocity", ArgKind::Velocity)],
+        summary: "Presses (on) or releases (off) the note, as hard as the velocity or all the way",
+        build: |v| InstructionKind::State { status: v[0].status(), velocity: v.get(1).map_or(MAX_VELOCITY, |v| v.int() as u8) },
+    },
+    Spec {
+        name: "vib",
+        aliases: &[],
+        args: &[arg("state", ArgKind::Status)],
+        summary: "Turns vibrato on or off",
+        build: |v| InstructionKind::Vibrato(v[0].status()),
+    },
+    Spec {
+        name: "vib opt",
+        aliases: &[],
+        args: &[arg("rate", ArgKind::Frequency), arg("depth", ArgKind::Number)],
+        summary: "Sets how fast and how deep the vibrato is, depth being a fraction of the frequency",
+        build: |v| InstructionKind::VibratoSettings { rate: v[0].number(), depth: v[1].number() },
+    },
+    Spec {
+        name: "adsr",
+        aliases: &[],
+        args: &[
+            arg("attack", ArgKind::Time),
+            arg("decay", ArgKind::Time),
+            arg("sustain", ArgKind::Number),
+            arg("release", ArgKind::Time),
+        ],
+        summary: "Sets the volume envelope, sustain being a level from 0 to 1",
+        build: |v| InstructionKind::AdsrSettings { a: v[0].number(), d: v[1].number(), s: v[2].number(), r: v[3].number() },
+    },
+    Spec {
+        name: "dahdsr",
+        aliases: &[],
+        args: &[
+            arg("delay", ArgKind::Time),
+            arg("attack", ArgKind::Time),
+            arg("hold", ArgKind::Time),
+            arg("decay", ArgKind::Time),
+            arg("sustain", ArgKind::Number),
+            arg("release", ArgKind::Time),
+        ],
+        summary: "Sets the volume envelope with a delay before the attack and a hold at full level before the decay",
+        build: |v| InstructionKind::DahdsrSettings {
+            delay: v[0].number(),
+            a: v[1].number(),
+            hold: v[2].number(),
+            d: v[3].number(),
+            s: v[4].number(),
+            r: v[5].number(),
+        },
+    },
+    Spec {
+        name: "shape",
+        aliases: &[],
+        args: &[arg("attack", ArgKind::Shape), arg("decay", ArgKind::Shape), arg("release", ArgKind::Shape)],
+        summary: "Sets how the attack, decay and release of the volume envelope bend",
+        build: |v| InstructionKind::EnvelopeShape { attack: v[0].number(), decay: v[1].number(), release: v[2].number() },
+    },
+    Spec {
+        name: "param",
+        aliases: &[],
+        args: &[arg("name", ArgKind::Param), arg("value", ArgKind::Number)],
+        summary: "Sets any parameter of the instrument, see :params for what the target instrument has",
+        build: |v| InstructionKind::Param { name: v[0].name(), value: v[1].number() },
+    },
+];
+
+/// Finds the spec named by the next tokens. Longer names win, so `vib opt` is preferred over `vib`
+pub fn find<'a, T>(specs: &'a [Spec<T>], words: &[&str]) -> Option<&'a Spec<T>> {
+    specs
+        .iter()
+        .flat_map(|spec| spec.names().map(move |name| (spec, name)))
+        .filter(|(_, name)| {
+            let name_words = name.split(' ').collect::<Vec<_>>();
+            words.len() >= name_words.len() && words[..name_words.len()] == name_words[..]
+        })
+        .max_by_key(|(_, name)| name.split(' ').count())
+        .map(|(spec, _)| spec)
+}
+
+/// Parses the spec named by the next tokens and its arguments, leaving anything after them.
+/// `what` names the kind of spec in errors
+pub fn parse_spec<T>(specs: &[Spec<T>], args: &mut Args, what: &str) -> Result<T, ParseError> {
+    let words = args.remaining().iter().map(|t| t.text).collect::<Vec<_>>();
+    let Some(spec) = find(specs, &words) else {
+        let token = args.word(what)?;
+        return Err(ParseError::new(format!("Unknown {} '{}', see :help", what, token.text), token.span));
+    };
+    let name = spec.names().find(|name| words.starts_with(&name.split(' ').collect::<Vec<_>>())).unwrap();
+    for _ in name.split(' ') {
+        args.next();
+    }
+    let mut values = vec![];
+    for arg in spec.args {
+        if arg.optional && args.is_empty() {
+            break;
+        }
+        values.push(arg.kind.parse(args, arg.name)?);
+    }
+    Ok((spec.build)(&values))
+}
+
+/// Every word `:help` knows about
+pub fn topics() -> Vec<&'static str> {
+    let mut topics = COMMANDS.iter().map(|s| s.name)
+        .chain(INSTRUCTIONS.iter().map(|s| s.name))
+        .chain(ARG_KINDS.iter().map(|k| k.name()))
+        .chain(["commands", "instructions"])
+        .filter(|name| !name.contains(' '))
+        .collect::<Vec<_>>();
+    topics.sort();
+    topics.dedup();
+    topics
+}
+
+fn spec_help<T>(spec: &Spec<T>, lines: &mut Vec<String>) {
+    let mut usage = spec.usage();
+    if !spec.aliases.is_empty() {
+        usage += &format!("    (also {})", spec.aliases.join(", "));
+    }
+    lines.push(usage);
+    lines.push(format!("    {}", spec.summary));
+    for arg in spec.args {
+        lines.push(format!("    {}: {}", arg.name, arg.kind.describe()));
+    }
+    lines.push(String::new());
+}
+
+fn summary_lines<T>(title: &str, specs: &[Spec<T>], lines: &mut Vec<String>) {
+    lines.push(String::from(title));
+    let width = specs.iter().map(|s| s.usage().len()).max().unwrap_or(0);
+    for spec in specs {
+        lines.push(format!("  {:<width$}  {}", spec.usage(), spec.summary, width = width));
+    }
+    lines.push(String::new());
+}
+
+/// Help text for a topic, or an overview of everything without one.
+/// Returns `None` for unknown topics
+pub fn help(topic: Option<&str>) -> Option<Vec<String>> {
+    let mut lines = vec![];
+    match topic {
+        None => {
+            lines.push(String::from("Type :help <topic> for details on a command, instruction or argument type"));
+            lines.push(String::new());
+            summary_lines("Commands", &COMMANDS, &mut lines);
+            summary_lines("Instructions, added to the target instrument at the target time", &INSTRUCTIONS, &mut lines);
+        }
+        Some("commands") => summary_lines("Commands", &COMMANDS, &mut lines),
+        Some("instructions") => summary_lines("Instructions", &INSTRUCTIONS, &mut lines),
+        Some(topic) => {
+            // A topic covers every spec starting with it, so `vib` also shows `vib opt`
+            let matches = |name: &str| name == topic || name.starts_with(&format!("{} ", topic));
+            for spec in COMMANDS.iter().filter(|s| s.names().any(matches)) {
+                spec_help(spec, &mut lines);
+            }
+            for spec in INSTRUCTIONS.iter().filter(|s| s.names().any(matches)) {
+                spec_help(spec, &mut lines);
+            }
+            for kind in ARG_KINDS.iter().filter(|k| k.name() == topic) {
+                lines.push(format!("<{}>", kind.name()));
+                lines.push(format!("    {}, like {}", kind.describe(), kind.example()));
+                lines.push(String::new());
+            }
+        }
+    }
+    match lines.is_empty() {
+        true => None,
+        false => Some(lines),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::args::Args;
+    use crate::command::registry::{help, parse_spec, topics, COMMANDS, INSTRUCTIONS};
+
+    #[test]
+    fn every_usage_parses() {
+        for spec in COMMANDS.iter() {
+            let input = spec.args.iter().fold(spec.name.to_string(), |acc, arg| acc + " " + arg.kind.example());
+            let mut args = Args::new(&input);
+            assert!(parse_spec(&COMMANDS, &mut args, "command").is_ok(), "{}", input);
+            assert!(args.finish().is_ok(), "{}", input);
+        }
+        for spec in INSTRUCTIONS.iter() {
+            let input = spec.args.iter().fold(spec.name.to_string(), |acc, arg| acc + " " + arg.kind.example());
+            let mut args = Args::new(&input);
+            assert!(parse_spec(&INSTRUCTIONS, &mut args, "instruction").is_ok(), "{}", input);
+            assert!(args.finish().is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn every_topic_has_help() {
+        for topic in topics() {
+            assert!(help(Some(topic)).is_some(), "{}", topic);
+        }
+        assert!(help(Some("vib")).unwrap().iter().any(|l| l.starts_with("vib opt <rate> <depth>")));
+        assert!(help(Some("nonsense")).is_none());
+    }
+}
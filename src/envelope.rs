@@ -0,0 +1,262 @@
+use crate::automation::Curve;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Point {
+    /// Since the note started, in seconds or beats when synced
+    pub time: f32,
+    pub value: f32,
+    /// How the envelope gets from this point to the next one
+    pub curve: Curve,
+}
+
+/// Free-form envelope driving one parameter from the moment a note starts, like the macros of
+/// trackers. It can hold at a sustain point or repeat a loop while the note is held
+#[derive(Clone, Default, Debug)]
+pub struct Envelope {
+    points: Vec<Point>,
+    /// Point to hold at while the note is held
+    pub sustain: Option<usize>,
+    /// First and last point of a loop repeated while the note is held. Wins over `sustain`
+    pub looped: Option<(usize, usize)>,
+    /// Point times are in beats instead of seconds
+    pub synced: bool,
+    // Time since the note started, `None` when not playing
+    position: Option<f64>,
+    released: bool,
+}
+
+impl Envelope {
+    /// Adds a point, replacing any point at the same time
+    pub fn set_point(&mut self, point: Point) {
+        match self.points.iter().position(|p| p.time >= point.time) {
+            Some(i) if self.points[i].time == point.time => self.points[i] = point,
+            Some(i) => self.points.insert(i, point),
+            None => self.points.push(point),
+        }
+    }
+
+    pub fn remove_point(&mut self, index: usize) -> Option<Point> {
+        (index < self.points.len()).then(|| self.points.remove(index))
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    pub fn trigger(&mut self) {
+        self.position = Some(0.0);
+        self.released = false;
+    }
+
+    pub fn release(&mut self) {
+        self.released = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.position = None;
+    }
+
+    /// The value `time` after the note started
+    pub fn value_at(&self, time: f64) -> Option<f32> {
+        let first = self.points.first()?;
+        if time <= first.time as f64 {
+            return Some(first.value);
+        }
+        let i = self.points.iter().rposition(|p| p.time as f64 <= time).unwrap();
+        let (from, to) = match self.points.get(i + 1) {
+            Some(to) => (self.points[i], to),
+            None => return Some(self.points[i].value),
+        };
+        let x = (time - from.time as f64) / (to.time - from.time) as f64;
+        Some(from.curve.interpolate(from.value, to.value, x))
+    }
+
+    /// The current value, moving on by `step` seconds or beats. `None` when not playing
+    pub fn tick(&mut self, step: f64) -> Option<f32> {
+        let position = self.position?;
+        let value = self.value_at(position);
+        let mut next = position + step;
+        let time = |i: usize| self.points.get(i).map(|p| p.time as f64);
+        if !self.released {
+            match (self.looped, self.sustain) {
+                (Some((start, end)), _) => {
+                    if let (Some(start), Some(end)) = (time(start), time(end)) {
+                        if end > start && position < end && next >= end {
+                            next = start + (next - end) % (end - start);
+                        }
+                    }
+                }
+                (None, Some(sustain)) => {
+                    if let Some(sustain) = time(sustain) {
+                        if position <= sustain && next > sustain {
+                            next = sustain;
+                        }
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        // Past the last point the value stays put, so there's nothing more to do
+        let end = self.points.last().map_or(0.0, |p| p.time as f64);
+        self.position = if position > end { None } else { Some(next) };
+        value
+    }
+}
+
+/// Envelopes of every instrument, by instrument index and parameter name
+pub struct Envelopes {
+    envelopes: HashMap<u128, BTreeMap<&'static str, Envelope>>,
+}
+
+impl Envelopes {
+    pub fn new() -> Self {
+        Self { envelopes: HashMap::new() }
+    }
+
+    pub fn get(&self, target: u128, name: &str) -> Option<&Envelope> {
+        self.envelopes.get(&target)?.get(name)
+    }
+
+    /// Every envelope of `target`, by parameter name
+    pub fn target(&self, target: u128) -> impl Iterator<Item = (&'static str, &Envelope)> {
+        self.envelopes.get(&target).into_iter().flat_map(|envelopes| envelopes.iter().map(|(name, env)| (*name, env)))
+    }
+
+    pub fn target_mut(&mut self, target: u128) -> impl Iterator<Item = (&'static str, &mut Envelope)> {
+        self.envelopes.get_mut(&target).into_iter().flat_map(|envelopes| envelopes.iter_mut().map(|(name, env)| (*name, env)))
+    }
+
+    /// Replaces or removes the envelope of a parameter, returning the old one
+    pub fn set(&mut self, target: u128, name: &'static str, envelope: Option<Envelope>) -> Option<Envelope> {
+        let Some(envelope) = envelope else {
+            let envelopes = self.envelopes.get_mut(&target)?;
+            let removed = envelopes.remove(name);
+            if envelopes.is_empty() {
+                self.envelopes.remove(&target);
+            }
+            return removed;
+        };
+        self.envelopes.entry(target).or_default().insert(name, envelope)
+    }
+
+    /// Starts or releases the envelopes of `target`, as a note does
+    pub fn trigger(&mut self, target: u128, pressed: bool) {
+        for (_, envelope) in self.target_mut(target) {
+            match pressed {
+                true => envelope.trigger(),
+                false => envelope.release(),
+            }
+        }
+    }
+
+    pub fn stop_all(&mut self) {
+        for envelope in self.envelopes.values_mut().flat_map(|it| it.values_mut()) {
+            envelope.stop();
+        }
+    }
+
+    pub fn take_target(&mut self, target: u128) -> BTreeMap<&'static str, Envelope> {
+        self.envelopes.remove(&target).unwrap_or_default()
+    }
+
+    pub fn restore_target(&mut self, target: u128, envelopes: BTreeMap<&'static str, Envelope>) {
+        if !envelopes.is_empty() {
+            self.envelopes.insert(target, envelopes);
+        }
+    }
+
+    /// Moves the envelopes of every target from `from` and up one step up or down, like
+    /// [`crate::automation::Automation::shift_targets`]
+    pub fn shift_targets(&mut self, from: u128, up: bool) {
+        let shifted = self.envelopes.keys().copied().filter(|t| *t >= from).collect::<Vec<_>>();
+        let moved = shifted.into_iter().map(|t| (t, self.envelopes.remove(&t).unwrap())).collect::<Vec<_>>();
+        for (target, envelopes) in moved {
+            self.envelopes.insert(if up { target + 1 } else { target - 1 }, envelopes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::automation::Curve;
+    use crate::envelope::{Envelope, Point};
+    use crate::instruction::{InstructionKind, Status};
+    use crate::instrument::oscillator::Waveform;
+
+    fn envelope() -> Envelope {
+        let mut env = Envelope::default();
+        for (time, value) in [(0.0, 0.0), (1.0, 1.0), (2.0, 0.0), (3.0, 0.5)] {
+            env.set_point(Point { time, value, curve: Curve::Linear });
+        }
+        env
+    }
+
+    #[test]
+    fn holds_at_sustain_until_released() {
+        let mut env = envelope();
+        env.sustain = Some(1);
+        assert_eq!(env.tick(0.5), None);
+        env.trigger();
+        let values = (0..5).map(|_| env.tick(0.5).unwrap()).collect::<Vec<_>>();
+        assert_eq!(values, vec![0.0, 0.5, 1.0, 1.0, 1.0]);
+        env.release();
+        let values = (0..6).map(|_| env.tick(0.5)).collect::<Vec<_>>();
+        assert_eq!(values, vec![Some(1.0), Some(0.5), Some(0.0), Some(0.25), Some(0.5), Some(0.5)]);
+        assert_eq!(env.tick(0.5), None);
+    }
+
+    #[test]
+    fn loops_while_held() {
+        let mut env = envelope();
+        env.looped = Some((1, 2));
+        env.trigger();
+        let values = (0..8).map(|_| env.tick(0.5).unwrap()).collect::<Vec<_>>();
+        assert_eq!(values, vec![0.0, 0.5, 1.0, 0.5, 1.0, 0.5, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn envelopes_follow_notes() {
+        let mut app = App::new();
+        app.set_sample_rates(10.0);
+        let mut env = Envelope::default();
+        env.set_point(Point { time: 0.0, value: 12.0, curve: Curve::Step });
+        env.set_point(Point { time: 0.5, value: 0.0, curve: Curve::Step });
+        app.set_envelope(0, "pitch", Some(env)).unwrap();
+        assert!(app.set_envelope(1, "pitch", Some(Envelope::default())).is_err());
+        app.insert_instruction(0, 3, InstructionKind::State { status: Status::On, velocity: 100 });
+
+        app.play();
+        let pitches = (0..10).map(|_| {
+            app.tick_all();
+            app.instruments[0].get_param("pitch").unwrap()
+        });
+        assert_eq!(pitches.collect::<Vec<_>>(), vec![0.0, 0.0, 0.0, 12.0, 12.0, 12.0, 12.0, 12.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn envelopes_sweep_pulse_width() {
+        let mut app = App::new();
+        app.set_sample_rates(48000.0);
+        let mut env = Envelope::default();
+        env.set_point(Point { time: 0.0, value: 0.5, curve: Curve::Linear });
+        env.set_point(Point { time: 0.01, value: 0.1, curve: Curve::Step });
+        app.set_envelope(1, "duty", Some(env)).unwrap();
+        app.insert_instruction(1, 0, InstructionKind::Waveform(Waveform::Square));
+        app.insert_instruction(1, 0, InstructionKind::State { status: Status::On, velocity: 127 });
+
+        app.play();
+        let duties = (0..1000).map(|_| {
+            app.tick_all();
+            app.instruments[1].get_param("duty").unwrap()
+        }).collect::<Vec<_>>();
+        assert_eq!(duties[0], 0.5);
+        assert!((duties[240] - 0.3).abs() < 1e-3);
+        assert_eq!(duties[999], 0.1);
+        // A narrow pulse spikes up and sits just below zero for the rest of its cycle
+        let cycle = (0..220).map(|_| app.tick_all().0).collect::<Vec<_>>();
+        let (high, low) = cycle.iter().fold((0f32, 0f32), |(high, low), x| (high.max(*x), low.min(*x)));
+        assert!(high > -low * 3.0, "{} {}", high, low);
+    }
+}
\ No newline at end of file
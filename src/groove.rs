@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Rows can't move further than this from where they'd be straight, so they keep their order
+const MAX_OFFSET: f32 = 0.45;
+
+/// Built-in grooves, as swing percentages and accents over four rows
+pub const BUILT_IN: [(&str, f32, [f32; 4]); 6] = [
+    ("straight", 50.0, [1.0, 1.0, 1.0, 1.0]),
+    ("swing", 58.0, [1.0, 1.0, 1.0, 1.0]),
+    ("shuffle", 67.0, [1.0, 1.0, 1.0, 1.0]),
+    ("hard", 75.0, [1.0, 1.0, 1.0, 1.0]),
+    ("accents", 50.0, [1.0, 0.7, 0.85, 0.7]),
+    ("funk", 56.0, [1.0, 0.6, 0.8, 0.65]),
+];
+
+/// Timing and dynamics a pattern is played with, repeating every few rows. Rows are still
+/// edited on the grid; the groove moves them when they're played
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Groove {
+    pub name: String,
+    /// How far each row is moved, as a fraction of a row, negative to play early
+    #[serde(default)]
+    pub timing: Vec<f32>,
+    /// What the velocity of notes on each row is multiplied by
+    #[serde(default)]
+    pub accents: Vec<f32>,
+}
+
+impl Groove {
+    /// Delays every second row so the first of each pair takes `percent` of the pair, 50 being
+    /// straight and 67 a triplet shuffle
+    pub fn swing(name: &str, percent: f32) -> Self {
+        let offset = percent.clamp(0.0, 100.0) / 50.0 - 1.0;
+        Self { name: name.to_string(), timing: vec![0.0, offset], accents: vec![] }
+    }
+
+    pub fn built_in(name: &str) -> Option<Self> {
+        let (name, swing, accents) = BUILT_IN.iter().find(|(n, _, _)| *n == name)?;
+        Some(Self { accents: accents.to_vec(), ..Self::swing(name, *swing) })
+    }
+
+    pub fn offset(&self, row: u128) -> f32 {
+        match self.timing.len() {
+            0 => 0.0,
+            n => self.timing[(row % n as u128) as usize].clamp(-MAX_OFFSET, MAX_OFFSET),
+        }
+    }
+
+    pub fn accent(&self, row: u128) -> f32 {
+        match self.accents.len() {
+            0 => 1.0,
+            n => self.accents[(row % n as u128) as usize].max(0.0),
+        }
+    }
+
+    /// Whether the groove moves anything at all
+    pub fn moves(&self) -> bool {
+        self.timing.iter().any(|offset| *offset != 0.0)
+    }
+
+    pub fn describe(&self) -> String {
+        let list = |values: &[f32]| values.iter().map(|v| format!("{:.2}", v)).collect::<Vec<_>>().join(" ");
+        format!("timing [{}]  accents [{}]", list(&self.timing), list(&self.accents))
+    }
+}
+
+/// Grooves of the song and of the patterns of single instruments, which win over the song's
+pub struct Grooves {
+    pub song: Option<Groove>,
+    targets: HashMap<u128, Groove>,
+    /// Grooves defined in the project file, found by name before the built-in ones
+    pub user: Vec<Groove>,
+}
+
+impl Grooves {
+    pub fn new() -> Self {
+        Self { song: None, targets: HashMap::new(), user: vec![] }
+    }
+
+    /// A user-defined or built-in groove
+    pub fn find(&self, name: &str) -> Option<Groove> {
+        self.user.iter().find(|g| g.name == name).cloned().or_else(|| Groove::built_in(name))
+    }
+
+    /// The groove `target` is played with, if any
+    pub fn get(&self, target: u128) -> Option<&Groove> {
+        self.targets.get(&target).or(self.song.as_ref())
+    }
+
+    /// The groove of `target`'s own, leaving out the song's
+    pub fn target(&self, target: u128) -> Option<&Groove> {
+        self.targets.get(&target)
+    }
+
+    pub fn set_target(&mut self, target: u128, groove: Option<Groove>) {
+        match groove {
+            Some(groove) => self.targets.insert(target, groove),
+            None => self.targets.remove(&target),
+        };
+    }
+
+    /// Every instrument with a groove of its own
+    pub fn targets(&self) -> impl Iterator<Item = (u128, &Groove)> {
+        self.targets.iter().map(|(target, groove)| (*target, groove))
+    }
+
+    pub fn take_target(&mut self, target: u128) -> Option<Groove> {
+        self.targets.remove(&target)
+    }
+
+    /// Moves the grooves of every target from `from` and up one step up or down, like
+    /// [`crate::automation::Automation::shift_targets`]
+    pub fn shift_targets(&mut self, from: u128, up: bool) {
+        let shifted = self.targets.keys().copied().filter(|t| *t >= from).collect::<Vec<_>>();
+        let moved = shifted.into_iter().map(|t| (t, self.targets.remove(&t).unwrap())).collect::<Vec<_>>();
+        for (target, groove) in moved {
+            self.targets.insert(if up { target + 1 } else { target - 1 }, groove);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::groove::Groove;
+    use crate::instruction::{InstructionKind, Status};
+
+    #[test]
+    fn moves_and_accents_rows_when_played() {
+        let mut app = App::new();
+        // Rows are 125 ticks long at 120 BPM
+        app.set_sample_rates(1000.0);
+        let note = |velocity| InstructionKind::State { status: Status::On, velocity };
+        app.insert_instruction(0, 0, note(100));
+        app.insert_instruction(0, 125, note(100));
+        app.insert_instruction(0, 130, InstructionKind::Note(60));
+        app.grooves.song = Some(Groove { accents: vec![1.0, 0.5], ..Groove::swing("swing", 60.0) });
+
+        app.insert_instruction(1, 125, note(100));
+        app.insert_instruction(1, 250, note(100));
+
+        let played = |app: &App, target| (0..500).filter(|t| !app.grooved_instructions(target, *t).is_empty()).collect::<Vec<_>>();
+        // The second row starts a fifth of a row late and the rest of it is squeezed to fit
+        assert_eq!(played(&app, 0), vec![0, 150, 154]);
+        assert_eq!(app.grooved_instructions(0, 150), vec![note(50)]);
+
+        // The instrument's own groove wins over the song's
+        app.grooves.set_target(0, Groove::built_in("straight"));
+        assert_eq!(played(&app, 0), vec![0, 125, 130]);
+        assert_eq!(played(&app, 1), vec![150, 250]);
+
+        // Rows played before follow the tempo when it changes
+        app.tempo.set_initial_bpm(60.0);
+        assert_eq!(played(&app, 1), vec![150, 300]);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::app::App;
+use crate::automation::{Breakpoint, Lane};
+use crate::envelope::Envelope;
+use crate::groove::Groove;
+use crate::instruction::InstructionKind;
+use std::collections::BTreeMap;
+use crate::instrument::Instrument;
+
+/// A reversible change to the song or its instruments
+pub enum Edit {
+    /// Replaces the instruction of one kind at a slot. `None` means no instruction of that kind
+    Instruction {
+        target: u128,
+        time: u128,
+        before: Option<InstructionKind>,
+        after: Option<InstructionKind>,
+    },
+    /// Applies settings directly to an instrument, outside of the song. Reverted by a snapshot
+    /// of every parameter
+    Settings {
+        target: u128,
+        after: Vec<InstructionKind>,
+    },
+    /// Replaces the breakpoint of an automation lane at a tick. `None` means no breakpoint
+    Breakpoint {
+        target: u128,
+        name: &'static str,
+        time: u128,
+        before: Option<Breakpoint>,
+        after: Option<Breakpoint>,
+    },
+    /// Replaces the tempo change at a tick. `None` means no change
+    Tempo {
+        time: u128,
+        before: Option<Breakpoint>,
+        after: Option<Breakpoint>,
+    },
+    /// Replaces the tempo the song starts at and its time signature
+    Timing {
+        before: (f32, (u32, u32)),
+        after: (f32, (u32, u32)),
+    },
+    /// Replaces the groove of an instrument, or of the whole song without a target. `None`
+    /// means no groove
+    Groove {
+        target: Option<u128>,
+        before: Option<Groove>,
+        after: Option<Groove>,
+    },
+    /// Replaces the envelope of a parameter. `None` means no envelope
+    Envelope {
+        target: u128,
+        name: &'static str,
+        before: Option<Envelope>,
+        after: Option<Envelope>,
+    },
+    InsertInstrument {
+        index: usize,
+        instrument: Box<dyn Instrument>,
+        instructions: Vec<(u128, InstructionKind)>,
+        lanes: BTreeMap<&'static str, Lane>,
+        envelopes: BTreeMap<&'static str, Envelope>,
+        groove: Option<Groove>,
+    },
+    RemoveInstrument {
+        index: usize,
+    },
+    /// Several edits that are undone and redone as one step
+    Group(Vec<Edit>),
+}
+
+impl Edit {
+    /// Applies the edit to `app`, returning the edit that reverts it
+    pub fn apply(self, app: &mut App) -> Edit {
+        match self {
+            Edit::Instruction { target, time, before, after } => {
+                if let Some(kind) = before {
+                    app.instructions.remove_type(target, time, kind);
+                }
+                if let Some(kind) = after {
+                    app.instructions.insert(target, time, kind);
+                }
+                Edit::Instruction { target, time, before: after, after: before }
+            }
+            Edit::Settings { target, after } => {
+                let Some(inst) = app.instruments.get_mut(target as usize) else {
+                    return Edit::Group(vec![]);
+                };
+                let before = inst.settings();
+                for kind in after {
+                    // Settings are validated before they become edits
+                    let _ = inst.apply_instruction(kind);
+                }
+                Edit::Settings { target, after: before }
+            }
+            Edit::Breakpoint { target, name, time, before, after } => {
+                match after {
+                    Some(point) => app.automation.insert(target, name, time, point),
+                    None => app.automation.remove(target, name, time),
+                };
+                Edit::Breakpoint { target, name, time, before: after, after: before }
+            }
+            Edit::Tempo { time, before, after } => {
+                match after {
+                    Some(change) => app.tempo.insert(time, change),
+                    None => app.tempo.remove(time),
+                };
+                app.metronome.reset();
+                Edit::Tempo { time, before: after, after: before }
+            }
+            Edit::Timing { before, after } => {
+                let (bpm, (clicks, unit)) = after;
+                app.tempo.set_initial_bpm(bpm);
+                // Signatures are checked before they become edits
+                let _ = app.tempo.set_signature(clicks, unit);
+                app.metronome.reset();
+                Edit::Timing { before: after, after: before }
+            }
+            Edit::Groove { target, before, after } => {
+                match target {
+                    Some(target) => app.grooves.set_target(target, after.clone()),
+                    None => app.grooves.song = after.clone(),
+                }
+                Edit::Groove { target, before: after, after: before }
+            }
+            Edit::Envelope { target, name, before, after } => {
+                app.envelopes.set(target, name, after.clone());
+                Edit::Envelope { target, name, before: after, after: before }
+            }
+            Edit::InsertInstrument { index, instrument, instructions, lanes, envelopes, groove } => {
+                app.instructions.shift_targets(index as u128, true);
+                app.automation.shift_targets(index as u128, true);
+                app.envelopes.shift_targets(index as u128, true);
+                app.grooves.shift_targets(index as u128, true);
+                for (time, kind) in instructions {
+                    app.instructions.insert(index as u128, time, kind);
+                }
+                app.automation.restore_target(index as u128, lanes);
+                app.envelopes.restore_target(index as u128, envelopes);
+                app.grooves.set_target(index as u128, groove);
+                app.instruments.insert(index, instrument);
+                Edit::RemoveInstrument { index }
+            }
+            Edit::RemoveInstrument { index } => {
+                if index >= app.instruments.len() {
+                    return Edit::Group(vec![]);
+                }
+                let instrument = app.instruments.remove(index);
+                let instructions = app.instructions.take_target(index as u128);
+                let lanes = app.automation.take_target(index as u128);
+                let envelopes = app.envelopes.take_target(index as u128);
+                let groove = app.grooves.take_target(index as u128);
+                app.instructions.shift_targets(index as u128 + 1, false);
+                app.automation.shift_targets(index as u128 + 1, false);
+                app.envelopes.shift_targets(index as u128 + 1, false);
+                app.grooves.shift_targets(index as u128 + 1, false);
+                Edit::InsertInstrument { index, instrument, instructions, lanes, envelopes, groove }
+            }
+            Edit::Group(edits) => {
+                let mut inverse = edits.into_iter().map(|e| e.apply(app)).collect::<Vec<_>>();
+                inverse.reverse();
+                Edit::Group(inverse)
+            }
+        }
+    }
+}
+
+/// Undo and redo stacks of applied edits
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    groups: Vec<Vec<Edit>>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo: vec![],
+            redo: vec![],
+            groups: vec![],
+        }
+    }
+
+    /// Records the inverse of an edit that was just applied
+    pub fn record(&mut self, inverse: Edit) {
+        self.redo.clear();
+        match self.groups.last_mut() {
+            Some(group) => group.push(inverse),
+            None => self.undo.push(inverse),
+        }
+    }
+
+    /// Starts collecting recorded edits into a single undo step. Groups may be nested
+    pub fn begin_group(&mut self) {
+        self.groups.push(vec![]);
+    }
+
+    pub fn end_group(&mut self) {
+        let Some(mut group) = self.groups.pop() else {
+            return;
+        };
+        if group.is_empty() {
+            return;
+        }
+        // Inverses have to be applied last to first
+        group.reverse();
+        self.record(Edit::Group(group));
+    }
+
+    pub fn pop_undo(&mut self) -> Option<Edit> {
+        self.undo.pop()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Edit> {
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, edit: Edit) {
+        self.undo.push(edit)
+    }
+
+    pub fn push_redo(&mut self, edit: Edit) {
+        self.redo.push(edit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::automation::{Breakpoint, Curve};
+    use crate::instruction::InstructionKind;
+    use crate::instrument::oscillator::Waveform;
+
+    #[test]
+    fn undo_redo_instructions() {
+        let mut app = App::new();
+        app.insert_instruction(0, 10, InstructionKind::Frequency(3.0));
+        app.insert_instruction(0, 10, InstructionKind::Frequency(4.0));
+        assert!(app.instructions.has(0, 10, InstructionKind::Frequency(4.0)));
+
+        assert!(app.undo());
+        assert!(app.instructions.has(0, 10, InstructionKind::Frequency(3.0)));
+        assert!(app.undo());
+        assert!(app.instructions.get(0, 10).is_empty());
+        assert!(!app.undo());
+
+        assert!(app.redo());
+        assert!(app.redo());
+        assert!(app.instructions.has(0, 10, InstructionKind::Frequency(4.0)));
+        assert!(!app.redo());
+    }
+
+    #[test]
+    fn groups_undo_as_one_step() {
+        let mut app = App::new();
+        app.begin_group();
+        app.insert_instruction(0, 0, InstructionKind::Waveform(Waveform::Saw));
+        app.insert_instruction(1, 5, InstructionKind::Note(60));
+        app.remove_instruction(0, 0, InstructionKind::Waveform(Waveform::Saw));
+        app.end_group();
+
+        assert!(app.undo());
+        assert!(app.instructions.get(0, 0).is_empty());
+        assert!(app.instructions.get(1, 5).is_empty());
+        assert!(app.redo());
+        assert!(app.instructions.get(0, 0).is_empty());
+        assert!(app.instructions.has(1, 5, InstructionKind::Note(60)));
+    }
+
+    #[test]
+    fn undo_settings() {
+        let mut app = App::new();
+        app.apply_settings(0, InstructionKind::AdsrSettings { a: 1.0, d: 1.0, s: 1.0, r: 1.0 }).unwrap();
+        app.apply_settings(0, InstructionKind::Param { name: "attack", value: 2.0 }).unwrap();
+        assert_eq!(app.instruments[0].get_param("attack"), Some(2.0));
+        assert!(app.undo());
+        assert_eq!(app.instruments[0].get_param("attack"), Some(1.0));
+        assert!(app.undo());
+        assert_eq!(app.instruments[0].get_param("attack"), Some(0.1));
+        assert_eq!(app.instruments[0].get_param("sustain"), Some(0.5));
+        assert!(app.apply_settings(1, InstructionKind::Param { name: "attack", value: 2.0 }).is_err());
+    }
+
+    #[test]
+    fn undo_instrument_removal() {
+        let mut app = App::new();
+        app.insert_instruction(0, 1, InstructionKind::Note(60));
+        app.insert_instruction(1, 2, InstructionKind::Note(62));
+        app.remove_instrument(0);
+        assert_eq!(app.instruments.len(), 1);
+        assert!(app.instructions.has(0, 2, InstructionKind::Note(62)));
+
+        assert!(app.undo());
+        assert_eq!(app.instruments.len(), 2);
+        assert!(app.instructions.has(0, 1, InstructionKind::Note(60)));
+        assert!(app.instructions.has(1, 2, InstructionKind::Note(62)));
+    }
+
+    #[test]
+    fn undo_breakpoints_with_their_instrument() {
+        let mut app = App::new();
+        let point = Breakpoint { value: 0.5, curve: Curve::Linear };
+        app.set_breakpoint(0, "volume", 10, point).unwrap();
+        app.set_breakpoint(0, "volume", 10, Breakpoint { value: 1.0, curve: Curve::Step }).unwrap();
+        assert!(app.undo());
+        assert_eq!(app.automation.lane(0, "volume").unwrap().get(10), Some(point));
+
+        app.add_instrument(Box::new(crate::instrument::synth::Synth::new()));
+        app.remove_instrument(0);
+        assert!(app.automation.lane(0, "volume").is_none());
+        assert!(app.undo());
+        assert!(app.undo());
+        assert_eq!(app.automation.lane(0, "volume").unwrap().get(10), Some(point));
+    }
+}
@@ -1,17 +1,26 @@
-use std::hash::Hash;
-use crate::instrument::oscillator::Waveform;
-use crate::util::ParseAt;
+use crate::command::args::{note_name, Args, ParseError};
+use crate::command::registry;
+use crate::instrument::adsr::SHAPES;
+use crate::instrument::oscillator::{Waveform, WAVEFORMS};
+use std::fmt::{Display, Formatter};
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Status { On, Off }
 
+/// Velocity of notes that don't say, as hard as they get
+pub const MAX_VELOCITY: u8 = 127;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum InstructionKind {
     Waveform(Waveform),
     Frequency(f32),
     // TODO: Notes should probably be something other than just a number
     Note(u16),
-    State(Status),
+    /// Presses or releases the note, pressing as hard as `velocity`, up to [`MAX_VELOCITY`]
+    State {
+        status: Status,
+        velocity: u8
+    },
     Vibrato(Status),
     VibratoSettings{
         rate: f32,
@@ -23,80 +32,161 @@ pub enum InstructionKind {
         s: f32,
         r: f32
     },
+    /// Envelope with optional delay before the attack and hold at full level before the decay
+    DahdsrSettings {
+        delay: f32,
+        a: f32,
+        hold: f32,
+        d: f32,
+        s: f32,
+        r: f32
+    },
+    /// Tensions of the envelope segments, see [`crate::instrument::adsr::SHAPES`]
+    EnvelopeShape {
+        attack: f32,
+        decay: f32,
+        release: f32
+    },
+    /// Sets any parameter an instrument describes, see [`crate::instrument::Instrument::params`]
+    Param {
+        name: &'static str,
+        value: f32
+    },
 }
 
 impl InstructionKind {
-    pub fn parse(s: String) -> Result<Self, String> {
-        let split = s.split_whitespace();
-        let args = split.collect::<Vec<&str>>();
-
-        let mut kind = None;
-
-        match *args.get(0).unwrap_or(&"") {
-            "form" => {
-                if let Some(w) = match *args.get(1).unwrap_or(&"") {
-                    "saw" => Some(Waveform::Saw),
-                    "square" => Some(Waveform::Square),
-                    "tri" => Some(Waveform::Triangle),
-                    "sine" => Some(Waveform::Sine),
-                    _ => None
-                } {
-                    kind = Some(InstructionKind::Waveform(w));
-                }
-            },
-            "freq" => {
-                if let Ok(f) = args.parse_at::<f32>(1) {
-                    kind = Some(InstructionKind::Frequency(f));
-                }
-            },
-            "note" => {
-                if let Ok(n) = args.parse_at::<u16>(1) {
-                    kind = Some(InstructionKind::Note(n));
-                }
-            },
-            "state" => {
-                if let Some(n) = match *args.get(1).unwrap_or(&"") {
-                    "on" => Some(Status::On),
-                    "off" => Some(Status::On),
-                    _ => None
-                } {
-                    kind = Some(InstructionKind::State(n));
-                }
-            },
-            "vib" => {
-                match *args.get(1).unwrap_or(&"") {
-                    "on" => kind = Some(InstructionKind::Vibrato(Status::On)),
-                    "off" => kind = Some(InstructionKind::Vibrato(Status::Off)),
-                    "opt" => {
-                        if let (Ok(r), Ok(d)) = (
-                            args.parse_at::<f32>(1),
-                            args.parse_at::<f32>(2),
-                        ) {
-                            kind = Some(InstructionKind::VibratoSettings {
-                                rate: r,
-                                depth: d
-                            });
-                        }
-                    }
-                    other => return Err(format!("Unknown option for command vib '{}'", other))
-                }
-            },
-            "adsr" => {
-                if let (Ok(a), Ok(d), Ok(s), Ok(r)) = (
-                    args.parse_at::<f32>(1),
-                    args.parse_at::<f32>(2),
-                    args.parse_at::<f32>(3),
-                    args.parse_at::<f32>(4),
-                ) {
-                    kind = Some(InstructionKind::AdsrSettings { a, d, s, r });
-                }
-            },
-            other => return Err(format!("Unknown instruction '{}'", other))
+    /// Whether this instruction starts or stops a note, as opposed to only changing state
+    pub fn is_trigger(&self) -> bool {
+        matches!(self, InstructionKind::State { .. })
+    }
+
+    /// Whether both instructions are of the same kind, regardless of their values
+    /// Parameters count as different kinds per parameter name
+    pub fn same_kind(&self, other: &InstructionKind) -> bool {
+        match (self, other) {
+            (InstructionKind::Param { name: a, .. }, InstructionKind::Param { name: b, .. }) => a == b,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
         }
+    }
+
+    /// Parses an instruction from the front of `args`, leaving anything after it.
+    /// See [`registry::INSTRUCTIONS`]
+    pub fn parse_args(args: &mut Args) -> Result<Self, ParseError> {
+        registry::parse_spec(&registry::INSTRUCTIONS, args, "instruction")
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::On => write!(f, "on"),
+            Status::Off => write!(f, "off"),
+        }
+    }
+}
 
-        match kind {
-            Some(k) => Ok(k),
-            None => Err(format!("Bad arguments for instruction '{}'", s))
+/// Formats the instruction the way it is typed, so that parsing the output gives it back
+impl Display for InstructionKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstructionKind::Waveform(w) => {
+                let (name, _) = WAVEFORMS.iter().find(|(_, it)| it == w).unwrap();
+                write!(f, "form {}", name)
+            }
+            InstructionKind::Frequency(hz) => write!(f, "freq {}Hz", hz),
+            InstructionKind::Note(n) => write!(f, "note {}", note_name(*n)),
+            InstructionKind::State { status, velocity: MAX_VELOCITY } => write!(f, "state {}", status),
+            InstructionKind::State { status, velocity } => write!(f, "state {} {}", status, velocity),
+            InstructionKind::Vibrato(s) => write!(f, "vib {}", s),
+            InstructionKind::VibratoSettings { rate, depth } => write!(f, "vib opt {}Hz {}", rate, depth),
+            InstructionKind::AdsrSettings { a, d, s, r } => write!(f, "adsr {}s {}s {} {}s", a, d, s, r),
+            InstructionKind::DahdsrSettings { delay, a, hold, d, s, r } => {
+                write!(f, "dahdsr {}s {}s {}s {}s {} {}s", delay, a, hold, d, s, r)
+            }
+            InstructionKind::EnvelopeShape { attack, decay, release } => {
+                write!(f, "shape {} {} {}", shape_name(*attack), shape_name(*decay), shape_name(*release))
+            }
+            InstructionKind::Param { name, value } => write!(f, "param {} {}", name, value),
         }
     }
-}
\ No newline at end of file
+}
+
+/// A segment tension by its name, if it has one
+fn shape_name(tension: f32) -> String {
+    match SHAPES.iter().find(|(_, t)| *t == tension) {
+        Some((name, _)) => name.to_string(),
+        None => tension.to_string(),
+    }
+}
+
+/// How far MIDI note numbers are above the notes here. Note 60 is the A at 440Hz as songs have
+/// always had it, where MIDI has that A at 69, so middle C is note 51
+pub const MIDI_OFFSET: u16 = 9;
+
+/// Frequency of a note in equal temperament, with note 60 at 440Hz
+pub fn note_to_frequency(note: u16) -> f32 {
+    2f32.powf((note as f32 - 60.0) / 12.0) * 440.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::args::{Args, ParseError, Span};
+    use crate::instruction::{InstructionKind, Status};
+    use crate::instrument::oscillator::Waveform;
+
+    fn parse(s: &str) -> Result<InstructionKind, ParseError> {
+        let mut args = Args::new(s);
+        let kind = InstructionKind::parse_args(&mut args)?;
+        args.finish()?;
+        Ok(kind)
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let kinds = [
+            InstructionKind::Waveform(Waveform::Triangle),
+            InstructionKind::Frequency(261.62558),
+            InstructionKind::Note(61),
+            InstructionKind::Note(3),
+            InstructionKind::State { status: Status::Off, velocity: 127 },
+            InstructionKind::State { status: Status::On, velocity: 64 },
+            InstructionKind::Vibrato(Status::On),
+            InstructionKind::VibratoSettings { rate: 6.0, depth: 0.01 },
+            InstructionKind::AdsrSettings { a: 0.1, d: 0.5, s: 0.5, r: 0.3 },
+            InstructionKind::DahdsrSettings { delay: 0.0, a: 0.01, hold: 0.2, d: 0.5, s: 0.0, r: 0.3 },
+            InstructionKind::EnvelopeShape { attack: 0.0, decay: -0.7, release: 0.25 },
+            InstructionKind::Param { name: "attack", value: 0.25 },
+        ];
+        for kind in kinds {
+            assert_eq!(parse(&kind.to_string()), Ok(kind));
+        }
+    }
+
+    #[test]
+    fn parses_arguments_in_order() {
+        assert_eq!(parse("state off"), Ok(InstructionKind::State { status: Status::Off, velocity: 127 }));
+        assert_eq!(parse("state on 0"), Ok(InstructionKind::State { status: Status::On, velocity: 0 }));
+        assert_eq!(
+            parse("vib opt 5Hz 0.2"),
+            Ok(InstructionKind::VibratoSettings { rate: 5.0, depth: 0.2 })
+        );
+        assert_eq!(
+            parse("adsr 10ms 0.2 0.7 1s"),
+            Ok(InstructionKind::AdsrSettings { a: 0.01, d: 0.2, s: 0.7, r: 1.0 })
+        );
+        assert_eq!(parse("freq A-4"), Ok(InstructionKind::Frequency(440.0)));
+        assert_eq!(
+            parse("shape exp log -0.2"),
+            Ok(InstructionKind::EnvelopeShape { attack: 0.7, decay: -0.7, release: -0.2 })
+        );
+    }
+
+    #[test]
+    fn errors_point_at_bad_token() {
+        assert_eq!(parse("form squiggle").unwrap_err().span, Span { start: 5, end: 13 });
+        assert_eq!(parse("vib opt 5 x").unwrap_err().span, Span { start: 10, end: 11 });
+        assert_eq!(parse("state on off").unwrap_err().span, Span { start: 9, end: 12 });
+        assert_eq!(parse("state on 128").unwrap_err().span, Span { start: 9, end: 12 });
+        assert_eq!(parse("nope").unwrap_err().span, Span { start: 0, end: 4 });
+    }
+}
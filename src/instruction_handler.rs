@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use crate::instruction::InstructionKind;
 
@@ -9,7 +9,7 @@ struct InstructionHashWrapper {
 
 impl PartialEq for InstructionHashWrapper {
     fn eq(&self, other: &Self) -> bool {
-        std::mem::discriminant(&self.kind) == std::mem::discriminant(&other.kind)
+        self.kind.same_kind(&other.kind)
     }
 }
 
@@ -18,12 +18,32 @@ impl Eq for InstructionHashWrapper {}
 impl Hash for InstructionHashWrapper {
     fn hash<H: Hasher>(&self, state: &mut H) {
         std::mem::discriminant(&self.kind).hash(state);
+        if let InstructionKind::Param { name, .. } = self.kind {
+            name.hash(state);
+        }
+    }
+}
+
+/// Where an instruction goes among the others at its slot. Settings and notes come before the
+/// press or release that plays them, and parameters go by name
+fn slot_order(kind: &InstructionKind) -> (u8, &'static str) {
+    match kind {
+        InstructionKind::Waveform(_) => (0, ""),
+        InstructionKind::VibratoSettings { .. } => (1, ""),
+        InstructionKind::AdsrSettings { .. } => (2, ""),
+        InstructionKind::DahdsrSettings { .. } => (3, ""),
+        InstructionKind::EnvelopeShape { .. } => (4, ""),
+        InstructionKind::Param { name, .. } => (5, name),
+        InstructionKind::Vibrato(_) => (6, ""),
+        InstructionKind::Frequency(_) => (7, ""),
+        InstructionKind::Note(_) => (8, ""),
+        InstructionKind::State { .. } => (9, ""),
     }
 }
 
 pub struct InstructionHandler {
     time_target_to_inst: HashMap<(u128, u128), HashSet<InstructionHashWrapper>>,
-    target_to_times: HashMap<u128, HashMap<u128, u128>>,
+    target_to_times: HashMap<u128, BTreeMap<u128, u128>>,
     time_to_targets: HashMap<u128, HashMap<u128, u128>>
 }
 
@@ -39,23 +59,23 @@ impl InstructionHandler {
     }
     pub fn insert(&mut self, target: u128, time: u128, kind: InstructionKind) {
         let wrapper = InstructionHashWrapper { kind };
-        self.time_target_to_inst.entry((target, time)).and_modify(|set| {
-            if set.contains(&wrapper) { set.remove(&wrapper); }
-            set.insert(wrapper);
-        }).or_insert(HashSet::from([wrapper]));
-        self.target_to_times.entry(target).and_modify(|map| {
-            map.entry(time).and_modify(|u|{ *u += 1 });
-        }).or_insert(HashMap::from([(time, 1)]));
-        self.target_to_times.entry(time).and_modify(|map| {
-            map.entry(target).and_modify(|u|{ *u += 1 });
-        }).or_insert(HashMap::from([(target, 1)]));
+        let set = self.time_target_to_inst.entry((target, time)).or_default();
+        // Only one instruction of each kind per slot, the newest one wins
+        if set.replace(wrapper).is_some() {
+            return;
+        }
+        *self.target_to_times.entry(target).or_default().entry(time).or_insert(0) += 1;
+        *self.time_to_targets.entry(time).or_default().entry(target).or_insert(0) += 1;
     }
 
+    /// Every instruction at the slot, in the order they're played, see [`slot_order`]
     pub fn get(&self, target: u128, time: u128) -> Vec<InstructionKind> {
-        self.time_target_to_inst.get(&(target, time))
+        let mut kinds = self.time_target_to_inst.get(&(target, time))
             .unwrap_or(&HashSet::new())
             .iter()
-            .fold(vec![], |mut acc,x| {acc.push(x.kind); acc})
+            .fold(vec![], |mut acc,x| {acc.push(x.kind); acc});
+        kinds.sort_by_key(slot_order);
+        kinds
     }
 
     pub fn has_type(&self, target: u128, time: u128, kind: InstructionKind) -> bool {
@@ -77,38 +97,116 @@ impl InstructionHandler {
         }
     }
 
+    /// Returns the instruction of the same kind as `kind` at the slot, if any
+    pub fn get_type(&self, target: u128, time: u128, kind: InstructionKind) -> Option<InstructionKind> {
+        self.time_target_to_inst.get(&(target, time))?
+            .get(&InstructionHashWrapper { kind })
+            .map(|it| it.kind)
+    }
+
+    /// Returns every instruction for `target` with a time in `start..end`, ordered by time.
+    /// Empty when `end` isn't after `start`
+    pub fn range(&self, target: u128, start: u128, end: u128) -> Vec<(u128, InstructionKind)> {
+        let Some(times) = self.target_to_times.get(&target).filter(|_| start < end) else {
+            return vec![];
+        };
+        times.range(start..end)
+            .flat_map(|(time, _)| self.get(target, *time).into_iter().map(|kind| (*time, kind)))
+            .collect()
+    }
+
+    /// Removes and returns every instruction for `target`, ordered by time
+    pub fn take_target(&mut self, target: u128) -> Vec<(u128, InstructionKind)> {
+        let times = self.target_to_times.get(&target).map(|it| it.keys().copied().collect::<Vec<_>>()).unwrap_or_default();
+        let mut taken = vec![];
+        for time in times {
+            for kind in self.get(target, time) {
+                self.remove_type(target, time, kind);
+                taken.push((time, kind));
+            }
+        }
+        taken
+    }
+
+    /// Moves the instructions of every target from `from` and up one step up or down,
+    /// keeping them in line with an instrument being inserted or removed
+    pub fn shift_targets(&mut self, from: u128, up: bool) {
+        let mut targets = self.target_to_times.keys().copied().filter(|t| *t >= from).collect::<Vec<_>>();
+        // Move the targets furthest in the shifting direction first, so nothing gets overwritten
+        targets.sort();
+        if up {
+            targets.reverse();
+        }
+        for target in targets {
+            let new_target = if up { target + 1 } else { target - 1 };
+            for (time, kind) in self.take_target(target) {
+                self.insert(new_target, time, kind);
+            }
+        }
+    }
+
+    /// Collects the state an instrument would be in at `time` if it had played from the start.
+    ///
+    /// Returns the most recent instruction of each kind strictly before `time`, in the order
+    /// they were played, by time and then as [`InstructionHandler::get`] orders a slot.
+    /// Instructions that trigger notes are left out.
+    pub fn chase(&self, target: u128, time: u128) -> Vec<InstructionKind> {
+        let Some(times) = self.target_to_times.get(&target) else {
+            return vec![];
+        };
+        let mut seen = HashSet::new();
+        let mut chased = vec![];
+        for (t, _) in times.range(..time).rev() {
+            for kind in self.get(target, *t).into_iter().rev() {
+                if kind.is_trigger() {
+                    continue;
+                }
+                if seen.insert(InstructionHashWrapper { kind }) {
+                    chased.push(kind);
+                }
+            }
+        }
+        chased.reverse();
+        chased
+    }
+
     pub fn remove_type(&mut self, target: u128, time: u128, kind: InstructionKind) {
         let wrapper = InstructionHashWrapper { kind };
-        self.time_target_to_inst.entry((target, time)).and_modify(|set|{
-            set.remove(&wrapper);
-        });
-        self.target_to_times.entry(target).and_modify(|map|{
-            let mut del = false;
-            map.entry(time).and_modify(|n|{
+        let removed = match self.time_target_to_inst.get_mut(&(target, time)) {
+            Some(set) => {
+                let removed = set.remove(&wrapper);
+                if set.is_empty() {
+                    self.time_target_to_inst.remove(&(target, time));
+                }
+                removed
+            }
+            None => false
+        };
+        if !removed {
+            return;
+        }
+        if let Some(map) = self.target_to_times.get_mut(&target) {
+            if let Some(n) = map.get_mut(&time) {
                 *n -= 1;
                 if *n == 0 {
-                    del = true;
+                    map.remove(&time);
                 }
-            });
-            map.remove(&time);
-        });
-        self.time_to_targets.entry(time).and_modify(|map|{
-            let mut del = false;
-            map.entry(target).and_modify(|n|{
+            }
+        }
+        if let Some(map) = self.time_to_targets.get_mut(&time) {
+            if let Some(n) = map.get_mut(&target) {
                 *n -= 1;
                 if *n == 0 {
-                    del = true;
+                    map.remove(&target);
                 }
-            });
-            map.remove(&target);
-        });
-
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::instruction::InstructionKind;
+    use crate::instruction::{InstructionKind, Status};
     use crate::instruction_handler::{InstructionHandler, InstructionHashWrapper};
     use crate::instrument::oscillator::Waveform;
 
@@ -118,9 +216,14 @@ mod tests {
         let y = InstructionHashWrapper { kind: InstructionKind::Waveform(Waveform::Sine) };
         let z = InstructionHashWrapper { kind: InstructionKind::Frequency(3.0) };
         let w = InstructionHashWrapper { kind: InstructionKind::Frequency(4.0) };
+        let a = InstructionHashWrapper { kind: InstructionKind::Param { name: "attack", value: 1.0 } };
+        let b = InstructionHashWrapper { kind: InstructionKind::Param { name: "attack", value: 2.0 } };
+        let c = InstructionHashWrapper { kind: InstructionKind::Param { name: "decay", value: 1.0 } };
         assert_eq!(x,y);
         assert_eq!(z,w);
         assert_ne!(x,z);
+        assert_eq!(a,b);
+        assert_ne!(a,c);
     }
 
     #[test]
@@ -141,5 +244,48 @@ mod tests {
         assert!(handler.has(0,1, InstructionKind::Note(4)));
         assert!(!handler.has(0,1, InstructionKind::Note(3)));
         assert!(handler.has_type(0,1, InstructionKind::Note(3)));
+        assert_eq!(handler.range(0, 0, 2).len(), 3);
+        assert_eq!(handler.range(0, 2, 0), vec![]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn chase_keeps_latest_of_each_kind() {
+        let mut handler = InstructionHandler::new();
+        handler.insert(0, 0, InstructionKind::Waveform(Waveform::Square));
+        handler.insert(0, 5, InstructionKind::Frequency(3.0));
+        handler.insert(0, 5, InstructionKind::State { status: Status::On, velocity: 100 });
+        handler.insert(0, 10, InstructionKind::Waveform(Waveform::Saw));
+        handler.insert(0, 20, InstructionKind::Frequency(4.0));
+        handler.insert(1, 1, InstructionKind::Frequency(5.0));
+
+        assert_eq!(handler.chase(0, 0), vec![]);
+        assert_eq!(handler.chase(0, 10), vec![
+            InstructionKind::Waveform(Waveform::Square),
+            InstructionKind::Frequency(3.0),
+        ]);
+        assert_eq!(handler.chase(0, 21), vec![
+            InstructionKind::Waveform(Waveform::Saw),
+            InstructionKind::Frequency(4.0),
+        ]);
+
+        handler.remove_type(0, 20, InstructionKind::Frequency(0.0));
+        assert_eq!(handler.chase(0, 21), vec![
+            InstructionKind::Frequency(3.0),
+            InstructionKind::Waveform(Waveform::Saw),
+        ]);
+
+        // Within a slot, settings come before the note and the press playing it
+        let kinds = [
+            InstructionKind::State { status: Status::On, velocity: 100 },
+            InstructionKind::Note(60),
+            InstructionKind::Param { name: "volume", value: 0.5 },
+            InstructionKind::Param { name: "attack", value: 0.1 },
+            InstructionKind::Waveform(Waveform::Square),
+        ];
+        for kind in kinds {
+            handler.insert(0, 30, kind);
+        }
+        assert_eq!(handler.get(0, 30), vec![kinds[4], kinds[3], kinds[2], kinds[1], kinds[0]]);
+        assert_eq!(handler.chase(0, 31), vec![InstructionKind::Frequency(3.0), kinds[4], kinds[3], kinds[2], kinds[1]]);
+    }
+}
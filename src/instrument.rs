@@ -1,14 +1,75 @@
 use crate::instruction::InstructionKind;
+use crate::instrument::oscillator::Oscillator;
+use crate::instrument::param::ParamDescriptor;
+use crate::instrument::sampler::Sample;
+use crate::instrument::synth::Synth;
+use std::sync::Arc;
 
-mod adsr;
+pub mod adsr;
+mod filter;
 pub mod oscillator;
+pub mod param;
+pub mod sampler;
+pub mod smoothed;
 pub mod synth;
 mod vibrato;
 
+/// Names accepted by [`from_name`]
+pub const NAMES: [&str; 2] = ["synth", "osc"];
+
+/// Creates a default instrument from its short name, as used in commands
+pub fn from_name(name: &str) -> Option<Box<dyn Instrument>> {
+    match name {
+        "synth" => Some(Box::new(Synth::new())),
+        "osc" => Some(Box::new(Oscillator::default())),
+        _ => None
+    }
+}
+
+/// Parameters of every kind of instrument
+fn all_params() -> impl Iterator<Item = &'static ParamDescriptor> {
+    synth::PARAMS.iter().chain(oscillator::PARAMS.iter()).chain(sampler::PARAMS.iter())
+}
+
+/// Names of the parameters of every kind of instrument, sorted and without duplicates
+pub fn param_names() -> Vec<&'static str> {
+    let mut names = all_params().map(|p| p.name).collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
 pub trait Instrument: Send {
+    /// Short name of the kind of instrument, as accepted by [`from_name`] for kinds that need
+    /// nothing more to be made
+    fn name(&self) -> &'static str;
+
     fn tick(&mut self) -> (f32, f32);
 
     fn set_sample_rate(&mut self, sample_rate: f32);
 
     fn apply_instruction(&mut self, instruction: InstructionKind) -> Result<(), &'static str>;
+
+    fn params(&self) -> &'static [ParamDescriptor];
+
+    fn get_param(&self, name: &str) -> Option<f32>;
+
+    /// Sets a parameter, clamping the value to its range
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), &'static str>;
+
+    /// Instructions that would bring a fresh instrument to the current settings of this one
+    fn settings(&self) -> Vec<InstructionKind> {
+        self.params()
+            .iter()
+            .filter_map(|p| Some(InstructionKind::Param { name: p.name, value: self.get_param(p.name)? }))
+            .collect()
+    }
+
+    /// Silences the instrument immediately, without a release
+    fn stop(&mut self);
+
+    /// The sample a sampler plays, for saving it with the song
+    fn sample(&self) -> Option<Arc<Sample>> {
+        None
+    }
 }
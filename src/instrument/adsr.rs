@@ -1,17 +1,42 @@
+/// Named segment shapes, as tensions
+pub const SHAPES: [(&str, f32); 3] = [("lin", 0.0), ("exp", 0.7), ("log", -0.7)];
+
+/// Bends progress `x` through a segment, both from 0 to 1. Positive tensions start slowly and
+/// speed up, like an exponential, negative ones start fast and slow down, like a logarithm
+fn shape(x: f32, tension: f32) -> f32 {
+    if tension.abs() < 0.001 {
+        return x;
+    }
+    let k = tension * 6.0;
+    ((k * x).exp() - 1.0) / (k.exp() - 1.0)
+}
+
 enum AdsrState {
     Pressed,
     Released,
     Off,
 }
 
+/// Envelope with delay, attack, hold, decay, sustain and release stages. Delay and hold are
+/// skipped when zero, which makes it a plain ADSR
 pub struct Adsr {
     state: AdsrState,
     sample_rate: f32,
     frame: u128,
+    delay: f32,
     attack: f32,
+    hold: f32,
     decay: f32,
     sustain: f32,
     release: f32,
+    /// Tensions of the attack, decay and release segments, from -1 to 1
+    curves: (f32, f32, f32),
+    /// Output of the last tick. Attacks and releases start from it, so they never jump
+    level: f32,
+    /// Level the current attack or release started from
+    start_level: f32,
+    /// Pressing while already pressed keeps the envelope going instead of restarting it
+    legato: bool,
 }
 
 impl Adsr {
@@ -21,59 +46,201 @@ impl Adsr {
             sample_rate: 0.0,
             // Used for frames since pressed, AND frames since released
             frame: 0,
+            delay: 0.0,
             attack: a.max(0.0),
+            hold: 0.0,
             decay: d.max(0.0),
             sustain: s.clamp(0.0, 1.0),
             release: r.max(0.0),
+            curves: (0.0, 0.0, 0.0),
+            level: 0.0,
+            start_level: 0.0,
+            legato: false,
         }
     }
 
+    /// Changes the envelope shape without interrupting a playing note
+    pub fn set_parameters(&mut self, a: f32, d: f32, s: f32, r: f32) {
+        self.attack = a.max(0.0);
+        self.decay = d.max(0.0);
+        self.sustain = s.clamp(0.0, 1.0);
+        self.release = r.max(0.0);
+    }
+
+    pub fn get_parameters(&self) -> (f32, f32, f32, f32) {
+        (self.attack, self.decay, self.sustain, self.release)
+    }
+
+    pub fn set_stages(&mut self, delay: f32, hold: f32) {
+        self.delay = delay.max(0.0);
+        self.hold = hold.max(0.0);
+    }
+
+    pub fn get_stages(&self) -> (f32, f32) {
+        (self.delay, self.hold)
+    }
+
+    pub fn set_curves(&mut self, attack: f32, decay: f32, release: f32) {
+        self.curves = (attack.clamp(-1.0, 1.0), decay.clamp(-1.0, 1.0), release.clamp(-1.0, 1.0));
+    }
+
+    pub fn get_curves(&self) -> (f32, f32, f32) {
+        self.curves
+    }
+
+    pub fn is_legato(&self) -> bool {
+        self.legato
+    }
+
+    pub fn set_legato(&mut self, legato: bool) {
+        self.legato = legato
+    }
+
     pub fn set_sample_rate(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate
     }
 
     pub fn press(&mut self) {
+        if self.legato && matches!(self.state, AdsrState::Pressed) {
+            return;
+        }
         self.state = AdsrState::Pressed;
         self.frame = 0;
+        self.start_level = self.level;
     }
 
     pub fn release(&mut self) {
+        if matches!(self.state, AdsrState::Off) {
+            return;
+        }
         self.state = AdsrState::Released;
         self.frame = 0;
+        self.start_level = self.level;
     }
 
     pub fn stop(&mut self) {
         self.state = AdsrState::Off;
         self.frame = 0;
+        self.level = 0.0;
     }
 
     pub fn tick(&mut self) -> f32 {
         let cur_frame = self.frame as f32;
-        if self.frame != u128::MAX {
-            self.frame += 1;
-        }
-        match self.state {
+        self.frame = self.frame.saturating_add(1);
+        let level = match self.state {
             AdsrState::Off => 0.0,
             AdsrState::Pressed => {
-                let attack_frames = self.attack * self.sample_rate;
-                let decay_frames = self.decay * self.sample_rate;
-                if cur_frame < attack_frames {
-                    // Attack
-                    cur_frame / attack_frames
-                } else if cur_frame < attack_frames + decay_frames {
+                let (attack_curve, decay_curve, _) = self.curves;
+                let delay_end = self.delay * self.sample_rate;
+                let attack_end = delay_end + self.attack * self.sample_rate;
+                let hold_end = attack_end + self.hold * self.sample_rate;
+                let decay_end = hold_end + self.decay * self.sample_rate;
+                if cur_frame < delay_end {
+                    // Delay, staying where the envelope was
+                    self.start_level
+                } else if cur_frame < attack_end {
+                    // Attack, from wherever the envelope was
+                    let x = (cur_frame - delay_end) / (attack_end - delay_end);
+                    self.start_level + (1.0 - self.start_level) * shape(x, attack_curve)
+                } else if cur_frame < hold_end {
+                    // Hold
+                    1.0
+                } else if cur_frame < decay_end {
                     // Decay
-                    (1.0 - ((cur_frame - attack_frames) / decay_frames)).clamp(self.sustain, 1.0)
+                    let x = (cur_frame - hold_end) / (decay_end - hold_end);
+                    1.0 - (1.0 - self.sustain) * shape(x, decay_curve)
                 } else {
                     // Sustain
                     self.sustain
                 }
             }
             AdsrState::Released => {
-                // Release
+                // Release, from wherever the envelope was
                 let release_frames = self.release * self.sample_rate;
-                (1.0 - (cur_frame / release_frames)) * self.sustain
+                if cur_frame < release_frames {
+                    self.start_level * (1.0 - shape(cur_frame / release_frames, self.curves.2))
+                } else {
+                    self.state = AdsrState::Off;
+                    0.0
+                }
             }
         }
-        .clamp(0.0, 1.0)
+        .clamp(0.0, 1.0);
+        self.level = level;
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instrument::adsr::{shape, Adsr};
+
+    fn envelope() -> Adsr {
+        let mut adsr = Adsr::new(1.0, 1.0, 0.5, 1.0);
+        adsr.set_sample_rate(10.0);
+        adsr
+    }
+
+    #[test]
+    fn releases_from_the_current_level() {
+        let mut adsr = envelope();
+        adsr.press();
+        for _ in 0..3 {
+            adsr.tick();
+        }
+        // Released during the attack, at 0.2
+        adsr.release();
+        assert_eq!(adsr.tick(), 0.2);
+        assert!(adsr.tick() < 0.2);
+        for _ in 0..10 {
+            adsr.tick();
+        }
+        assert_eq!(adsr.tick(), 0.0);
+    }
+
+    #[test]
+    fn retriggers_from_the_current_level() {
+        let mut adsr = envelope();
+        adsr.press();
+        for _ in 0..16 {
+            adsr.tick();
+        }
+        // Retriggered during the decay, at 0.75
+        adsr.press();
+        assert_eq!(adsr.tick(), 0.75);
+        assert!(adsr.tick() > 0.75);
+    }
+
+    #[test]
+    fn legato_keeps_the_envelope_going() {
+        let mut adsr = envelope();
+        adsr.set_legato(true);
+        adsr.press();
+        for _ in 0..25 {
+            adsr.tick();
+        }
+        adsr.press();
+        assert_eq!(adsr.tick(), 0.5);
+    }
+
+    #[test]
+    fn delays_and_holds() {
+        let mut adsr = envelope();
+        adsr.set_stages(0.5, 0.5);
+        adsr.press();
+        let levels = (0..22).map(|_| adsr.tick()).collect::<Vec<_>>();
+        assert_eq!(levels[4], 0.0);
+        assert_eq!(levels[10], 0.5);
+        assert_eq!(levels[16], 1.0);
+        assert_eq!(levels[21], 0.95);
+    }
+
+    #[test]
+    fn shapes_bend_segments() {
+        assert_eq!(shape(0.5, 0.0), 0.5);
+        assert!(shape(0.5, 0.7) < 0.5);
+        assert!(shape(0.5, -0.7) > 0.5);
+        assert!((shape(1.0, 1.0) - 1.0).abs() < 1e-6);
+        assert_eq!(shape(0.0, -1.0), 0.0);
     }
 }
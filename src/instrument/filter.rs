@@ -0,0 +1,68 @@
+/// Resonant low-pass filter, a state variable filter that stays stable while its cutoff moves
+pub struct LowPass {
+    sample_rate: f32,
+    // State of the two integrators
+    ic1: f32,
+    ic2: f32,
+}
+
+impl LowPass {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: 0.0,
+            ic1: 0.0,
+            ic2: 0.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn reset(&mut self) {
+        self.ic1 = 0.0;
+        self.ic2 = 0.0;
+    }
+
+    /// Filters one sample. `resonance` goes from 0 to 1, just short of self-oscillation
+    pub fn tick(&mut self, input: f32, cutoff: f32, resonance: f32) -> f32 {
+        // Cutoffs past what the sample rate can represent let everything through
+        if self.sample_rate <= 0.0 || cutoff >= self.sample_rate * 0.49 {
+            return input;
+        }
+        let g = (std::f32::consts::PI * cutoff.max(1.0) / self.sample_rate).tan();
+        let k = 2.0 - 1.95 * resonance.clamp(0.0, 1.0);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        let v3 = input - self.ic2;
+        let v1 = a1 * self.ic1 + a2 * v3;
+        let v2 = self.ic2 + a2 * self.ic1 + a3 * v3;
+        self.ic1 = 2.0 * v1 - self.ic1;
+        self.ic2 = 2.0 * v2 - self.ic2;
+        v2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instrument::filter::LowPass;
+
+    /// Peak level of a filtered sine, once the filter has settled
+    fn peak(frequency: f32, cutoff: f32) -> f32 {
+        let mut filter = LowPass::new();
+        filter.set_sample_rate(48000.0);
+        (0..48000)
+            .map(|i| (i as f32 * frequency * 2.0 * std::f32::consts::PI / 48000.0).sin())
+            .map(|x| filter.tick(x, cutoff, 0.0))
+            .skip(24000)
+            .fold(0.0, |acc: f32, x| acc.max(x.abs()))
+    }
+
+    #[test]
+    fn passes_lows_and_cuts_highs() {
+        assert!(peak(100.0, 1000.0) > 0.95);
+        assert!(peak(10000.0, 1000.0) < 0.02);
+        assert_eq!(peak(10000.0, 30000.0), 1.0);
+    }
+}
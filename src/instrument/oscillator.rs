@@ -1,5 +1,7 @@
-use crate::instruction::{InstructionKind, Status};
-use crate::instrument::Instrument;
+use crate::instruction::{note_to_frequency, InstructionKind, Status, MAX_VELOCITY};
+use crate::instrument::param::{ParamDescriptor, Unit};
+use crate::instrument::smoothed::{Smoothed, DEFAULT_RAMP_TIME};
+use crate::instrument::{param, Instrument};
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Waveform {
@@ -9,13 +11,46 @@ pub enum Waveform {
     Triangle,
 }
 
+/// Waveforms by the names used in commands
+pub const WAVEFORMS: [(&str, Waveform); 4] = [
+    ("sine", Waveform::Sine),
+    ("square", Waveform::Square),
+    ("saw", Waveform::Saw),
+    ("tri", Waveform::Triangle),
+];
+
+const WAVEFORM_LABELS: [&str; 4] = [WAVEFORMS[0].0, WAVEFORMS[1].0, WAVEFORMS[2].0, WAVEFORMS[3].0];
+
+/// The waveform as the value of a stepped parameter
+pub fn waveform_to_param(waveform: Waveform) -> f32 {
+    WAVEFORMS.iter().position(|(_, w)| *w == waveform).unwrap_or(0) as f32
+}
+
+pub fn waveform_from_param(value: f32) -> Waveform {
+    WAVEFORMS[(value.round() as usize).min(WAVEFORMS.len() - 1)].1
+}
+
+pub static PARAMS: [ParamDescriptor; 5] = [
+    ParamDescriptor::stepped("waveform", "Shape of the wave", 0.0, &WAVEFORM_LABELS),
+    ParamDescriptor::new("freq", "Frequency of the wave", 0.0, 20000.0, 220.0, Unit::Hz),
+    ParamDescriptor::new("duty", "Share of each cycle the square wave is high, its pulse width", 0.01, 0.99, 0.5, Unit::None),
+    ParamDescriptor::new("vel_amp", "How much softer notes are quieter, 0 ignoring velocity", 0.0, 1.0, 1.0, Unit::None),
+    ParamDescriptor::new("smoothing", "Time changes to parameters take, to avoid clicks", 0.0, 1.0, DEFAULT_RAMP_TIME, Unit::Seconds),
+];
+
 pub struct Oscillator {
     pub sample_rate: f32,
     pub waveform: Waveform,
-    pub current_sample_index: f32,
+    /// Position in the current cycle, from 0 to 1
+    phase: f32,
+    /// Multiplies the frequency, for vibrato
     pub current_sample_jump: f32,
-    pub frequency_hz: f32,
-    pub is_on: bool,
+    frequency: Smoothed,
+    /// Pulse width of the square wave, from 0 to 1
+    duty: f32,
+    // Fades the output in and out as the oscillator is turned on and off
+    gate: Smoothed,
+    vel_amp: f32,
 }
 
 impl Oscillator {
@@ -23,35 +58,54 @@ impl Oscillator {
         Self {
             sample_rate: 0.0,
             waveform: Waveform::Sine,
-            current_sample_index: 0.0,
+            phase: 0.0,
             current_sample_jump: 1.0,
-            frequency_hz: 220.0,
-            is_on: false,
+            frequency: Smoothed::new(220.0),
+            duty: 0.5,
+            gate: Smoothed::new(0.0),
+            vel_amp: 1.0,
         }
     }
 
-    fn advance_sample(&mut self) {
-        self.current_sample_index = (self.current_sample_index + self.current_sample_jump) % (self.sample_rate / self.frequency_hz)
-        // self.current_sample_index = (self.current_sample_index + self.current_sample_jump) % (two_pi * self.frequency_hz);
-        // self.current_sample_index += 1.0;
-        // self.current_sample_index += self.current_sample_jump;
+    pub fn frequency(&self) -> f32 {
+        self.frequency.target()
+    }
+
+    pub fn set_frequency(&mut self, hz: f32) {
+        self.frequency.set(hz)
     }
 
-    pub fn set_waveform(&mut self, waveform: Waveform) {
-        self.waveform = waveform;
+    pub fn set_on(&mut self, is_on: bool) {
+        self.gate.set(if is_on { 1.0 } else { 0.0 });
     }
 
-    fn calculate_sine_output_from_freq(&self, freq: f32) -> f32 {
+    pub fn ramp_time(&self) -> f32 {
+        self.frequency.ramp_time()
+    }
+
+    pub fn set_ramp_time(&mut self, seconds: f32) {
+        self.frequency.set_ramp_time(seconds);
+        self.gate.set_ramp_time(seconds);
+    }
+
+    fn advance_sample(&mut self) {
+        let frequency = self.frequency.tick();
+        if self.sample_rate > 0.0 {
+            self.phase = (self.phase + frequency * self.current_sample_jump / self.sample_rate).fract();
+        }
+    }
+
+    fn calculate_sine_output_from_harmonic(&self, harmonic: f32) -> f32 {
         let two_pi = 2.0 * std::f32::consts::PI;
-        (self.current_sample_index * freq * two_pi / self.sample_rate).sin()
+        (self.phase * harmonic * two_pi).sin()
     }
 
     fn is_multiple_of_freq_above_nyquist(&self, multiple: f32) -> bool {
-        self.frequency_hz * multiple > self.sample_rate / 2.0
+        self.frequency.value() * multiple > self.sample_rate / 2.0
     }
 
     fn sine_wave(&mut self) -> f32 {
-        self.calculate_sine_output_from_freq(self.frequency_hz)
+        self.calculate_sine_output_from_harmonic(1.0)
     }
 
     fn generative_waveform(
@@ -63,16 +117,24 @@ impl Oscillator {
         let mut i = 1;
         while !self.is_multiple_of_freq_above_nyquist(i as f32) {
             let gain = 1.0 / (i as f32).powf(gain_exponent);
-            output += gain
-                * self
-                    .calculate_sine_output_from_freq(self.frequency_hz * i as f32);
+            output += gain * self.calculate_sine_output_from_harmonic(i as f32);
             i += harmonic_index_increment;
         }
         output
     }
 
+    /// A pulse as wide as the duty, without the offset narrow pulses have. At half its cycle
+    /// only odd harmonics are left, as in a plain square
     fn square_wave(&mut self) -> f32 {
-        self.generative_waveform(2, 1.0)
+        let pi = std::f32::consts::PI;
+        let mut output = 0.0;
+        let mut i = 1;
+        while !self.is_multiple_of_freq_above_nyquist(i as f32) {
+            let harmonic = i as f32;
+            output += (harmonic * pi * self.duty).sin() / harmonic * (2.0 * pi * harmonic * (self.phase - self.duty / 2.0)).cos();
+            i += 1;
+        }
+        output
     }
 
     fn saw_wave(&mut self) -> f32 {
@@ -85,39 +147,83 @@ impl Oscillator {
 
     pub fn tick(&mut self) -> f32 {
         self.advance_sample();
-        if !self.is_on {
+        let gate = self.gate.tick();
+        if gate == 0.0 {
             return 0.0;
         }
-        match self.waveform {
+        let output = match self.waveform {
             Waveform::Sine => self.sine_wave(),
             Waveform::Square => self.square_wave(),
             Waveform::Saw => self.saw_wave(),
             Waveform::Triangle => self.triangle_wave(),
-        }
+        };
+        output * gate
     }
 }
 
 impl Instrument for Oscillator {
+    fn name(&self) -> &'static str {
+        "osc"
+    }
+
     fn tick(&mut self) -> (f32, f32) {
         let x = self.tick();
         (x,x)
     }
 
     fn set_sample_rate(&mut self, sample_rate: f32) {
-        self.sample_rate = sample_rate
+        self.sample_rate = sample_rate;
+        self.frequency.set_sample_rate(sample_rate);
+        self.gate.set_sample_rate(sample_rate);
     }
 
     fn apply_instruction(&mut self, instruction: InstructionKind) -> Result<(), &'static str> {
         match instruction {
             InstructionKind::Waveform(w) => self.waveform = w,
-            InstructionKind::Frequency(f) => self.frequency_hz = f,
-            InstructionKind::Note(u) => self.frequency_hz = 2f32.powf((u as f32 - 60.0) / 12.0) * 440.0,
-            InstructionKind::State(s) => match s {
-                Status::On => self.is_on = true,
-                Status::Off => self.is_on = false
+            InstructionKind::Frequency(f) => self.set_frequency(f),
+            InstructionKind::Note(u) => self.set_frequency(note_to_frequency(u)),
+            InstructionKind::State { status, velocity } => match status {
+                Status::On => {
+                    let velocity = velocity as f32 / MAX_VELOCITY as f32;
+                    self.gate.set(1.0 - self.vel_amp + self.vel_amp * velocity);
+                }
+                Status::Off => self.set_on(false)
             },
+            InstructionKind::Param { name, value } => return self.set_param(name, value),
             _ => return Err("Illegal instruction for 'Oscillator'")
         }
         Ok(())
     }
+
+    fn params(&self) -> &'static [ParamDescriptor] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        match name {
+            "waveform" => Some(waveform_to_param(self.waveform)),
+            "freq" => Some(self.frequency()),
+            "duty" => Some(self.duty),
+            "vel_amp" => Some(self.vel_amp),
+            "smoothing" => Some(self.ramp_time()),
+            _ => None
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), &'static str> {
+        let value = param::find(&PARAMS, name).ok_or("No such parameter for 'Oscillator'")?.clamp(value);
+        match name {
+            "waveform" => self.waveform = waveform_from_param(value),
+            "freq" => self.set_frequency(value),
+            "duty" => self.duty = value,
+            "vel_amp" => self.vel_amp = value,
+            "smoothing" => self.set_ramp_time(value),
+            _ => unreachable!("parameter without a descriptor"),
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.gate.jump(0.0);
+    }
 }
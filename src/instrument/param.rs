@@ -0,0 +1,88 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Unit {
+    None,
+    Seconds,
+    Hz,
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unit::None => Ok(()),
+            Unit::Seconds => write!(f, "s"),
+            Unit::Hz => write!(f, "Hz"),
+        }
+    }
+}
+
+/// Describes one knob of an instrument, so it can be shown, set and automated generically
+#[derive(Debug)]
+pub struct ParamDescriptor {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub unit: Unit,
+    /// Names of the values of a stepped parameter, indexed from `min`. Empty for continuous ones
+    pub labels: &'static [&'static str],
+}
+
+impl ParamDescriptor {
+    pub const fn new(name: &'static str, summary: &'static str, min: f32, max: f32, default: f32, unit: Unit) -> Self {
+        Self { name, summary, min, max, default, unit, labels: &[] }
+    }
+
+    pub const fn stepped(name: &'static str, summary: &'static str, default: f32, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            summary,
+            min: 0.0,
+            max: (labels.len() - 1) as f32,
+            default,
+            unit: Unit::None,
+            labels,
+        }
+    }
+
+    /// Brings a value into range, rounding it for stepped parameters
+    pub fn clamp(&self, value: f32) -> f32 {
+        let value = value.clamp(self.min, self.max);
+        match self.labels.is_empty() {
+            true => value,
+            false => value.round(),
+        }
+    }
+
+    /// Formats a value with its unit, or its label for stepped parameters
+    pub fn format(&self, value: f32) -> String {
+        match self.labels.get(self.clamp(value) as usize) {
+            Some(label) if !self.labels.is_empty() => label.to_string(),
+            _ => format!("{}{}", value, self.unit),
+        }
+    }
+}
+
+/// Finds a descriptor by name
+pub fn find(params: &'static [ParamDescriptor], name: &str) -> Option<&'static ParamDescriptor> {
+    params.iter().find(|p| p.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instrument::param::{ParamDescriptor, Unit};
+
+    #[test]
+    fn clamping_and_formatting() {
+        let time = ParamDescriptor::new("attack", "", 0.0, 10.0, 0.1, Unit::Seconds);
+        assert_eq!(time.clamp(-1.0), 0.0);
+        assert_eq!(time.format(0.5), "0.5s");
+
+        let stepped = ParamDescriptor::stepped("vib", "", 0.0, &["off", "on"]);
+        assert_eq!(stepped.clamp(0.7), 1.0);
+        assert_eq!(stepped.format(1.0), "on");
+        assert_eq!(stepped.format(5.0), "on");
+    }
+}
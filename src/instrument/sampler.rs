@@ -0,0 +1,179 @@
+use crate::instruction::{note_to_frequency, InstructionKind, Status, MAX_VELOCITY};
+use crate::instrument::param::{ParamDescriptor, Unit};
+use crate::instrument::smoothed::{Smoothed, DEFAULT_RAMP_TIME};
+use crate::instrument::{param, Instrument};
+use std::sync::Arc;
+
+/// Note a sample plays at its own rate
+pub const ROOT_NOTE: u16 = 60;
+
+pub static PARAMS: [ParamDescriptor; 6] = [
+    ParamDescriptor::new("volume", "Output level", 0.0, 1.0, 1.0, Unit::None),
+    ParamDescriptor::new("pan", "Position from left (-1) to right (1)", -1.0, 1.0, 0.0, Unit::None),
+    ParamDescriptor::new("pitch", "Offset from the frequency, in semitones", -48.0, 48.0, 0.0, Unit::None),
+    ParamDescriptor::new("start", "Where in the sample notes start, as a fraction of its length", 0.0, 1.0, 0.0, Unit::None),
+    ParamDescriptor::new("vel_amp", "How much softer notes are quieter, 0 ignoring velocity", 0.0, 1.0, 1.0, Unit::None),
+    ParamDescriptor::new("smoothing", "Time changes to parameters take, to avoid clicks", 0.0, 1.0, DEFAULT_RAMP_TIME, Unit::Seconds),
+];
+
+/// A recorded sound, mono from -1 to 1
+#[derive(Debug)]
+pub struct Sample {
+    pub data: Vec<f32>,
+    /// Rate the sample is played at for [`ROOT_NOTE`]
+    pub rate: f32,
+    /// Start and end of the part repeated for as long as the note is held
+    pub looped: Option<(usize, usize)>,
+}
+
+/// Plays a sample, pitched by the note
+pub struct Sampler {
+    sample: Arc<Sample>,
+    sample_rate: f32,
+    frequency: f32,
+    position: f64,
+    playing: bool,
+    /// Set when a note is pressed and acted on at the next tick, once every instruction of
+    /// the tick is in
+    restart: bool,
+    start: f32,
+    gate: Smoothed,
+    /// Semitones
+    pitch: Smoothed,
+    volume: Smoothed,
+    pan: Smoothed,
+    vel_amp: f32,
+}
+
+impl Sampler {
+    pub fn new(sample: Arc<Sample>) -> Self {
+        Self {
+            sample,
+            sample_rate: 0.0,
+            frequency: note_to_frequency(ROOT_NOTE),
+            position: 0.0,
+            playing: false,
+            restart: false,
+            start: 0.0,
+            gate: Smoothed::new(0.0),
+            pitch: Smoothed::new(0.0),
+            volume: Smoothed::new(1.0),
+            pan: Smoothed::new(0.0),
+            vel_amp: 1.0,
+        }
+    }
+
+    /// The sample at the position, between the two nearest sample points
+    fn read(&self) -> f32 {
+        let data = &self.sample.data;
+        let index = self.position as usize;
+        let next = match self.sample.looped {
+            Some((start, end)) if index + 1 >= end => start,
+            _ => index + 1,
+        };
+        let fraction = (self.position - index as f64) as f32;
+        let (a, b) = (data[index], data.get(next).copied().unwrap_or(0.0));
+        a + (b - a) * fraction
+    }
+}
+
+impl Instrument for Sampler {
+    fn name(&self) -> &'static str {
+        "sampler"
+    }
+
+    fn tick(&mut self) -> (f32, f32) {
+        if self.restart {
+            self.restart = false;
+            self.position = (self.start * self.sample.data.len() as f32) as f64;
+            self.playing = true;
+        }
+        let gate = self.gate.tick();
+        let (pitch, volume, pan) = (self.pitch.tick(), self.volume.tick(), self.pan.tick());
+        if !self.playing || self.position as usize >= self.sample.data.len() || self.sample_rate <= 0.0 {
+            self.playing = false;
+            return (0.0, 0.0);
+        }
+        let ans = self.read() * gate * volume;
+        let ratio = self.frequency / note_to_frequency(ROOT_NOTE) * 2f32.powf(pitch / 12.0);
+        self.position += (ratio * self.sample.rate / self.sample_rate) as f64;
+        // Loops that don't end after they start just play through
+        if let Some((start, end)) = self.sample.looped.filter(|(start, end)| start < end) {
+            if self.position >= end as f64 {
+                self.position = start as f64 + (self.position - start as f64) % (end - start) as f64;
+            }
+        }
+        (ans * (1.0 - pan).min(1.0), ans * (1.0 + pan).min(1.0))
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.gate.set_sample_rate(sample_rate);
+        self.pitch.set_sample_rate(sample_rate);
+        self.volume.set_sample_rate(sample_rate);
+        self.pan.set_sample_rate(sample_rate);
+    }
+
+    fn apply_instruction(&mut self, instruction: InstructionKind) -> Result<(), &'static str> {
+        match instruction {
+            InstructionKind::State { status: Status::On, velocity } => {
+                let velocity = velocity as f32 / MAX_VELOCITY as f32;
+                // Restarted from silence, or it would jump from the middle of the last note
+                self.gate.jump(0.0);
+                self.gate.set(1.0 - self.vel_amp + self.vel_amp * velocity);
+                self.restart = true;
+            }
+            InstructionKind::State { status: Status::Off, .. } => self.gate.set(0.0),
+            InstructionKind::Frequency(f) => self.frequency = f,
+            InstructionKind::Note(u) => self.frequency = note_to_frequency(u),
+            InstructionKind::Param { name, value } => return self.set_param(name, value),
+            _ => return Err("Samplers only play notes and parameters"),
+        }
+        Ok(())
+    }
+
+    fn params(&self) -> &'static [ParamDescriptor] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        Some(match name {
+            "volume" => self.volume.target(),
+            "pan" => self.pan.target(),
+            "pitch" => self.pitch.target(),
+            "start" => self.start,
+            "vel_amp" => self.vel_amp,
+            "smoothing" => self.volume.ramp_time(),
+            _ => return None,
+        })
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), &'static str> {
+        let value = param::find(&PARAMS, name).ok_or("No such parameter for 'Sampler'")?.clamp(value);
+        match name {
+            "volume" => self.volume.set(value),
+            "pan" => self.pan.set(value),
+            "pitch" => self.pitch.set(value),
+            "start" => self.start = value,
+            "vel_amp" => self.vel_amp = value,
+            "smoothing" => {
+                self.gate.set_ramp_time(value);
+                self.pitch.set_ramp_time(value);
+                self.volume.set_ramp_time(value);
+                self.pan.set_ramp_time(value);
+            }
+            _ => unreachable!("parameter without a descriptor"),
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.gate.jump(0.0);
+        self.playing = false;
+        self.restart = false;
+    }
+
+    fn sample(&self) -> Option<Arc<Sample>> {
+        Some(self.sample.clone())
+    }
+}
@@ -0,0 +1,109 @@
+/// Ramp time new instruments start with, short enough to go unnoticed
+pub const DEFAULT_RAMP_TIME: f32 = 0.005;
+
+/// A value that ramps linearly to new targets instead of jumping, which would click
+#[derive(Copy, Clone, Debug)]
+pub struct Smoothed {
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: u32,
+    ramp_time: f32,
+    sample_rate: f32,
+}
+
+impl Smoothed {
+    pub fn new(value: f32) -> Self {
+        Self {
+            current: value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+            ramp_time: DEFAULT_RAMP_TIME,
+            sample_rate: 0.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn ramp_time(&self) -> f32 {
+        self.ramp_time
+    }
+
+    /// Sets how long ramps to new targets take, in seconds. Ramps underway keep their pace
+    pub fn set_ramp_time(&mut self, seconds: f32) {
+        self.ramp_time = seconds.max(0.0);
+    }
+
+    /// Starts ramping towards `value`, from wherever the value is now
+    pub fn set(&mut self, value: f32) {
+        if value == self.target {
+            return;
+        }
+        self.target = value;
+        let samples = (self.ramp_time * self.sample_rate) as u32;
+        if samples == 0 {
+            self.jump(value);
+            return;
+        }
+        self.step = (value - self.current) / samples as f32;
+        self.remaining = samples;
+    }
+
+    /// Moves to `value` right away
+    pub fn jump(&mut self, value: f32) {
+        self.current = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    /// The value being ramped towards, which is what the parameter is set to
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn value(&self) -> f32 {
+        self.current
+    }
+
+    /// Advances the ramp by a sample, returning the new value
+    pub fn tick(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = match self.remaining {
+                0 => self.target,
+                _ => self.current + self.step,
+            };
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instrument::smoothed::Smoothed;
+
+    #[test]
+    fn ramps_to_targets() {
+        let mut value = Smoothed::new(0.0);
+        value.set(1.0);
+        assert_eq!(value.tick(), 1.0);
+
+        value.set_sample_rate(1000.0);
+        value.set_ramp_time(0.004);
+        value.set(0.0);
+        assert_eq!(value.target(), 0.0);
+        assert_eq!(value.tick(), 0.75);
+        assert_eq!(value.tick(), 0.5);
+        // A new target ramps on from where the value is
+        value.set(1.0);
+        assert_eq!(value.tick(), 0.625);
+        for _ in 0..3 {
+            value.tick();
+        }
+        assert_eq!(value.value(), 1.0);
+        assert_eq!(value.tick(), 1.0);
+    }
+}
@@ -1,39 +1,103 @@
-use crate::instruction::{InstructionKind, Status};
+use crate::instruction::{note_to_frequency, InstructionKind, Status, MAX_VELOCITY};
 use crate::instrument::adsr::Adsr;
-use crate::instrument::oscillator::{Oscillator, Waveform};
+use crate::instrument::filter::LowPass;
+use crate::instrument::oscillator::{waveform_from_param, waveform_to_param, Oscillator, Waveform, WAVEFORMS};
+use crate::instrument::param::{ParamDescriptor, Unit};
+use crate::instrument::smoothed::{Smoothed, DEFAULT_RAMP_TIME};
 use crate::instrument::vibrato::Vibrato;
-use crate::instrument::Instrument;
+use crate::instrument::{param, Instrument};
+
+pub static PARAMS: [ParamDescriptor; 23] = [
+    ParamDescriptor::stepped("waveform", "Shape of the wave", 3.0, &[WAVEFORMS[0].0, WAVEFORMS[1].0, WAVEFORMS[2].0, WAVEFORMS[3].0]),
+    ParamDescriptor::new("freq", "Frequency of the wave", 0.0, 20000.0, 220.0, Unit::Hz),
+    ParamDescriptor::new("pitch", "Offset from the frequency, in semitones", -48.0, 48.0, 0.0, Unit::None),
+    ParamDescriptor::new("volume", "Output level", 0.0, 1.0, 1.0, Unit::None),
+    ParamDescriptor::new("pan", "Position from left (-1) to right (1)", -1.0, 1.0, 0.0, Unit::None),
+    ParamDescriptor::new("cutoff", "Frequency above which the filter cuts", 20.0, 20000.0, 20000.0, Unit::Hz),
+    ParamDescriptor::new("resonance", "How much the filter rings around the cutoff", 0.0, 1.0, 0.0, Unit::None),
+    ParamDescriptor::new("vel_amp", "How much softer notes are quieter, 0 ignoring velocity", 0.0, 1.0, 1.0, Unit::None),
+    ParamDescriptor::new("vel_filter", "How much softer notes lower the cutoff, up to four octaves", 0.0, 1.0, 0.0, Unit::None),
+    ParamDescriptor::new("attack", "Time from pressed to full level", 0.0, 10.0, 0.1, Unit::Seconds),
+    ParamDescriptor::new("decay", "Time from full to sustain level", 0.0, 10.0, 0.5, Unit::Seconds),
+    ParamDescriptor::new("sustain", "Level while held", 0.0, 1.0, 0.5, Unit::None),
+    ParamDescriptor::new("release", "Time from released to silent", 0.0, 10.0, 0.3, Unit::Seconds),
+    ParamDescriptor::new("delay", "Time from pressed to the start of the attack", 0.0, 10.0, 0.0, Unit::Seconds),
+    ParamDescriptor::new("hold", "Time at full level before the decay", 0.0, 10.0, 0.0, Unit::Seconds),
+    ParamDescriptor::new("attack_shape", "Bend of the attack, positive to start slow and negative to start fast", -1.0, 1.0, 0.0, Unit::None),
+    ParamDescriptor::new("decay_shape", "Bend of the decay", -1.0, 1.0, 0.0, Unit::None),
+    ParamDescriptor::new("release_shape", "Bend of the release", -1.0, 1.0, 0.0, Unit::None),
+    ParamDescriptor::stepped("legato", "Pressing while held doesn't restart the envelope", 0.0, &["off", "on"]),
+    ParamDescriptor::stepped("vib", "Vibrato", 0.0, &["off", "on"]),
+    ParamDescriptor::new("vib_rate", "Vibrato speed", 0.0, 50.0, 6.0, Unit::Hz),
+    ParamDescriptor::new("vib_depth", "Vibrato depth, as a fraction of the frequency", 0.0, 1.0, 0.01, Unit::None),
+    ParamDescriptor::new("smoothing", "Time changes to parameters take, to avoid clicks", 0.0, 1.0, DEFAULT_RAMP_TIME, Unit::Seconds),
+];
 
 pub struct Synth {
     oscillator: Oscillator,
     adsr: Adsr,
     vibrato: Vibrato,
-    volume: (f32, f32),
+    /// Semitones
+    pitch: Smoothed,
+    volume: Smoothed,
+    pan: Smoothed,
+    filter: LowPass,
+    cutoff: Smoothed,
+    resonance: Smoothed,
+    /// Velocity of the last note pressed, from 0 to 1
+    velocity: Smoothed,
+    vel_amp: f32,
+    vel_filter: f32,
 }
 
 impl Synth {
     pub fn new() -> Self {
         let mut osc = Oscillator::default();
-        osc.is_on = true;
+        osc.set_on(true);
         osc.waveform = Waveform::Triangle;
         Self {
             oscillator: osc,
             adsr: Adsr::new(0.1, 0.5, 0.5, 0.3),
             vibrato: Vibrato::new(6.0, 0.01),
-            volume: (1.0, 1.0),
+            pitch: Smoothed::new(0.0),
+            volume: Smoothed::new(1.0),
+            pan: Smoothed::new(0.0),
+            filter: LowPass::new(),
+            cutoff: Smoothed::new(20000.0),
+            resonance: Smoothed::new(0.0),
+            velocity: Smoothed::new(1.0),
+            vel_amp: 1.0,
+            vel_filter: 0.0,
         }
     }
 }
 
 impl Instrument for Synth {
+    fn name(&self) -> &'static str {
+        "synth"
+    }
+
     fn tick(&mut self) -> (f32, f32) {
-        self.oscillator.current_sample_jump = self.vibrato.tick();
-        let ans = self.oscillator.tick() * self.adsr.tick();
-        (ans * self.volume.0, ans * self.volume.1)
+        let pitch = 2f32.powf(self.pitch.tick() / 12.0);
+        self.oscillator.current_sample_jump = self.vibrato.tick() * pitch;
+        let velocity = self.velocity.tick();
+        let cutoff = self.cutoff.tick() * 2f32.powf(self.vel_filter * (velocity - 1.0) * 4.0);
+        let filtered = self.filter.tick(self.oscillator.tick(), cutoff, self.resonance.tick());
+        let gain = 1.0 - self.vel_amp + self.vel_amp * velocity;
+        let ans = filtered * self.adsr.tick() * self.volume.tick() * gain;
+        let pan = self.pan.tick();
+        (ans * (1.0 - pan).min(1.0), ans * (1.0 + pan).min(1.0))
     }
 
     fn set_sample_rate(&mut self, sample_rate: f32) {
-        self.oscillator.sample_rate = sample_rate;
+        self.oscillator.set_sample_rate(sample_rate);
+        self.pitch.set_sample_rate(sample_rate);
+        self.volume.set_sample_rate(sample_rate);
+        self.pan.set_sample_rate(sample_rate);
+        self.filter.set_sample_rate(sample_rate);
+        self.cutoff.set_sample_rate(sample_rate);
+        self.resonance.set_sample_rate(sample_rate);
+        self.velocity.set_sample_rate(sample_rate);
         self.adsr.set_sample_rate(sample_rate);
         self.vibrato.set_sample_rate(sample_rate);
     }
@@ -41,20 +105,114 @@ impl Instrument for Synth {
     fn apply_instruction(&mut self, instruction: InstructionKind) -> Result<(), &'static str> {
         match instruction {
             InstructionKind::Waveform(w) => self.oscillator.waveform = w,
-            InstructionKind::State(s) => match s {
-                Status::On => self.adsr.press(),
+            InstructionKind::State { status, velocity } => match status {
+                Status::On => {
+                    self.velocity.set(velocity as f32 / MAX_VELOCITY as f32);
+                    self.adsr.press();
+                }
                 Status::Off => self.adsr.release()
             },
             InstructionKind::Vibrato(s) => match s {
                 Status::On => self.vibrato.set_state(true),
                 Status::Off => self.vibrato.set_state(false)
             }
-            InstructionKind::Frequency(f) => self.oscillator.frequency_hz = f,
-            InstructionKind::Note(u) => self.oscillator.frequency_hz = 2f32.powf((u as f32 - 60.0) / 12.0) * 440.0,
+            InstructionKind::Frequency(f) => self.oscillator.set_frequency(f),
+            InstructionKind::Note(u) => self.oscillator.set_frequency(note_to_frequency(u)),
             InstructionKind::VibratoSettings { rate, depth } => self.vibrato.set_rate_and_depth(rate, depth),
-            InstructionKind::AdsrSettings { a, d, s, r } => self.adsr = Adsr::new(a, d, s, r),
-            _ => return Err("Illegal instruction for 'Synth'")
+            InstructionKind::AdsrSettings { a, d, s, r } => self.adsr.set_parameters(a, d, s, r),
+            InstructionKind::DahdsrSettings { delay, a, hold, d, s, r } => {
+                self.adsr.set_stages(delay, hold);
+                self.adsr.set_parameters(a, d, s, r);
+            }
+            InstructionKind::EnvelopeShape { attack, decay, release } => self.adsr.set_curves(attack, decay, release),
+            InstructionKind::Param { name, value } => return self.set_param(name, value),
         }
         Ok(())
     }
+
+    fn params(&self) -> &'static [ParamDescriptor] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        let (a, d, s, r) = self.adsr.get_parameters();
+        let (delay, hold) = self.adsr.get_stages();
+        let (attack_shape, decay_shape, release_shape) = self.adsr.get_curves();
+        let (rate, depth) = self.vibrato.get_rate_and_depth();
+        Some(match name {
+            "waveform" => waveform_to_param(self.oscillator.waveform),
+            "freq" => self.oscillator.frequency(),
+            "pitch" => self.pitch.target(),
+            "volume" => self.volume.target(),
+            "pan" => self.pan.target(),
+            "cutoff" => self.cutoff.target(),
+            "resonance" => self.resonance.target(),
+            "vel_amp" => self.vel_amp,
+            "vel_filter" => self.vel_filter,
+            "attack" => a,
+            "decay" => d,
+            "sustain" => s,
+            "release" => r,
+            "delay" => delay,
+            "hold" => hold,
+            "attack_shape" => attack_shape,
+            "decay_shape" => decay_shape,
+            "release_shape" => release_shape,
+            "legato" => if self.adsr.is_legato() { 1.0 } else { 0.0 },
+            "vib" => if self.vibrato.is_on() { 1.0 } else { 0.0 },
+            "vib_rate" => rate,
+            "vib_depth" => depth,
+            "smoothing" => self.volume.ramp_time(),
+            _ => return None
+        })
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), &'static str> {
+        let value = param::find(&PARAMS, name).ok_or("No such parameter for 'Synth'")?.clamp(value);
+        let (a, d, s, r) = self.adsr.get_parameters();
+        let (delay, hold) = self.adsr.get_stages();
+        let (attack_shape, decay_shape, release_shape) = self.adsr.get_curves();
+        let (rate, depth) = self.vibrato.get_rate_and_depth();
+        match name {
+            "waveform" => self.oscillator.waveform = waveform_from_param(value),
+            "freq" => self.oscillator.set_frequency(value),
+            "pitch" => self.pitch.set(value),
+            "volume" => self.volume.set(value),
+            "pan" => self.pan.set(value),
+            "cutoff" => self.cutoff.set(value),
+            "resonance" => self.resonance.set(value),
+            "vel_amp" => self.vel_amp = value,
+            "vel_filter" => self.vel_filter = value,
+            "attack" => self.adsr.set_parameters(value, d, s, r),
+            "decay" => self.adsr.set_parameters(a, value, s, r),
+            "sustain" => self.adsr.set_parameters(a, d, value, r),
+            "release" => self.adsr.set_parameters(a, d, s, value),
+            "delay" => self.adsr.set_stages(value, hold),
+            "hold" => self.adsr.set_stages(delay, value),
+            "attack_shape" => self.adsr.set_curves(value, decay_shape, release_shape),
+            "decay_shape" => self.adsr.set_curves(attack_shape, value, release_shape),
+            "release_shape" => self.adsr.set_curves(attack_shape, decay_shape, value),
+            "legato" => self.adsr.set_legato(value >= 0.5),
+            "vib" => self.vibrato.set_state(value >= 0.5),
+            "vib_rate" => self.vibrato.set_rate_and_depth(value, depth),
+            "vib_depth" => self.vibrato.set_rate_and_depth(rate, value),
+            "smoothing" => {
+                self.oscillator.set_ramp_time(value);
+                self.vibrato.set_ramp_time(value);
+                self.pitch.set_ramp_time(value);
+                self.volume.set_ramp_time(value);
+                self.pan.set_ramp_time(value);
+                self.cutoff.set_ramp_time(value);
+                self.resonance.set_ramp_time(value);
+                self.velocity.set_ramp_time(value);
+            }
+            _ => unreachable!("parameter without a descriptor"),
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.adsr.stop();
+        self.filter.reset();
+    }
 }
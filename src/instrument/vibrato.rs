@@ -1,7 +1,9 @@
 
+use crate::instrument::smoothed::Smoothed;
+
 pub struct Vibrato {
     rate: f32,
-    depth: f32,
+    depth: Smoothed,
     sample_rate: f32,
     current_phase: f32,
     is_on: bool,
@@ -11,7 +13,7 @@ impl Vibrato {
     pub fn new(rate: f32, depth: f32) -> Self {
         Self {
             rate,
-            depth,
+            depth: Smoothed::new(depth),
             sample_rate: 0.0,
             current_phase: 0.0,
             is_on: false
@@ -22,22 +24,36 @@ impl Vibrato {
         self.is_on = is_on
     }
 
+    pub fn is_on(&self) -> bool {
+        self.is_on
+    }
+
     pub fn set_sample_rate(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate;
+        self.depth.set_sample_rate(sample_rate);
+    }
+
+    pub fn set_ramp_time(&mut self, seconds: f32) {
+        self.depth.set_ramp_time(seconds);
     }
 
     pub fn set_rate_and_depth(&mut self, rate: f32, depth: f32) {
         self.rate = rate;
-        self.depth = depth;
+        self.depth.set(depth);
+    }
+
+    pub fn get_rate_and_depth(&self) -> (f32, f32) {
+        (self.rate, self.depth.target())
     }
 
     pub fn tick(&mut self) -> f32 {
+        let depth = self.depth.tick();
         if !self.is_on {
             return 1.0
         }
 
         let modulation = (( self.rate * 2.0 * std::f32::consts::PI * self.current_phase) / self.sample_rate) .sin();
         self.current_phase = (self.current_phase + 1.0 ) % self.sample_rate;
-        self.depth * modulation + 1.0
+        depth * modulation + 1.0
     }
 }
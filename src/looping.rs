@@ -0,0 +1,56 @@
+use crate::tempo::TempoMap;
+
+/// Part of the song playback goes around
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Loop {
+    Region { start: u128, end: u128 },
+    /// Whichever pattern the playhead is in, patterns being `rows` long
+    Pattern { rows: u128 },
+}
+
+impl Loop {
+    /// Start and end of the loop, with the playhead at `tick`
+    pub fn bounds(&self, tempo: &TempoMap, tick: u128) -> (u128, u128) {
+        match *self {
+            Loop::Region { start, end } => (start, end),
+            Loop::Pattern { rows } => {
+                let first = tempo.row_at(tick) / rows * rows;
+                (tempo.row_tick(first), tempo.row_tick(first + rows))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::instruction::{InstructionKind, Status};
+    use crate::looping::Loop;
+
+    #[test]
+    fn loops_around_with_state_chased() {
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        let volume = |value| InstructionKind::Param { name: "volume", value };
+        app.insert_instruction(0, 50, volume(0.5));
+        app.insert_instruction(0, 150, volume(0.25));
+        app.insert_instruction(0, 120, InstructionKind::State { status: Status::On, velocity: 127 });
+        app.looping = Some(Loop::Region { start: 100, end: 200 });
+        app.play();
+        for _ in 0..200 {
+            app.tick_all();
+        }
+        assert_eq!(app.position(), 100);
+        assert_eq!(app.instruments[0].get_param("volume"), Some(0.5));
+        // The note fades out instead of stopping dead at the wrap
+        assert_ne!(app.tick_all(), (0.0, 0.0));
+
+        // Patterns of four rows last half a second at 120 BPM
+        app.looping = Some(Loop::Pattern { rows: 4 });
+        app.seek(900);
+        for _ in 0..100 {
+            app.tick_all();
+        }
+        assert_eq!(app.position(), 500);
+    }
+}
@@ -4,10 +4,23 @@ use std::sync::{Arc, Mutex};
 
 mod app;
 mod audio;
+mod automation;
+mod command;
+mod envelope;
+mod groove;
+mod history;
 mod instrument;
+mod looping;
+mod metronome;
+mod midi;
+mod mml;
+mod project;
+mod render;
+mod song;
+mod tempo;
+mod tracker;
 mod view;
 mod instruction;
-mod util;
 mod instruction_handler;
 
 fn main() -> anyhow::Result<()> {
@@ -0,0 +1,122 @@
+use crate::tempo::TempoMap;
+
+/// How long a click rings, in seconds
+const CLICK_TIME: f32 = 0.03;
+const ACCENT_FREQUENCY: f32 = 1600.0;
+const FREQUENCY: f32 = 1000.0;
+
+/// Click track following the tempo map and time signature, louder on the first click of a bar
+pub struct Metronome {
+    pub on: bool,
+    pub volume: f32,
+    /// Heard in renders too, not only while playing live
+    pub in_renders: bool,
+    sample_rate: f32,
+    frequency: f32,
+    phase: f32,
+    remaining: u32,
+    length: u32,
+    /// Index and tick of the next click, worked out again after a seek
+    next: Option<(u128, u128)>,
+}
+
+impl Metronome {
+    pub fn new() -> Self {
+        Self {
+            on: false,
+            volume: 0.5,
+            in_renders: false,
+            sample_rate: 0.0,
+            frequency: FREQUENCY,
+            phase: 0.0,
+            remaining: 0,
+            length: 0,
+            next: None,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn trigger(&mut self, accent: bool) {
+        self.frequency = if accent { ACCENT_FREQUENCY } else { FREQUENCY };
+        self.length = (CLICK_TIME * self.sample_rate) as u32;
+        self.remaining = self.length;
+        self.phase = 0.0;
+    }
+
+    /// Forgets where the next click is, for when the playhead or the tempo map changes
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+
+    /// Clicks if a click of the time signature starts at `tick`. Returns whether it clicked,
+    /// and if so whether the click starts a bar
+    pub fn follow(&mut self, tempo: &TempoMap, tick: u128) -> Option<bool> {
+        let length = tempo.click_beats();
+        let (index, at) = *self.next.get_or_insert_with(|| {
+            let index = (tempo.beat_at(tick) / length - 1e-9).ceil().max(0.0) as u128;
+            (index, tempo.tick_at(index as f64 * length))
+        });
+        if tick < at {
+            return None;
+        }
+        if tick > at {
+            // Jumped past it, so the clicks are worked out again from here
+            self.next = None;
+            return None;
+        }
+        self.next = Some((index + 1, tempo.tick_at((index + 1) as f64 * length)));
+        let accent = index % tempo.signature().0 as u128 == 0;
+        self.trigger(accent);
+        Some(accent)
+    }
+
+    pub fn tick(&mut self) -> f32 {
+        if self.remaining == 0 || self.sample_rate <= 0.0 {
+            return 0.0;
+        }
+        let level = self.remaining as f32 / self.length as f32;
+        self.remaining -= 1;
+        self.phase = (self.phase + self.frequency / self.sample_rate) % 1.0;
+        (self.phase * std::f32::consts::TAU).sin() * level * level * self.volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::metronome::Metronome;
+    use crate::tempo::TempoMap;
+
+    #[test]
+    fn clicks_on_every_beat_of_the_signature() {
+        let mut tempo = TempoMap::new();
+        tempo.set_sample_rate(1000.0);
+        tempo.set_signature(3, 4).unwrap();
+        let mut metronome = Metronome::new();
+        let clicks = (0..2000).filter_map(|t| metronome.follow(&tempo, t).map(|accent| (t, accent)));
+        assert_eq!(clicks.collect::<Vec<_>>(), vec![(0, true), (500, false), (1000, false), (1500, true)]);
+
+        // Eighth notes click twice as often
+        tempo.set_signature(6, 8).unwrap();
+        metronome.reset();
+        assert_eq!((600..1000).filter(|t| metronome.follow(&tempo, *t).is_some()).collect::<Vec<_>>(), vec![750]);
+    }
+
+    #[test]
+    fn counts_in_a_bar_before_playing() {
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        app.count_in = true;
+        app.play();
+        for _ in 0..2000 {
+            app.tick_all();
+        }
+        assert!(!app.is_counting_in());
+        assert_eq!(app.position(), 0);
+        app.tick_all();
+        assert_eq!(app.position(), 1);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::app::App;
+use crate::automation::{Breakpoint, Curve};
+use crate::instruction::{InstructionKind, Status, MIDI_OFFSET};
+use crate::instrument::oscillator::Waveform;
+use crate::instrument::synth::Synth;
+use crate::tempo::{MAX_BPM, MIN_BPM};
+use smf::{Event, EventKind, Smf};
+use std::collections::HashMap;
+
+mod smf;
+
+/// Tempo of MIDI files without a tempo event, 120 BPM
+const DEFAULT_TEMPO: u32 = 500_000;
+/// Pulses a beat in exported files
+const DIVISION: u16 = 480;
+/// Key of the note instruments play before they're given one, the A at 220 Hz
+const DEFAULT_NOTE: u8 = 57;
+/// Channels of exported tracks, leaving out the one General MIDI keeps for drums
+const CHANNELS: [u8; 15] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15];
+
+/// Waveforms standing in for the sixteen families of General MIDI programs, from pianos to
+/// sound effects
+const FAMILIES: [Waveform; 16] = [
+    Waveform::Triangle,
+    Waveform::Triangle,
+    Waveform::Square,
+    Waveform::Saw,
+    Waveform::Triangle,
+    Waveform::Saw,
+    Waveform::Saw,
+    Waveform::Saw,
+    Waveform::Square,
+    Waveform::Sine,
+    Waveform::Square,
+    Waveform::Saw,
+    Waveform::Saw,
+    Waveform::Triangle,
+    Waveform::Square,
+    Waveform::Saw,
+];
+
+/// Turns the pulses of a MIDI file into ticks, following its tempo events
+struct Clock {
+    division: f64,
+    sample_rate: f64,
+    /// Pulse each tempo starts at, the tick it starts at and the tempo in microseconds a beat
+    tempos: Vec<(u64, f64, u32)>,
+}
+
+impl Clock {
+    fn new(smf: &Smf, sample_rate: f32) -> Self {
+        let mut changes = smf.tracks.iter().flatten().filter_map(|e| match e.kind {
+            EventKind::Tempo(tempo) if tempo > 0 => Some((e.time, tempo)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        changes.sort_by_key(|(time, _)| *time);
+        let mut clock = Self { division: smf.division as f64, sample_rate: sample_rate as f64, tempos: vec![(0, 0.0, DEFAULT_TEMPO)] };
+        for (time, tempo) in changes {
+            let tick = clock.exact_tick(time);
+            clock.tempos.retain(|(start, _, _)| *start < time);
+            clock.tempos.push((time, tick, tempo));
+        }
+        clock
+    }
+
+    fn exact_tick(&self, pulse: u64) -> f64 {
+        let (start, tick, tempo) = *self.tempos.iter().rev().find(|(start, _, _)| *start <= pulse).unwrap();
+        tick + (pulse - start) as f64 / self.division * tempo as f64 / 1e6 * self.sample_rate
+    }
+
+    fn tick(&self, pulse: u64) -> u128 {
+        self.exact_tick(pulse).round() as u128
+    }
+}
+
+/// One of the instruments playing the notes of a channel, which are monophonic
+struct Voice {
+    target: u128,
+    key: Option<u8>,
+}
+
+/// Reads a Standard MIDI File into the song as one undo step. Every channel of every track
+/// gets instruments of its own, as many as it has notes sounding at once. Returns a summary
+pub fn import(app: &mut App, data: &[u8]) -> Result<String, String> {
+    let smf = Smf::parse(data)?;
+    let sample_rate = app.get_sample_rate();
+    if sample_rate <= 0.0 {
+        return Err(String::from("Notes can't be placed before there's a sample rate"));
+    }
+    let clock = Clock::new(&smf, sample_rate);
+    let bpm = |tempo: u32| (60e6 / tempo as f64) as f32;
+
+    app.begin_group();
+    let (_, _, first) = clock.tempos[0];
+    app.set_initial_bpm(bpm(first));
+    for (pulse, _, tempo) in clock.tempos.iter().skip(1) {
+        let change = Breakpoint { value: bpm(*tempo).clamp(MIN_BPM, MAX_BPM), curve: Curve::Step };
+        app.set_tempo(clock.tick(*pulse), change).expect("tempo is in range");
+    }
+    let signature = smf.tracks.iter().flatten().find_map(|e| match e.kind {
+        EventKind::TimeSignature { numerator, denominator } => Some((numerator as u32, denominator as u32)),
+        _ => None,
+    });
+    if let Some((clicks, unit)) = signature {
+        // Signatures the tempo map can't count in are left as they are
+        let _ = app.set_signature(clicks, unit);
+    }
+
+    let mut notes = 0;
+    let mut first_instrument = None;
+    for track in &smf.tracks {
+        let mut voices: HashMap<u8, Vec<Voice>> = HashMap::new();
+        let mut programs: HashMap<u8, Waveform> = HashMap::new();
+        // Notes ending at a pulse free their instruments for notes starting at the same pulse
+        let mut events = track.clone();
+        events.sort_by_key(|e| (e.time, matches!(e.kind, EventKind::NoteOn { .. })));
+        for event in events {
+            let tick = clock.tick(event.time);
+            match event.kind {
+                EventKind::NoteOn { channel, key, velocity } => {
+                    let voices = voices.entry(channel).or_default();
+                    let index = match voices.iter().position(|v| v.key.is_none()) {
+                        Some(index) => index,
+                        None => {
+                            let target = app.instruments.len() as u128;
+                            app.add_instrument(Box::new(Synth::new()));
+                            first_instrument.get_or_insert(target);
+                            if let Some(waveform) = programs.get(&channel) {
+                                app.insert_instruction(target, tick, InstructionKind::Waveform(*waveform));
+                            }
+                            voices.push(Voice { target, key: None });
+                            voices.len() - 1
+                        }
+                    };
+                    let voice = &mut voices[index];
+                    voice.key = Some(key);
+                    // Keys below the lowest note there is are played on it
+                    let note = (key as u16).saturating_sub(MIDI_OFFSET);
+                    app.insert_instruction(voice.target, tick, InstructionKind::Note(note));
+                    app.insert_instruction(voice.target, tick, InstructionKind::State { status: Status::On, velocity });
+                    notes += 1;
+                }
+                EventKind::NoteOff { channel, key } => {
+                    let voice = voices.get_mut(&channel).and_then(|voices| voices.iter_mut().find(|v| v.key == Some(key)));
+                    if let Some(voice) = voice {
+                        voice.key = None;
+                        app.insert_instruction(voice.target, tick, InstructionKind::State { status: Status::Off, velocity: 0 });
+                    }
+                }
+                EventKind::Program { channel, program } => {
+                    let waveform = FAMILIES[(program / 8) as usize % FAMILIES.len()];
+                    programs.insert(channel, waveform);
+                    for voice in voices.get(&channel).into_iter().flatten() {
+                        app.insert_instruction(voice.target, tick, InstructionKind::Waveform(waveform));
+                    }
+                }
+                EventKind::Tempo(_) | EventKind::TimeSignature { .. } | EventKind::TrackName(_) => {}
+            }
+        }
+    }
+    app.end_group();
+
+    let added = first_instrument.map_or(0, |first| app.instruments.len() as u128 - first);
+    Ok(format!("Imported {} notes onto {} new instruments at {} BPM", notes, added, app.get_bpm()))
+}
+
+fn tempo_event(time: u64, bpm: f32) -> Event {
+    Event { time, kind: EventKind::Tempo((60e6 / bpm as f64).round() as u32) }
+}
+
+/// Writes the tempo map and the notes of every instrument playing any to a type 1 Standard
+/// MIDI File, a track for each instrument after the one with the tempo
+pub fn export(app: &App) -> Result<Vec<u8>, String> {
+    if app.get_sample_rate() <= 0.0 {
+        return Err(String::from("Notes can't be timed before there's a sample rate"));
+    }
+    let pulse = |tick: u128| (app.tempo.beat_at(tick) * DIVISION as f64).round() as u64;
+
+    let (numerator, denominator) = app.tempo.signature();
+    let mut conductor = vec![
+        Event { time: 0, kind: EventKind::TimeSignature { numerator: numerator as u8, denominator: denominator as u8 } },
+        tempo_event(0, app.tempo.initial_bpm()),
+    ];
+    let changes = app.tempo.changes().points().collect::<Vec<_>>();
+    for (i, (time, change)) in changes.iter().enumerate() {
+        match (change.curve, changes.get(i + 1)) {
+            // Files only change tempo suddenly, so ramps go up or down a row at a time
+            (Curve::Linear | Curve::Exponential, Some((end, _))) => {
+                for row in app.tempo.row_at(*time)..app.tempo.row_at(*end) {
+                    let tick = app.tempo.row_tick(row).max(*time);
+                    conductor.push(tempo_event(pulse(tick), app.tempo.bpm_at(tick)));
+                }
+            }
+            _ => conductor.push(tempo_event(pulse(*time), change.value)),
+        }
+    }
+    let mut tracks = vec![conductor];
+
+    for (target, instrument) in app.instruments.iter().enumerate() {
+        let channel = CHANNELS[target % CHANNELS.len()];
+        let mut events = vec![Event { time: 0, kind: EventKind::TrackName(format!("{} {}", instrument.name(), target)) }];
+        let (mut key, mut velocity) = (DEFAULT_NOTE, 0);
+        let mut sounding = None;
+        let mut instructions = app.instructions.range(target as u128, 0, u128::MAX);
+        // A note set at the same time as a press is the note pressed
+        instructions.sort_by_key(|(time, kind)| (*time, !matches!(kind, InstructionKind::Note(_))));
+        for (tick, kind) in instructions {
+            let time = pulse(tick);
+            let start = match kind {
+                InstructionKind::Note(note) => {
+                    key = (note + MIDI_OFFSET).min(127) as u8;
+                    // Changing the note of a held one plays the new note from there
+                    sounding.is_some_and(|held| held != key)
+                }
+                InstructionKind::State { status: Status::On, velocity: v } => {
+                    velocity = v;
+                    true
+                }
+                InstructionKind::State { status: Status::Off, .. } => false,
+                _ => continue,
+            };
+            if let Some(held) = sounding.take() {
+                events.push(Event { time, kind: EventKind::NoteOff { channel, key: held } });
+            }
+            if start {
+                events.push(Event { time, kind: EventKind::NoteOn { channel, key, velocity } });
+                sounding = Some(key);
+            }
+        }
+        if let Some(held) = sounding {
+            let time = events.last().map_or(0, |e| e.time) + DIVISION as u64;
+            events.push(Event { time, kind: EventKind::NoteOff { channel, key: held } });
+        }
+        if events.len() > 1 {
+            tracks.push(events);
+        }
+    }
+    Ok(Smf { division: DIVISION, tracks }.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::instruction::{note_to_frequency, InstructionKind, Status};
+    use crate::instrument::oscillator::Waveform;
+    use crate::automation::{Breakpoint, Curve};
+    use crate::midi::{export, import};
+    use crate::midi::smf::{EventKind, Smf};
+
+    fn track(events: &[u8]) -> Vec<u8> {
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend((events.len() as u32).to_be_bytes());
+        chunk.extend(events);
+        chunk
+    }
+
+    #[test]
+    fn imports_notes_programs_and_tempo() {
+        // Two tracks at 4 pulses a beat: the first sets 60 BPM then 120 BPM after two beats,
+        // the second plays a chord of two notes then a single one using running status
+        let mut file = b"MThd\0\0\0\x06\0\x01\0\x02\0\x04".to_vec();
+        file.extend(track(&[0, 0xFF, 0x51, 3, 0x0F, 0x42, 0x40, 8, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20, 0, 0xFF, 0x2F, 0]));
+        file.extend(track(&[
+            0, 0xC0, 81, 0, 0x90, 60, 100, 0, 64, 90, 4, 60, 0, 0, 64, 0, 0, 67, 80, 8, 0x80, 67, 0, 0, 0xFF, 0x2F, 0,
+        ]));
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        let summary = import(&mut app, &file).unwrap();
+        assert_eq!(summary, "Imported 3 notes onto 2 new instruments at 60 BPM");
+        assert_eq!(app.instruments.len(), 4);
+
+        let on = |velocity| InstructionKind::State { status: Status::On, velocity };
+        let off = InstructionKind::State { status: Status::Off, velocity: 0 };
+        assert!(app.instructions.has(2, 0, InstructionKind::Waveform(Waveform::Square)));
+        assert!(app.instructions.has(2, 0, on(100)));
+        // Middle C sounds as middle C
+        assert!(app.instructions.has(2, 0, InstructionKind::Note(51)));
+        assert!((note_to_frequency(51) - 261.63).abs() < 0.01);
+        assert!(app.instructions.has(3, 0, InstructionKind::Note(55)));
+        // A beat lasts a second at 60 BPM, and the note freed by the chord plays the next one
+        assert!(app.instructions.has(2, 1000, InstructionKind::Note(58)));
+        assert!(app.instructions.has(2, 1000, on(80)));
+        assert!(app.instructions.has(3, 1000, off));
+        // The tempo doubles after the second beat
+        assert!(app.instructions.has(2, 2500, off));
+        assert_eq!(app.tempo.bpm_at(2000), 120.0);
+
+        assert!(app.undo());
+        assert_eq!(app.instruments.len(), 2);
+        assert_eq!(app.tempo.bpm_at(2000), 120.0);
+    }
+
+    #[test]
+    fn exports_what_imports_back() {
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        let on = |velocity| InstructionKind::State { status: Status::On, velocity };
+        let off = InstructionKind::State { status: Status::Off, velocity: 0 };
+        app.insert_instruction(0, 0, InstructionKind::Note(60));
+        app.insert_instruction(0, 0, on(100));
+        app.insert_instruction(0, 250, InstructionKind::Note(62));
+        app.insert_instruction(0, 500, off);
+        app.set_tempo(1000, Breakpoint { value: 60.0, curve: Curve::Step }).unwrap();
+        app.insert_instruction(1, 1500, on(40));
+
+        let mut copy = App::new();
+        copy.set_sample_rates(1000.0);
+        let summary = import(&mut copy, &export(&app).unwrap()).unwrap();
+        assert_eq!(summary, "Imported 3 notes onto 2 new instruments at 120 BPM");
+        // The new note of a held one is heard as a note of its own
+        assert!(copy.instructions.has(2, 0, InstructionKind::Note(60)));
+        assert!(copy.instructions.has(2, 250, InstructionKind::Note(62)));
+        assert!(copy.instructions.has(2, 250, on(100)));
+        assert!(copy.instructions.has(2, 500, off));
+        assert!(copy.instructions.has(3, 1500, InstructionKind::Note(48)));
+        assert!(copy.instructions.has(3, 1500, on(40)));
+        assert_eq!(copy.tempo.bpm_at(1000), 60.0);
+
+        // The A at 440Hz is written as the A above middle C
+        let smf = Smf::parse(&export(&app).unwrap()).unwrap();
+        let keys = smf.tracks.iter().flatten().filter_map(|e| match e.kind {
+            EventKind::NoteOn { key, .. } => Some(key),
+            _ => None,
+        });
+        assert_eq!(keys.collect::<Vec<_>>(), vec![69, 71, 57]);
+    }
+}
@@ -0,0 +1,225 @@
+/// Events of a Standard MIDI File that mean something to a song. The rest are skipped
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventKind {
+    NoteOn { channel: u8, key: u8, velocity: u8 },
+    NoteOff { channel: u8, key: u8 },
+    Program { channel: u8, program: u8 },
+    /// Microseconds per quarter note
+    Tempo(u32),
+    TimeSignature { numerator: u8, denominator: u8 },
+    TrackName(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// Pulses since the start of the track
+    pub time: u64,
+    pub kind: EventKind,
+}
+
+/// A Standard MIDI File, timed in pulses per quarter note
+pub struct Smf {
+    pub division: u16,
+    pub tracks: Vec<Vec<Event>>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn done(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.position..self.position + length).ok_or("The MIDI file ends early")?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn peek(&self) -> Result<u8, String> {
+        self.data.get(self.position).copied().ok_or(String::from("The MIDI file ends early"))
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// A variable length quantity, seven bits a byte with the top bit set on all but the last
+    fn vlq(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(String::from("Bad variable length number in the MIDI file"))
+    }
+}
+
+impl Smf {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(data);
+        if reader.bytes(4).ok() != Some(b"MThd".as_slice()) {
+            return Err(String::from("Not a MIDI file"));
+        }
+        let length = reader.u32()? as usize;
+        let mut header = Reader::new(reader.bytes(length)?);
+        let _format = header.u16()?;
+        let _track_count = header.u16()?;
+        let division = header.u16()?;
+        if division & 0x8000 != 0 || division == 0 {
+            return Err(String::from("MIDI files timed in SMPTE frames aren't supported"));
+        }
+
+        let mut tracks = vec![];
+        while !reader.done() {
+            let id = reader.bytes(4)?;
+            let length = reader.u32()? as usize;
+            let chunk = reader.bytes(length)?;
+            // Chunks of other kinds are allowed, and skipped
+            if id == b"MTrk" {
+                tracks.push(parse_track(chunk)?);
+            }
+        }
+        Ok(Self { division, tracks })
+    }
+
+    /// The file as type 1, with events of each track in order of time
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = b"MThd".to_vec();
+        data.extend(6u32.to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+        data.extend((self.tracks.len() as u16).to_be_bytes());
+        data.extend(self.division.to_be_bytes());
+        for track in &self.tracks {
+            let chunk = write_track(track);
+            data.extend(b"MTrk");
+            data.extend((chunk.len() as u32).to_be_bytes());
+            data.extend(chunk);
+        }
+        data
+    }
+}
+
+fn write_vlq(data: &mut Vec<u8>, value: u64) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+fn write_track(events: &[Event]) -> Vec<u8> {
+    let mut data = vec![];
+    let mut time = 0;
+    for event in events {
+        write_vlq(&mut data, event.time - time);
+        time = event.time;
+        match &event.kind {
+            // Notes are started at least softly, as a velocity of 0 would end them
+            EventKind::NoteOn { channel, key, velocity } => data.extend([0x90 | channel, *key, (*velocity).clamp(1, 127)]),
+            EventKind::NoteOff { channel, key } => data.extend([0x80 | channel, *key, 64]),
+            EventKind::Program { channel, program } => data.extend([0xC0 | channel, *program]),
+            EventKind::Tempo(tempo) => {
+                data.extend([0xFF, 0x51, 3]);
+                data.extend(&(*tempo).min(0xFF_FFFF).to_be_bytes()[1..]);
+            }
+            EventKind::TimeSignature { numerator, denominator } => {
+                data.extend([0xFF, 0x58, 4, *numerator, denominator.trailing_zeros() as u8, 24, 8]);
+            }
+            EventKind::TrackName(name) => {
+                data.extend([0xFF, 0x03]);
+                write_vlq(&mut data, name.len() as u64);
+                data.extend(name.as_bytes());
+            }
+        }
+    }
+    data.extend([0, 0xFF, 0x2F, 0]);
+    data
+}
+
+fn parse_track(data: &[u8]) -> Result<Vec<Event>, String> {
+    let mut reader = Reader::new(data);
+    let mut events = vec![];
+    let mut time = 0;
+    let mut running = None;
+    while !reader.done() {
+        time += reader.vlq()?;
+        let status = match reader.peek()? {
+            byte if byte & 0x80 != 0 => reader.u8()?,
+            // Running status: the status of the last channel message carries on
+            _ => running.ok_or("Data without a status in the MIDI file")?,
+        };
+        let channel = status & 0x0F;
+        let kind = match status {
+            0xFF => {
+                running = None;
+                let kind = reader.u8()?;
+                let length = reader.vlq()? as usize;
+                let data = reader.bytes(length)?;
+                match (kind, data) {
+                    (0x2F, _) => break,
+                    (0x03, name) => Some(EventKind::TrackName(String::from_utf8_lossy(name).into_owned())),
+                    (0x51, [a, b, c]) => Some(EventKind::Tempo(u32::from_be_bytes([0, *a, *b, *c]))),
+                    (0x58, [numerator, power, ..]) if *power < 8 => {
+                        Some(EventKind::TimeSignature { numerator: *numerator, denominator: 1 << power })
+                    }
+                    _ => None,
+                }
+            }
+            0xF0 | 0xF7 => {
+                running = None;
+                let length = reader.vlq()? as usize;
+                reader.bytes(length)?;
+                None
+            }
+            0x80..=0xEF => {
+                running = Some(status);
+                match status & 0xF0 {
+                    0x80 => {
+                        let (key, _) = (reader.u8()?, reader.u8()?);
+                        Some(EventKind::NoteOff { channel, key })
+                    }
+                    0x90 => match (reader.u8()?, reader.u8()?) {
+                        // A note on without velocity is how most files end notes
+                        (key, 0) => Some(EventKind::NoteOff { channel, key }),
+                        (key, velocity) => Some(EventKind::NoteOn { channel, key, velocity }),
+                    },
+                    0xC0 => Some(EventKind::Program { channel, program: reader.u8()? }),
+                    0xD0 => {
+                        reader.u8()?;
+                        None
+                    }
+                    _ => {
+                        reader.bytes(2)?;
+                        None
+                    }
+                }
+            }
+            _ => return Err(format!("Unknown status {:#04x} in the MIDI file", status)),
+        };
+        if let Some(kind) = kind {
+            events.push(Event { time, kind });
+        }
+    }
+    Ok(events)
+}
@@ -0,0 +1,288 @@
+use crate::app::App;
+use crate::automation::{Breakpoint, Curve};
+use crate::instruction::{InstructionKind, Status, MAX_VELOCITY, MIDI_OFFSET};
+use crate::instrument::synth::Synth;
+use crate::tempo::{MAX_BPM, MIN_BPM};
+
+/// Longest a line may get once its loops are played out
+const MAX_EXPANDED: usize = 1 << 20;
+/// Instruments past the last one that channels may ask for, added as synths
+const MAX_NEW_INSTRUMENTS: usize = 26;
+
+/// A note of a channel, with its times in beats
+struct Note {
+    target: usize,
+    start: f64,
+    /// `None` when tied into the next note, which ends it instead
+    end: Option<f64>,
+    key: u16,
+    velocity: u8,
+    /// Tied from the note before, so it takes over without pressing again
+    legato: bool,
+}
+
+/// Where a channel is, carried from one of its lines to the next
+struct Channel {
+    target: usize,
+    beat: f64,
+    octave: i32,
+    /// Default length of notes, in beats
+    length: f64,
+    velocity: u8,
+    /// Eighths of its length a note sounds for
+    gate: u32,
+    tie: bool,
+}
+
+impl Channel {
+    fn new(target: usize) -> Self {
+        Self { target, beat: 0.0, octave: 4, length: 1.0, velocity: 100, gate: 8, tie: false }
+    }
+}
+
+/// Plays out loops, `[cde]3` becoming `cdecdecde`. Loops without a count play twice
+fn expand(text: &[char]) -> Result<Vec<char>, String> {
+    let mut out = vec![];
+    let mut at = 0;
+    while at < text.len() {
+        match text[at] {
+            '[' => {
+                let mut depth = 0;
+                let end = (at..text.len()).find(|i| {
+                    match text[*i] {
+                        '[' => depth += 1,
+                        ']' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                }).ok_or("A loop has no end ']'")?;
+                let inner = expand(&text[at + 1..end])?;
+                at = end + 1;
+                let digits = text[at..].iter().take_while(|c| c.is_ascii_digit()).collect::<String>();
+                at += digits.len();
+                let count = match digits.is_empty() {
+                    true => 2,
+                    false => digits.parse::<usize>().map_err(|_| format!("Loops can't repeat {} times", digits))?,
+                };
+                let length = inner.len().checked_mul(count).and_then(|n| n.checked_add(out.len()));
+                if length.is_none_or(|length| length > MAX_EXPANDED) {
+                    return Err(String::from("Loops repeat too much"));
+                }
+                for _ in 0..count {
+                    out.extend(&inner);
+                }
+            }
+            ']' => return Err(String::from("A loop ends without starting")),
+            c => {
+                out.push(c);
+                at += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+struct Parser {
+    text: Vec<char>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.text.get(self.at).copied()
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let digits = self.text[self.at..].iter().take_while(|c| c.is_ascii_digit()).collect::<String>();
+        self.at += digits.len();
+        digits.parse().ok()
+    }
+
+    /// A length like `8` or `4.`, in beats, or `default` without a number
+    fn length(&mut self, default: f64) -> Result<f64, String> {
+        let mut beats = match self.number() {
+            Some(0) => return Err(String::from("Lengths start at 1, a whole note")),
+            Some(n) => 4.0 / n as f64,
+            None => default,
+        };
+        let mut dot = beats / 2.0;
+        while self.peek() == Some('.') {
+            self.at += 1;
+            beats += dot;
+            dot /= 2.0;
+        }
+        Ok(beats)
+    }
+}
+
+/// Reads a line of MML into the notes and tempo changes of a channel
+fn parse(text: &str, channel: &mut Channel, notes: &mut Vec<Note>, tempos: &mut Vec<(f64, f32)>) -> Result<(), String> {
+    let mut parser = Parser { text: expand(&text.chars().collect::<Vec<_>>())?, at: 0 };
+    while let Some(c) = parser.peek() {
+        parser.at += 1;
+        match c.to_ascii_lowercase() {
+            c @ 'a'..='g' => {
+                let accidental = match parser.peek() {
+                    Some('+' | '#') => 1,
+                    Some('-') => -1,
+                    _ => 0,
+                };
+                if accidental != 0 {
+                    parser.at += 1;
+                }
+                let beats = parser.length(channel.length)?;
+                let semitone = [9, 11, 0, 2, 4, 5, 7][(c as u8 - b'a') as usize] + accidental;
+                // Octaves count from C as note names do, so `o4 a` is A-4
+                let key = ((channel.octave + 1) * 12 + semitone - MIDI_OFFSET as i32).clamp(0, 127) as u16;
+                let (start, end) = (channel.beat, channel.beat + beats * channel.gate as f64 / 8.0);
+                let tied = std::mem::take(&mut channel.tie);
+                match notes.last_mut().filter(|last| tied && last.target == channel.target) {
+                    // Tied to the same note, it just goes on for longer
+                    Some(last) if last.key == key => last.end = Some(end),
+                    Some(last) => {
+                        last.end = None;
+                        notes.push(Note { target: channel.target, start, end: Some(end), key, velocity: channel.velocity, legato: true });
+                    }
+                    None => notes.push(Note { target: channel.target, start, end: Some(end), key, velocity: channel.velocity, legato: false }),
+                }
+                channel.beat += beats;
+            }
+            'r' => channel.beat += parser.length(channel.length)?,
+            '^' => {
+                let beats = parser.length(channel.length)?;
+                channel.beat += beats;
+                if let Some(end) = notes.last_mut().filter(|n| n.target == channel.target).and_then(|n| n.end.as_mut()) {
+                    *end += beats;
+                }
+            }
+            '&' => channel.tie = true,
+            'o' => channel.octave = parser.number().filter(|o| *o <= 9).ok_or("Octaves go from o0 to o9")? as i32,
+            '>' => channel.octave = (channel.octave + 1).min(9),
+            '<' => channel.octave = (channel.octave - 1).max(0),
+            'l' => channel.length = parser.length(channel.length)?,
+            'v' => {
+                let volume = parser.number().filter(|v| *v <= 15).ok_or("Volumes go from v0 to v15")?;
+                channel.velocity = (volume as f32 / 15.0 * MAX_VELOCITY as f32).round() as u8;
+            }
+            'q' => channel.gate = parser.number().filter(|q| (1..=8).contains(q)).ok_or("Gate times go from q1 to q8")?,
+            't' => {
+                let bpm = parser.number().ok_or("A tempo needs a number, like t120")?;
+                tempos.push((channel.beat, (bpm as f32).clamp(MIN_BPM, MAX_BPM)));
+            }
+            '@' => channel.target = parser.number().ok_or("An instrument needs a number, like @1")? as usize,
+            c if c.is_whitespace() || c == '|' => {}
+            c => return Err(format!("Unknown command '{}'", c)),
+        }
+    }
+    Ok(())
+}
+
+/// Reads MML into the song as one undo step. Each line plays on a channel, named by letters
+/// and a colon starting the line like `A:` or `AB:` for two at once, or on channel A. Channels
+/// play on the instrument of the same index, A on 0, or on the one `@` chooses. Returns a summary
+pub fn import(app: &mut App, text: &str) -> Result<String, String> {
+    if app.get_sample_rate() <= 0.0 {
+        return Err(String::from("Notes can't be placed before there's a sample rate"));
+    }
+    let mut channels = (0..26).map(Channel::new).collect::<Vec<_>>();
+    let mut used = [false; 26];
+    let (mut notes, mut tempos) = (vec![], vec![]);
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap();
+        let (names, music) = match line.split_once(':') {
+            Some((names, music)) if !names.trim().is_empty() && names.trim().chars().all(|c| c.is_ascii_uppercase()) => (names.trim(), music),
+            Some(_) => return Err(format!("Line {}: Channels are named by capital letters, like A: or AB:", n + 1)),
+            None => ("A", line),
+        };
+        if music.trim().is_empty() {
+            continue;
+        }
+        for name in names.chars() {
+            let index = (name as u8 - b'A') as usize;
+            used[index] = true;
+            parse(music, &mut channels[index], &mut notes, &mut tempos).map_err(|e| format!("Line {}: {}", n + 1, e))?;
+        }
+    }
+
+    let last = notes.iter().map(|n| n.target).max();
+    if last.is_some_and(|last| last >= app.instruments.len() + MAX_NEW_INSTRUMENTS) {
+        return Err(format!("No instrument {}", last.unwrap()));
+    }
+    app.begin_group();
+    while last.is_some_and(|last| last >= app.instruments.len()) {
+        app.add_instrument(Box::new(Synth::new()));
+    }
+    // Changes only move what comes after them, so in order each lands where its beat is
+    tempos.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (beat, bpm) in tempos {
+        match app.tempo.tick_at(beat) {
+            0 => app.set_initial_bpm(bpm),
+            tick => app.set_tempo(tick, Breakpoint { value: bpm, curve: Curve::Step }).expect("tempo is in range"),
+        }
+    }
+    for note in &notes {
+        let target = note.target as u128;
+        let start = app.tempo.tick_at(note.start);
+        app.insert_instruction(target, start, InstructionKind::Note(note.key));
+        if !note.legato {
+            app.insert_instruction(target, start, InstructionKind::State { status: Status::On, velocity: note.velocity });
+        }
+        if let Some(end) = note.end {
+            app.insert_instruction(target, app.tempo.tick_at(end), InstructionKind::State { status: Status::Off, velocity: 0 });
+        }
+    }
+    app.end_group();
+    Ok(format!("Imported {} notes on {} channels", notes.len(), used.iter().filter(|u| **u).count()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::instruction::{InstructionKind, Status};
+    use crate::mml::import;
+
+    #[test]
+    fn imports_channels_of_notes() {
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        let text = "A: t120 o4 l8 c d+ r4 [e]2 c4&d4 ; melody\nB: @3 v15 q4 >c2. t60 c";
+        assert_eq!(import(&mut app, text).unwrap(), "Imported 8 notes on 2 channels");
+        assert_eq!(app.instruments.len(), 4);
+
+        let on = |velocity| InstructionKind::State { status: Status::On, velocity };
+        let off = InstructionKind::State { status: Status::Off, velocity: 0 };
+        // Eighth notes last a quarter of a second at 120 BPM, starting on C-4
+        assert!(app.instructions.has(0, 0, InstructionKind::Note(51)));
+        assert!(app.instructions.has(0, 0, on(100)));
+        assert!(app.instructions.has(0, 250, InstructionKind::Note(54)));
+        assert!(app.instructions.has(0, 1000, InstructionKind::Note(55)));
+        assert!(app.instructions.has(0, 1250, InstructionKind::Note(55)));
+        // The tie glides into the next note without pressing it again, after the tempo of the
+        // other channel halves at the fourth beat
+        assert!(app.instructions.has(0, 1500, on(100)));
+        assert!(app.instructions.has(0, 2500, InstructionKind::Note(53)));
+        assert!(!app.instructions.has(0, 2500, on(100)));
+        assert!(!app.instructions.has(0, 2500, off));
+        assert!(app.instructions.has(0, 3500, off));
+        // Held for half its length
+        assert!(app.instructions.has(3, 0, InstructionKind::Note(63)));
+        assert!(app.instructions.has(3, 0, on(127)));
+        assert!(app.instructions.has(3, 750, off));
+        assert!(app.instructions.has(3, 1500, on(127)));
+        assert!(app.instructions.has(3, 2000, off));
+
+        assert!(app.undo());
+        assert_eq!(app.instruments.len(), 2);
+        assert_eq!(app.get_bpm(), 120);
+
+        assert_eq!(import(&mut app, "A: o4 cx").unwrap_err(), "Line 1: Unknown command 'x'");
+        assert_eq!(import(&mut app, "[c]99999999999999999999").unwrap_err(), "Line 1: Loops can't repeat 99999999999999999999 times");
+        assert_eq!(import(&mut app, "[[c]9999999999]9999999999").unwrap_err(), "Line 1: Loops repeat too much");
+        // Capitals without a colon are notes, not channel names
+        assert_eq!(import(&mut app, "C D E").unwrap(), "Imported 3 notes on 1 channels");
+        assert!(app.instructions.has(0, 500, InstructionKind::Note(53)));
+        // The A of the fourth octave is the A at 440Hz
+        import(&mut app, "o4 a").unwrap();
+        assert!(app.instructions.has(0, 0, InstructionKind::Note(60)));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::app::App;
+use crate::groove::Groove;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Settings of a song kept in a RON file next to it
+#[derive(Default, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    /// Grooves of this project, found by name before the built-in ones
+    pub grooves: Vec<Groove>,
+    /// Name of the groove of the whole song
+    pub groove: Option<String>,
+    /// Names of the grooves of single instruments, by instrument index
+    pub instrument_grooves: BTreeMap<usize, String>,
+}
+
+impl Project {
+    pub fn from_ron(text: &str) -> Result<Self, String> {
+        ron::from_str(text).map_err(|e| format!("Bad project file: {}", e))
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).expect("projects always serialize")
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Can't read '{}': {}", path, e))?;
+        Self::from_ron(&text)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_ron()).map_err(|e| format!("Can't write '{}': {}", path, e))
+    }
+
+    /// The project settings of `app`. Grooves in use that aren't built in, like swings of a
+    /// custom percentage, are kept with the project so it loads the same
+    pub fn from_app(app: &App) -> Self {
+        let mut grooves = app.grooves.user.clone();
+        let mut keep = |groove: &Groove| {
+            if app.grooves.find(&groove.name).as_ref() != Some(groove) {
+                grooves.retain(|g| g.name != groove.name);
+                grooves.push(groove.clone());
+            }
+            groove.name.clone()
+        };
+        let groove = app.grooves.song.as_ref().map(&mut keep);
+        let instrument_grooves = app.grooves.targets().map(|(target, groove)| (target as usize, keep(groove))).collect();
+        Self { grooves, groove, instrument_grooves }
+    }
+
+    /// Replaces the project settings of `app`, with the grooves it plays as one undo step.
+    /// Fails without changing anything if a groove is missing
+    pub fn apply(self, app: &mut App) -> Result<(), String> {
+        let user = std::mem::replace(&mut app.grooves.user, self.grooves);
+        let find = |name: &String| app.grooves.find(name).ok_or(format!("No groove '{}'", name));
+        let song = self.groove.as_ref().map(find).transpose();
+        let targets = self.instrument_grooves.iter().map(|(target, name)| Ok((*target as u128, find(name)?))).collect::<Result<Vec<_>, String>>();
+        let (song, targets) = match (song, targets) {
+            (Ok(song), Ok(targets)) => (song, targets),
+            (Err(e), _) | (_, Err(e)) => {
+                app.grooves.user = user;
+                return Err(e);
+            }
+        };
+        app.begin_group();
+        app.set_song_groove(song);
+        for target in 0..app.instruments.len() as u128 {
+            if targets.iter().all(|(t, _)| *t != target) {
+                app.set_groove(target, None);
+            }
+        }
+        for (target, groove) in targets {
+            app.set_groove(target, Some(groove));
+        }
+        app.end_group();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::groove::Groove;
+    use crate::project::Project;
+
+    #[test]
+    fn keeps_grooves_through_a_file() {
+        let mut app = App::new();
+        app.grooves.user.push(Groove { name: String::from("lazy"), timing: vec![0.1], accents: vec![1.0, 0.5] });
+        app.grooves.song = Some(Groove::swing("swing 62%", 62.0));
+        app.grooves.set_target(1, app.grooves.find("lazy"));
+        let text = Project::from_app(&app).to_ron();
+
+        let mut loaded = App::new();
+        Project::from_ron(&text).unwrap().apply(&mut loaded).unwrap();
+        assert_eq!(loaded.grooves.song, app.grooves.song);
+        assert_eq!(loaded.grooves.get(1), app.grooves.get(1));
+        assert_eq!(loaded.grooves.user.len(), 2);
+        // The grooves played come off again in one undo
+        assert!(loaded.undo());
+        assert_eq!(loaded.grooves.song, None);
+        assert_eq!(loaded.grooves.get(1), None);
+        assert!(!loaded.undo());
+
+        let missing = Project::from_ron("(groove: Some(\"nope\"))").unwrap();
+        assert!(missing.apply(&mut loaded).is_err());
+        assert_eq!(loaded.grooves.user.len(), 2);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::app::App;
+use crate::looping::Loop;
+use std::sync::Mutex;
+
+/// Seconds rendered past the last thing in the song, for releases to fade out
+const TAIL: f32 = 2.0;
+
+/// Where stems are taken from, straight out of each instrument or after the mixer
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Tap {
+    Pre,
+    Post,
+}
+
+/// Stereo samples, left and right
+pub type Frames = Vec<(f32, f32)>;
+
+/// The last tick anything in the song happens at
+fn song_end(app: &App) -> u128 {
+    let targets = 0..app.instruments.len() as u128;
+    let instructions = targets.clone().filter_map(|t| app.instructions.range(t, 0, u128::MAX).last().map(|(time, _)| *time));
+    let lanes = targets.flat_map(|t| app.automation.lanes(t).filter_map(|(_, lane)| lane.points().last()).map(|(time, _)| time).collect::<Vec<_>>());
+    let tempos = app.tempo.changes().points().map(|(time, _)| time);
+    instructions.chain(lanes).chain(tempos).max().unwrap_or(0)
+}
+
+/// Ticks rendered at a time by [`export`], between which the app is let go
+const CHUNK: usize = 4096;
+
+/// A render of the song into memory, played a chunk at a time so the app needn't be held for
+/// all of it
+pub struct Render {
+    stems: Option<Tap>,
+    /// Where playback was, whether it played and counted in, and its loop, to put back after
+    restore: (u128, bool, bool, Option<Loop>),
+    length: usize,
+    master: Frames,
+    tracks: Vec<Frames>,
+    frame: Vec<(f32, f32)>,
+}
+
+impl Render {
+    /// Plays the song from the start with the same ticks as playback, and with `stems` keeps
+    /// what every instrument played too
+    pub fn start(app: &mut App, stems: Option<Tap>) -> Result<Self, String> {
+        let sample_rate = app.get_sample_rate();
+        if sample_rate <= 0.0 {
+            return Err(String::from("Nothing can be rendered before there's a sample rate"));
+        }
+        // Loops would never let it end
+        let restore = (app.position(), app.is_playing(), app.count_in, app.looping.take());
+        app.count_in = false;
+        app.rendering = true;
+        app.seek(0);
+        app.play();
+
+        let length = (song_end(app) + (TAIL * sample_rate) as u128) as usize;
+        let tracks = vec![Vec::with_capacity(length); if stems.is_some() { app.instruments.len() } else { 0 }];
+        Ok(Self { stems, restore, length, master: Vec::with_capacity(length), tracks, frame: vec![] })
+    }
+
+    /// Renders up to `ticks` more, returning whether it's done
+    pub fn step(&mut self, app: &mut App, ticks: usize) -> bool {
+        for _ in 0..ticks.min(self.length - self.master.len()) {
+            match self.stems {
+                Some(tap) => {
+                    self.master.push(app.tick_stems(&mut self.frame, tap == Tap::Post));
+                    for (i, track) in self.tracks.iter_mut().enumerate() {
+                        track.push(self.frame.get(i).copied().unwrap_or_default());
+                    }
+                }
+                None => self.master.push(app.tick_all()),
+            }
+        }
+        self.master.len() == self.length
+    }
+
+    /// Share of the song rendered so far, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        self.master.len() as f32 / self.length.max(1) as f32
+    }
+
+    /// Puts playback back where it was, returning the master and the stems
+    pub fn finish(self, app: &mut App) -> (Frames, Vec<Frames>) {
+        let (position, playing, count_in, looping) = self.restore;
+        app.pause();
+        app.rendering = false;
+        app.looping = looping;
+        app.seek(position);
+        if playing {
+            app.play();
+        }
+        app.count_in = count_in;
+        (self.master, self.tracks)
+    }
+}
+
+/// A stereo WAV file of 32-bit floats, which keeps stems louder than full scale intact
+pub fn wav(frames: &[(f32, f32)], sample_rate: f32) -> Vec<u8> {
+    let rate = sample_rate.round() as u32;
+    let size = (frames.len() * 8) as u32;
+    let mut out = Vec::with_capacity(44 + size as usize);
+    out.extend(b"RIFF");
+    out.extend((36 + size).to_le_bytes());
+    out.extend(b"WAVEfmt ");
+    out.extend(16u32.to_le_bytes());
+    // Floating point samples, in two channels
+    out.extend(3u16.to_le_bytes());
+    out.extend(2u16.to_le_bytes());
+    out.extend(rate.to_le_bytes());
+    out.extend((rate * 8).to_le_bytes());
+    out.extend(8u16.to_le_bytes());
+    out.extend(32u16.to_le_bytes());
+    out.extend(b"data");
+    out.extend(size.to_le_bytes());
+    for (left, right) in frames {
+        out.extend(left.to_le_bytes());
+        out.extend(right.to_le_bytes());
+    }
+    out
+}
+
+/// Where the stem of an instrument goes, next to the master, like `song-0-synth.wav`
+fn stem_path(path: &str, index: usize, name: &str) -> String {
+    match path.rsplit_once('.').filter(|(_, extension)| !extension.contains('/')) {
+        Some((stem, extension)) => format!("{}-{}-{}.{}", stem, index, name, extension),
+        None => format!("{}-{}-{}", path, index, name),
+    }
+}
+
+/// Renders the song to a WAV file at `path`, and with `stems` every instrument to one of its
+/// own next to it. The app is only locked a chunk at a time, so the interface keeps going while
+/// playback stays silent, and `progress` hears how far it got after each. Whoever else holds
+/// the app must leave the song and playhead alone until it's done. Returns a summary
+pub fn export(app: &Mutex<App>, path: &str, stems: Option<Tap>, mut progress: impl FnMut(f32)) -> Result<String, String> {
+    let (mut render, names, sample_rate) = {
+        let mut app = app.lock().unwrap();
+        let names = app.instruments.iter().map(|inst| inst.name()).collect::<Vec<_>>();
+        (Render::start(&mut app, stems)?, names, app.get_sample_rate())
+    };
+    while !render.step(&mut app.lock().unwrap(), CHUNK) {
+        progress(render.progress());
+    }
+    let (master, tracks) = render.finish(&mut app.lock().unwrap());
+    let write = |path: &str, frames: &[(f32, f32)]| {
+        std::fs::write(path, wav(frames, sample_rate)).map_err(|e| format!("Can't write '{}': {}", path, e))
+    };
+    write(path, &master)?;
+    for (i, track) in tracks.iter().enumerate() {
+        write(&stem_path(path, i, names[i]), track)?;
+    }
+    let seconds = master.len() as f32 / sample_rate;
+    match tracks.len() {
+        0 => Ok(format!("Rendered {:.1}s to '{}'", seconds, path)),
+        n => Ok(format!("Rendered {:.1}s to '{}' and {} stems", seconds, path, n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::instruction::{InstructionKind, Status};
+    use crate::render::{export, stem_path, wav, Frames, Render, Tap};
+    use std::sync::Mutex;
+
+    fn render(app: &mut App, stems: Option<Tap>) -> (Frames, Vec<Frames>) {
+        let mut render = Render::start(app, stems).unwrap();
+        while !render.step(app, 1000) {}
+        render.finish(app)
+    }
+
+    #[test]
+    fn renders_stems_that_add_up_to_the_master() {
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        app.insert_instruction(0, 0, InstructionKind::State { status: Status::On, velocity: 127 });
+        app.insert_instruction(1, 100, InstructionKind::State { status: Status::On, velocity: 127 });
+        app.insert_instruction(1, 500, InstructionKind::State { status: Status::Off, velocity: 0 });
+        app.seek(300);
+
+        let (master, stems) = render(&mut app, Some(Tap::Post));
+        assert_eq!(master.len(), 2500);
+        assert_eq!(stems.len(), 2);
+        assert!(stems.iter().all(|s| s.len() == 2500 && s.iter().any(|f| f.0 != 0.0)));
+        for (i, (left, right)) in master.iter().enumerate() {
+            assert!((left - stems[0][i].0 - stems[1][i].0).abs() < 1e-6);
+            assert!((right - stems[0][i].1 - stems[1][i].1).abs() < 1e-6);
+        }
+        // Playback is back where it was
+        assert_eq!(app.position(), 300);
+        assert!(!app.is_playing());
+
+        let (_, pre) = render(&mut app, Some(Tap::Pre));
+        assert!(pre[0].iter().map(|f| f.0.abs()).sum::<f32>() > stems[0].iter().map(|f| f.0.abs()).sum::<f32>());
+
+        let file = wav(&master, 1000.0);
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(file.len(), 44 + 2500 * 8);
+        assert_eq!(stem_path("out/mix.wav", 1, "osc"), "out/mix-1-osc.wav");
+
+        // Exports let go of the app between chunks, telling how far they got each time
+        let path = std::env::temp_dir().join(format!("fmangroove-render-{}.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+        app.insert_instruction(1, 10000, InstructionKind::State { status: Status::Off, velocity: 0 });
+        let app = Mutex::new(app);
+        let mut steps = vec![];
+        assert_eq!(export(&app, path, None, |done| steps.push(done)).unwrap(), format!("Rendered 12.0s to '{}'", path));
+        assert_eq!(std::fs::read(path).unwrap().len(), 44 + 12000 * 8);
+        assert_eq!(steps, vec![4096.0 / 12000.0, 8192.0 / 12000.0]);
+        assert!(!app.lock().unwrap().rendering);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,642 @@
+use crate::app::App;
+use crate::automation::{Breakpoint, Curve, Lane, CURVES};
+use crate::command::args::{note_name, parse_note_name, Args};
+use crate::envelope::{Envelope, Point};
+use crate::history::Edit;
+use crate::instruction::{InstructionKind, Status};
+use crate::instrument::param::{self, ParamDescriptor};
+use crate::instrument::sampler::{self, Sample, Sampler};
+use crate::instrument::{self, Instrument};
+use crate::tempo::{TempoMap, MAX_BPM, MIN_BPM};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// First line of every song file
+const HEADER: &str = "fmangroove song";
+/// Sample points on each `data` line
+const POINTS_PER_LINE: usize = 32;
+
+/// An instrument read from a song file, made once the whole file is in
+#[derive(Default)]
+struct Track {
+    kind: String,
+    params: Vec<(&'static str, f32)>,
+    /// Rate and loop of the sample of a sampler
+    sample: Option<(f32, Option<(usize, usize)>)>,
+    data: Vec<f32>,
+    /// Instructions by row and ticks into the row
+    instructions: Vec<(u128, u128, InstructionKind)>,
+    lanes: BTreeMap<&'static str, Lane>,
+    envelopes: BTreeMap<&'static str, Envelope>,
+}
+
+impl Track {
+    fn params(&self) -> &'static [ParamDescriptor] {
+        match self.kind.as_str() {
+            "sampler" => &sampler::PARAMS,
+            kind => instrument::from_name(kind).expect("kinds are checked when read").params(),
+        }
+    }
+
+    fn param(&self, name: &str) -> Result<&'static str, String> {
+        param::find(self.params(), name).map(|p| p.name).ok_or(format!("A {} has no parameter '{}'", self.kind, name))
+    }
+
+    /// Whether `inst` is this instrument, so it can stay when the song is read again
+    fn fits(&self, inst: &dyn Instrument) -> bool {
+        match (&self.sample, inst.sample()) {
+            (Some((rate, looped)), Some(sample)) => sample.rate == *rate && sample.looped == *looped && sample.data == self.data,
+            (None, None) => inst.name() == self.kind,
+            _ => false,
+        }
+    }
+
+    /// Takes the instructions and automation, their times now in ticks of `app`
+    fn ticks(&mut self, app: &App, rate: f32) -> (Vec<(u128, InstructionKind)>, BTreeMap<&'static str, Lane>) {
+        let instructions = std::mem::take(&mut self.instructions).into_iter()
+            .map(|(row, offset, kind)| (app.tempo.row_tick(row) + scale(app, rate, offset), kind))
+            .collect();
+        let lanes = std::mem::take(&mut self.lanes).into_iter().map(|(name, lane)| {
+            let mut scaled = Lane::default();
+            for (time, point) in lane.points() {
+                scaled.insert(scale(app, rate, time), point);
+            }
+            (name, scaled)
+        }).collect();
+        (instructions, lanes)
+    }
+
+    fn instrument(self, sample_rate: f32) -> Box<dyn Instrument> {
+        let mut inst: Box<dyn Instrument> = match self.sample {
+            Some((rate, looped)) => Box::new(Sampler::new(Arc::new(Sample { data: self.data, rate, looped }))),
+            None => instrument::from_name(&self.kind).expect("kinds are checked when read"),
+        };
+        for (name, value) in self.params {
+            let _ = inst.set_param(name, value);
+        }
+        inst.set_sample_rate(sample_rate);
+        inst
+    }
+}
+
+/// A song read from a file, with the tempo it starts at in a map of its own
+struct Song {
+    rate: f32,
+    tempo: TempoMap,
+    tempos: Vec<(u128, Breakpoint)>,
+    tracks: Vec<Track>,
+}
+
+/// A tick of a file written at `rate` as a tick of `app`, keeping its time when the rates differ
+fn scale(app: &App, rate: f32, tick: u128) -> u128 {
+    (tick as f64 * app.get_sample_rate() as f64 / rate as f64).round() as u128
+}
+
+/// A new instrument of the same kind, to tell which settings were changed
+fn fresh(inst: &dyn Instrument) -> Option<Box<dyn Instrument>> {
+    match inst.sample() {
+        Some(sample) => Some(Box::new(Sampler::new(sample))),
+        None => instrument::from_name(inst.name()),
+    }
+}
+
+/// The row a tick is in, and how far into it
+fn row_of(tempo: &TempoMap, tick: u128) -> (u128, u128) {
+    let mut row = tempo.row_at(tick);
+    while row > 0 && tempo.row_tick(row) > tick {
+        row -= 1;
+    }
+    while tempo.row_tick(row + 1) <= tick {
+        row += 1;
+    }
+    (row, tick - tempo.row_tick(row))
+}
+
+/// Instructions of a slot in the order they're written, notes before what presses them
+fn order(kind: &InstructionKind) -> (u8, String) {
+    match kind {
+        InstructionKind::Note(_) => (0, String::new()),
+        InstructionKind::State { .. } => (1, String::new()),
+        kind => (2, kind.to_string()),
+    }
+}
+
+/// One instruction in a row. The common ones get short forms, the rest are typed out
+fn word(kind: &InstructionKind) -> String {
+    match kind {
+        InstructionKind::Note(note) => note_name(*note),
+        InstructionKind::State { status: Status::On, velocity } => format!("v{}", velocity),
+        InstructionKind::State { status: Status::Off, velocity: 0 } => String::from("off"),
+        kind => format!("({})", kind),
+    }
+}
+
+/// A cell of the rows, like `C-4 v100 +300 off`, `+N` moving what follows N ticks into the row
+fn cell(instructions: &[(u128, InstructionKind)]) -> String {
+    let mut words = vec![];
+    let mut last = 0;
+    for (offset, kind) in instructions {
+        if *offset != last {
+            words.push(format!("+{}", offset));
+            last = *offset;
+        }
+        words.push(word(kind));
+    }
+    match words.is_empty() {
+        true => String::from("..."),
+        false => words.join(" "),
+    }
+}
+
+/// Writes the song as text: settings first, then a line per row with a column per instrument
+pub fn write(app: &App) -> Result<String, String> {
+    if app.get_sample_rate() <= 0.0 {
+        return Err(String::from("Songs can't be written before there's a sample rate"));
+    }
+    let mut out = String::new();
+    let (clicks, unit) = app.tempo.signature();
+    writeln!(out, "{}", HEADER).unwrap();
+    writeln!(out, "rate {}", app.get_sample_rate()).unwrap();
+    writeln!(out, "bpm {}", app.tempo.initial_bpm()).unwrap();
+    writeln!(out, "signature {}/{}", clicks, unit).unwrap();
+    for (time, change) in app.tempo.changes().points() {
+        writeln!(out, "tempo {} {} {}", time, change.value, change.curve).unwrap();
+    }
+
+    let mut columns = vec![];
+    for (i, inst) in app.instruments.iter().enumerate() {
+        let target = i as u128;
+        writeln!(out, "\ninst {} {}", i, inst.name()).unwrap();
+        let fresh = fresh(inst.as_ref());
+        for p in inst.params() {
+            let Some(value) = inst.get_param(p.name) else { continue };
+            if Some(value) != fresh.as_ref().and_then(|f| f.get_param(p.name)) {
+                writeln!(out, "set {} {} {}", i, p.name, value).unwrap();
+            }
+        }
+        for (name, lane) in app.automation.lanes(target) {
+            for (time, point) in lane.points() {
+                writeln!(out, "lane {} {} {} {} {}", i, name, time, point.value, point.curve).unwrap();
+            }
+        }
+        for (name, env) in app.envelopes.target(target) {
+            write!(out, "env {} {} {}", i, name, if env.synced { "beats" } else { "secs" }).unwrap();
+            if let Some(sustain) = env.sustain {
+                write!(out, " sustain {}", sustain).unwrap();
+            }
+            if let Some((start, end)) = env.looped {
+                write!(out, " loop {} {}", start, end).unwrap();
+            }
+            writeln!(out).unwrap();
+            for point in env.points() {
+                writeln!(out, "point {} {} {} {} {}", i, name, point.time, point.value, point.curve).unwrap();
+            }
+        }
+        if let Some(sample) = inst.sample() {
+            write!(out, "sample {} {}", i, sample.rate).unwrap();
+            if let Some((start, end)) = sample.looped {
+                write!(out, " loop {} {}", start, end).unwrap();
+            }
+            writeln!(out).unwrap();
+            for points in sample.data.chunks(POINTS_PER_LINE) {
+                let words = points.iter().map(|p| format!("{:04x}", (p.clamp(-1.0, 1.0) * 32768.0).round().min(32767.0) as i16));
+                writeln!(out, "data {} {}", i, words.collect::<Vec<_>>().join(" ")).unwrap();
+            }
+        }
+
+        let mut rows = BTreeMap::<u128, Vec<(u128, InstructionKind)>>::new();
+        for (time, kind) in app.instructions.range(target, 0, u128::MAX) {
+            let (row, offset) = row_of(&app.tempo, time);
+            rows.entry(row).or_default().push((offset, kind));
+        }
+        for instructions in rows.values_mut() {
+            instructions.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| order(&a.1).cmp(&order(&b.1))));
+        }
+        columns.push(rows);
+    }
+
+    let last = columns.iter().filter_map(|rows| rows.keys().last()).max();
+    let cells = columns.iter().map(|rows| {
+        (0..last.map_or(0, |last| last + 1)).map(|row| cell(rows.get(&row).map_or(&[][..], |i| i))).collect::<Vec<_>>()
+    }).collect::<Vec<_>>();
+    let titles = app.instruments.iter().enumerate().map(|(i, inst)| format!("{} {}", i, inst.name())).collect::<Vec<_>>();
+    let widths = cells.iter().zip(&titles).map(|(c, t)| c.iter().map(|c| c.len()).chain([t.len()]).max().unwrap()).collect::<Vec<_>>();
+
+    write!(out, "\n# row").unwrap();
+    for (title, width) in titles.iter().zip(&widths) {
+        write!(out, " | {:<width$}", title, width = width).unwrap();
+    }
+    writeln!(out, " |").unwrap();
+    for row in 0..last.map_or(0, |last| last + 1) as usize {
+        write!(out, "{:04} ", row).unwrap();
+        for (column, width) in cells.iter().zip(&widths) {
+            write!(out, " | {:<width$}", column[row], width = width).unwrap();
+        }
+        writeln!(out, " |").unwrap();
+    }
+    Ok(out)
+}
+
+fn number<T: std::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String> {
+    let word = word.ok_or(format!("Missing {}", what))?;
+    word.parse().map_err(|_| format!("Bad {} '{}'", what, word))
+}
+
+fn curve(word: Option<&str>) -> Result<Curve, String> {
+    let word = word.ok_or("Missing curve")?;
+    CURVES.iter().find(|(name, _)| *name == word).map(|(_, c)| *c).ok_or(format!("Bad curve '{}'", word))
+}
+
+fn looped(words: &mut std::str::SplitWhitespace) -> Result<(usize, usize), String> {
+    Ok((number(words.next(), "loop start")?, number(words.next(), "loop end")?))
+}
+
+fn track<'a>(tracks: &'a mut [Track], words: &mut std::str::SplitWhitespace) -> Result<&'a mut Track, String> {
+    let index: usize = number(words.next(), "instrument")?;
+    tracks.get_mut(index).ok_or(format!("No instrument {}", index))
+}
+
+/// Splits a cell into its words, keeping whole the instructions in parentheses
+fn words(cell: &str) -> Result<Vec<&str>, String> {
+    let mut words = vec![];
+    let mut rest = cell.trim_start();
+    while !rest.is_empty() {
+        let (word, after) = match rest.strip_prefix('(') {
+            Some(inside) => inside.split_once(')').ok_or(format!("No ')' closing '{}'", rest))?,
+            None => rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len())),
+        };
+        words.push(word);
+        rest = after.trim_start();
+    }
+    Ok(words)
+}
+
+fn instruction(word: &str) -> Result<InstructionKind, String> {
+    if let Some(velocity) = word.strip_prefix('v') {
+        return Ok(InstructionKind::State { status: Status::On, velocity: number(Some(velocity), "velocity")? });
+    }
+    if word == "off" {
+        return Ok(InstructionKind::State { status: Status::Off, velocity: 0 });
+    }
+    if let Some(note) = parse_note_name(word).or(word.parse().ok()) {
+        return Ok(InstructionKind::Note(note));
+    }
+    let mut args = Args::new(word);
+    let kind = InstructionKind::parse_args(&mut args).and_then(|kind| args.finish().map(|_| kind));
+    kind.map_err(|e| format!("Bad instruction '{}': {}", word, e))
+}
+
+/// Reads a line of the rows into the tracks
+fn row(line: &str, tracks: &mut [Track]) -> Result<(), String> {
+    let mut cells = line.split('|');
+    let row = number(cells.next().map(str::trim), "row")?;
+    let cells = cells.collect::<Vec<_>>();
+    let cells = match cells.split_last() {
+        Some((last, cells)) if last.trim().is_empty() => cells,
+        _ => &cells[..],
+    };
+    if cells.len() != tracks.len() {
+        return Err(format!("The row has {} cells for {} instruments", cells.len(), tracks.len()));
+    }
+    for (cell, track) in cells.iter().zip(tracks) {
+        let mut offset = 0;
+        for word in words(cell)? {
+            match word.strip_prefix('+') {
+                _ if word == "..." => {}
+                Some(ticks) => offset = number(Some(ticks), "offset")?,
+                None => track.instructions.push((row, offset, instruction(word)?)),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a song without touching the app, so a bad file changes nothing. Ticks are at the
+/// `rate` of the file, or at `rate` if it doesn't say
+fn parse(text: &str, mut rate: f32) -> Result<Song, String> {
+    let mut lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty() && !l.trim_start().starts_with('#'));
+    if lines.next().map(|(_, l)| l.trim()) != Some(HEADER) {
+        return Err(String::from("Not a song file"));
+    }
+    let mut tempo = TempoMap::new();
+    let mut tempos = vec![];
+    let mut tracks: Vec<Track> = vec![];
+    for (n, line) in lines {
+        let mut words = line.split_whitespace();
+        let first = words.next().unwrap();
+        let result = match first {
+            _ if first.starts_with(|c: char| c.is_ascii_digit()) => row(line, &mut tracks),
+            "rate" => number(words.next(), "rate").map(|r| rate = r).and_then(|_| match rate > 0.0 {
+                true => Ok(()),
+                false => Err(String::from("The rate has to be above 0")),
+            }),
+            "bpm" => number(words.next(), "BPM").map(|bpm| tempo.set_initial_bpm(bpm)),
+            "signature" => {
+                let (clicks, unit) = words.next().and_then(|s| s.split_once('/')).ok_or("Missing signature, like 4/4")?;
+                tempo.set_signature(number(Some(clicks), "signature")?, number(Some(unit), "signature")?).map_err(String::from)
+            }
+            "tempo" => (|| {
+                let time: u128 = number(words.next(), "tick")?;
+                let bpm: f32 = number(words.next(), "BPM")?;
+                if !(MIN_BPM..=MAX_BPM).contains(&bpm) {
+                    return Err(format!("Tempos go from {} to {} BPM", MIN_BPM, MAX_BPM));
+                }
+                tempos.push((time, Breakpoint { value: bpm, curve: curve(words.next())? }));
+                Ok(())
+            })(),
+            "inst" => (|| {
+                let index: usize = number(words.next(), "instrument")?;
+                let kind = words.next().ok_or("Missing kind of instrument")?;
+                if index != tracks.len() {
+                    return Err(format!("Instrument {} comes after {}", index, tracks.len()));
+                }
+                if kind != "sampler" && instrument::from_name(kind).is_none() {
+                    return Err(format!("No kind of instrument '{}'", kind));
+                }
+                tracks.push(Track { kind: String::from(kind), ..Default::default() });
+                Ok(())
+            })(),
+            "set" => (|| {
+                let track = track(&mut tracks, &mut words)?;
+                let name = track.param(words.next().ok_or("Missing parameter")?)?;
+                track.params.push((name, number(words.next(), "value")?));
+                Ok(())
+            })(),
+            "lane" => (|| {
+                let track = track(&mut tracks, &mut words)?;
+                let name = track.param(words.next().ok_or("Missing parameter")?)?;
+                let time = number(words.next(), "tick")?;
+                let point = Breakpoint { value: number(words.next(), "value")?, curve: curve(words.next())? };
+                track.lanes.entry(name).or_default().insert(time, point);
+                Ok(())
+            })(),
+            "env" => (|| {
+                let track = track(&mut tracks, &mut words)?;
+                let name = track.param(words.next().ok_or("Missing parameter")?)?;
+                let mut env = Envelope::default();
+                env.synced = match words.next() {
+                    Some("beats") => true,
+                    Some("secs") => false,
+                    _ => return Err(String::from("Envelopes are in 'secs' or 'beats'")),
+                };
+                while let Some(option) = words.next() {
+                    match option {
+                        "sustain" => env.sustain = Some(number(words.next(), "sustain point")?),
+                        "loop" => env.looped = Some(looped(&mut words)?),
+                        option => return Err(format!("Unknown envelope option '{}'", option)),
+                    }
+                }
+                track.envelopes.insert(name, env);
+                Ok(())
+            })(),
+            "point" => (|| {
+                let track = track(&mut tracks, &mut words)?;
+                let name = words.next().ok_or("Missing parameter")?;
+                let env = track.envelopes.iter_mut().find(|(n, _)| **n == name).map(|(_, e)| e);
+                let env = env.ok_or(format!("No envelope for '{}' before its points", name))?;
+                let time = number(words.next(), "time")?;
+                env.set_point(Point { time, value: number(words.next(), "value")?, curve: curve(words.next())? });
+                Ok(())
+            })(),
+            "sample" => (|| {
+                let track = track(&mut tracks, &mut words)?;
+                if track.kind != "sampler" {
+                    return Err(String::from("Only samplers have samples"));
+                }
+                let rate = number(words.next(), "sample rate")?;
+                let looped = match words.next() {
+                    Some("loop") => Some(looped(&mut words)?),
+                    Some(word) => return Err(format!("Unexpected '{}'", word)),
+                    None => None,
+                };
+                track.sample = Some((rate, looped));
+                Ok(())
+            })(),
+            "data" => (|| {
+                let track = track(&mut tracks, &mut words)?;
+                for word in words.by_ref() {
+                    let point = u16::from_str_radix(word, 16).map_err(|_| format!("Bad sample point '{}'", word))?;
+                    track.data.push(point as i16 as f32 / 32768.0);
+                }
+                Ok(())
+            })(),
+            first => Err(format!("Unknown line '{}'", first)),
+        };
+        result.map_err(|e| format!("Line {}: {}", n + 1, e))?;
+    }
+    if let Some(i) = tracks.iter().position(|t| t.kind == "sampler" && t.sample.is_none()) {
+        return Err(format!("Sampler {} has no sample", i));
+    }
+    // Data comes after the sample line, so loops are only checked once all of it is in
+    for (i, track) in tracks.iter().enumerate() {
+        if let Some((_, Some((start, end)))) = track.sample {
+            if start >= end || end > track.data.len() {
+                return Err(format!("The loop of sampler {} must end after it starts, within its {} points", i, track.data.len()));
+            }
+        }
+    }
+
+    Ok(Song { rate, tempo, tempos, tracks })
+}
+
+/// Brings the tempo of `app` to the song's, editing only the changes that differ
+fn set_tempo(app: &mut App, song: &Song) {
+    let tempos = song.tempos.iter().map(|(time, change)| (scale(app, song.rate, *time), *change)).collect::<BTreeMap<_, _>>();
+    for (time, change) in app.tempo.changes().points().collect::<Vec<_>>() {
+        if tempos.get(&time) != Some(&change) {
+            app.remove_tempo(time);
+        }
+    }
+    let (clicks, unit) = song.tempo.signature();
+    app.set_initial_bpm(song.tempo.initial_bpm());
+    app.set_signature(clicks, unit).expect("signature was checked");
+    for (time, change) in tempos {
+        if app.tempo.changes().get(time) != Some(change) {
+            app.set_tempo(time, change).expect("tempo was checked");
+        }
+    }
+}
+
+/// Replaces the whole song with one read from text written by [`write`], as one undo step.
+/// Grooves stay with the project file. Returns a summary
+pub fn read(app: &mut App, text: &str) -> Result<String, String> {
+    if app.get_sample_rate() <= 0.0 {
+        return Err(String::from("Songs can't be read before there's a sample rate"));
+    }
+    replace(app, parse(text, app.get_sample_rate())?)
+}
+
+fn replace(app: &mut App, song: Song) -> Result<String, String> {
+    let grooves = (0..song.tracks.len() as u128).map(|i| app.grooves.get(i).cloned()).collect::<Vec<_>>();
+    let mut count = 0;
+    app.begin_group();
+    for index in (0..app.instruments.len()).rev() {
+        app.remove_instrument(index);
+    }
+    set_tempo(app, &song);
+    for (index, (mut track, groove)) in song.tracks.into_iter().zip(grooves).enumerate() {
+        let (instructions, lanes) = track.ticks(app, song.rate);
+        count += instructions.len();
+        let envelopes = std::mem::take(&mut track.envelopes);
+        let instrument = track.instrument(app.get_sample_rate());
+        app.edit(Edit::InsertInstrument { index, instrument, instructions, lanes, envelopes, groove });
+    }
+    app.end_group();
+    app.seek(app.position());
+    Ok(format!("Loaded {} instruments with {} instructions", app.instruments.len(), count))
+}
+
+fn same_envelope(a: Option<&Envelope>, b: Option<&Envelope>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.points() == b.points() && a.sustain == b.sustain && a.looped == b.looped && a.synced == b.synced,
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+/// Brings the song to the text again after its file changed from `before`, as one undo step.
+/// When the instruments are still the same kinds, only what differs in their rows, automation
+/// and envelopes and the settings changed in the file are edited, so playback goes on with its
+/// sounding notes. Otherwise the whole song is replaced like [`read`] does. Returns a summary
+pub fn reload(app: &mut App, before: &str, text: &str) -> Result<String, String> {
+    if app.get_sample_rate() <= 0.0 {
+        return Err(String::from("Songs can't be read before there's a sample rate"));
+    }
+    let song = parse(text, app.get_sample_rate())?;
+    let before = parse(before, app.get_sample_rate()).ok();
+    let kept = song.tracks.len() == app.instruments.len()
+        && song.tracks.iter().zip(&app.instruments).all(|(track, inst)| track.fits(inst.as_ref()));
+    if !kept {
+        return replace(app, song);
+    }
+
+    let mut changes = 0;
+    app.begin_group();
+    set_tempo(app, &song);
+    for (index, mut track) in song.tracks.into_iter().enumerate() {
+        let target = index as u128;
+        let (instructions, mut lanes) = track.ticks(app, song.rate);
+        let old = app.instructions.range(target, 0, u128::MAX);
+        for (time, kind) in old.iter().filter(|i| !instructions.contains(i)) {
+            app.remove_instruction(target, *time, *kind);
+            changes += 1;
+        }
+        for (time, kind) in instructions.iter().filter(|i| !old.contains(i)) {
+            app.insert_instruction(target, *time, *kind);
+            changes += 1;
+        }
+
+        let names = app.automation.lanes(target).map(|(name, _)| name).chain(lanes.keys().copied()).collect::<BTreeSet<_>>();
+        for name in names {
+            let old = app.automation.lane(target, name).map(|l| l.points().collect::<Vec<_>>()).unwrap_or_default();
+            let new = lanes.remove(name).map(|l| l.points().collect::<Vec<_>>()).unwrap_or_default();
+            for (time, _) in old.iter().filter(|p| !new.contains(p)) {
+                app.remove_breakpoint(target, name, *time);
+                changes += 1;
+            }
+            for (time, point) in new.iter().filter(|p| !old.contains(p)) {
+                app.set_breakpoint(target, name, *time, *point).expect("parameters were checked");
+                changes += 1;
+            }
+        }
+
+        let names = app.envelopes.target(target).map(|(name, _)| name).chain(track.envelopes.keys().copied()).collect::<BTreeSet<_>>();
+        for name in names {
+            let new = track.envelopes.remove(name);
+            if !same_envelope(app.envelopes.get(target, name), new.as_ref()) {
+                app.set_envelope(target, name, new).expect("parameters were checked");
+                changes += 1;
+            }
+        }
+
+        // Playing moves settings away from the file, so only those the file changed are set
+        let fresh = fresh(app.instruments[index].as_ref());
+        let set = |track: Option<&Track>, name| track?.params.iter().rev().find(|(n, _)| *n == name).map(|(_, v)| *v);
+        for p in app.instruments[index].params() {
+            let value = set(Some(&track), p.name);
+            if value == set(before.as_ref().and_then(|b| b.tracks.get(index)), p.name) {
+                continue;
+            }
+            if let Some(value) = value.or(fresh.as_ref().and_then(|f| f.get_param(p.name))) {
+                app.apply_settings(target, InstructionKind::Param { name: p.name, value }).expect("parameters were checked");
+                changes += 1;
+            }
+        }
+    }
+    app.end_group();
+    app.chase();
+    Ok(format!("Reloaded with {} changes", changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::automation::{Breakpoint, Curve};
+    use crate::envelope::{Envelope, Point};
+    use crate::instruction::{InstructionKind, Status};
+    use crate::instrument::sampler::{Sample, Sampler};
+    use crate::song::{read, reload, write};
+    use std::sync::Arc;
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        app.tempo.set_initial_bpm(150.0);
+        app.set_tempo(2000, Breakpoint { value: 90.0, curve: Curve::Linear }).unwrap();
+        let sample = Sample { data: vec![0.0, 0.5, -1.0, 0.25], rate: 8363.0, looped: Some((1, 3)) };
+        app.add_instrument(Box::new(Sampler::new(Arc::new(sample))));
+        app.instruments[1].set_param("vel_amp", 0.25).unwrap();
+        app.insert_instruction(0, 0, InstructionKind::Note(60));
+        app.insert_instruction(0, 0, InstructionKind::State { status: Status::On, velocity: 90 });
+        app.insert_instruction(0, 130, InstructionKind::State { status: Status::Off, velocity: 0 });
+        app.insert_instruction(0, 130, InstructionKind::Param { name: "volume", value: 0.5 });
+        app.insert_instruction(2, 2500, InstructionKind::Note(40));
+        app.insert_instruction(2, 2500, InstructionKind::State { status: Status::On, velocity: 127 });
+        app.set_breakpoint(1, "freq", 300, Breakpoint { value: 220.0, curve: Curve::Exponential }).unwrap();
+        let mut env = Envelope::default();
+        (env.sustain, env.synced) = (Some(1), true);
+        env.set_point(Point { time: 0.0, value: 1.0, curve: Curve::Linear });
+        env.set_point(Point { time: 0.5, value: 0.2, curve: Curve::Step });
+        app.set_envelope(0, "volume", Some(env)).unwrap();
+        let text = write(&app).unwrap();
+        assert!(text.contains("0001  | +30 off (param volume 0.5) |"), "{}", text);
+
+        let mut loaded = App::new();
+        loaded.set_sample_rates(1000.0);
+        assert_eq!(read(&mut loaded, &text).unwrap(), "Loaded 3 instruments with 6 instructions");
+        assert_eq!(write(&loaded).unwrap(), text);
+        assert!(loaded.instructions.has(0, 130, InstructionKind::State { status: Status::Off, velocity: 0 }));
+        assert_eq!(loaded.instruments[2].sample().unwrap().data, vec![0.0, 0.5, -1.0, 0.25]);
+        // One undo brings the old song back
+        loaded.undo();
+        assert_eq!(loaded.instruments.len(), 2);
+        assert_eq!(loaded.get_bpm(), 120);
+
+        assert_eq!(read(&mut loaded, "fmangroove song\ninst 0 kazoo").unwrap_err(), "Line 2: No kind of instrument 'kazoo'");
+        let error = "The loop of sampler 2 must end after it starts, within its 4 points";
+        assert_eq!(read(&mut loaded, &text.replace("loop 1 3", "loop 2 2")).unwrap_err(), error);
+        assert_eq!(read(&mut loaded, &text.replace("loop 1 3", "loop 1 5")).unwrap_err(), error);
+    }
+
+    #[test]
+    fn reloads_without_cutting_notes() {
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        let text = "fmangroove song\ninst 0 synth\ninst 1 osc\n0000 | C-4 v127 | ... |\n0008 | off | ... |\n";
+        read(&mut app, text).unwrap();
+        app.play();
+        for _ in 0..100 {
+            app.tick_all();
+        }
+        let edited = text.replace("inst 0 synth", "inst 0 synth\nset 0 volume 0.5").replace("0008 | off", "0004 | E-4 v127 | ... |\n0008 | off");
+        assert_eq!(reload(&mut app, text, &edited).unwrap(), "Reloaded with 3 changes");
+        assert_eq!(app.position(), 100);
+        assert!((0..10).map(|_| app.tick_all().0.abs()).sum::<f32>() > 0.0);
+        assert!(app.instructions.has(0, 500, InstructionKind::Note(55)));
+        assert_eq!(app.instruments[0].get_param("volume"), Some(0.5));
+
+        // Another kind of instrument can't stay, so the song is replaced
+        assert_eq!(reload(&mut app, &edited, &text.replace("inst 1 osc", "inst 1 synth")).unwrap(), "Loaded 2 instruments with 3 instructions");
+    }
+}
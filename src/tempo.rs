@@ -0,0 +1,263 @@
+use crate::automation::{Breakpoint, Curve, Lane};
+
+pub const DEFAULT_BPM: f32 = 120.0;
+pub const MIN_BPM: f32 = 1.0;
+pub const MAX_BPM: f32 = 999.0;
+/// Rows of the pattern in a beat, each a sixteenth note
+pub const ROWS_PER_BEAT: u128 = 4;
+/// Note values a time signature can count in, as in the lower number of 6/8
+pub const SIGNATURE_UNITS: [u32; 5] = [1, 2, 4, 8, 16];
+/// Rows in a pattern unless a pattern loop says otherwise, four bars
+pub const PATTERN_ROWS: u128 = 64;
+
+/// A stretch of time over which the tempo follows one curve
+struct Segment {
+    start: u128,
+    /// Beats played in the segments before this one
+    beats_before: f64,
+    /// `None` for the last segment, which goes on forever
+    end: Option<u128>,
+    from: f32,
+    to: f32,
+    curve: Curve,
+}
+
+impl Segment {
+    fn seconds(&self, sample_rate: f64) -> Option<f64> {
+        self.end.map(|end| (end - self.start) as f64 / sample_rate)
+    }
+
+    /// Beats played in the first `elapsed` seconds of the segment
+    fn beats(&self, elapsed: f64, sample_rate: f64) -> f64 {
+        let (from, to) = (self.from as f64, self.to as f64);
+        let Some(length) = self.seconds(sample_rate).filter(|l| *l > 0.0) else {
+            return from * elapsed / 60.0;
+        };
+        let x = elapsed / length;
+        match self.curve {
+            Curve::Step => from * elapsed / 60.0,
+            Curve::Linear => (from + from + (to - from) * x) / 2.0 * elapsed / 60.0,
+            Curve::Exponential if (to / from - 1.0).abs() > 1e-9 => {
+                let ratio = to / from;
+                from * length / 60.0 * (ratio.powf(x) - 1.0) / ratio.ln()
+            }
+            Curve::Exponential => from * elapsed / 60.0,
+        }
+    }
+}
+
+/// Tempo of the song over time, as breakpoints in BPM on a lane. Changes are either sudden or
+/// glide to the next change, for accelerandos and ritardandos
+pub struct TempoMap {
+    /// Tempo before the first change, or throughout if there are none
+    bpm: f32,
+    changes: Lane,
+    /// Clicks in a bar and the note value of a click, like 3 and 4 for 3/4
+    signature: (u32, u32),
+    sample_rate: f32,
+    /// Worked out again whenever the tempo changes, as playback looks them up every sample
+    segments: Vec<Segment>,
+    /// Counts changes to the map, so what's worked out from it can tell when it's stale
+    version: u64,
+}
+
+impl TempoMap {
+    pub fn new() -> Self {
+        let mut map = Self { bpm: DEFAULT_BPM, changes: Lane::default(), signature: (4, 4), sample_rate: 0.0, segments: vec![], version: 0 };
+        map.rebuild();
+        map
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.rebuild();
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn initial_bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    pub fn set_initial_bpm(&mut self, bpm: f32) {
+        self.bpm = bpm.clamp(MIN_BPM, MAX_BPM);
+        self.rebuild();
+    }
+
+    pub fn signature(&self) -> (u32, u32) {
+        self.signature
+    }
+
+    pub fn set_signature(&mut self, clicks: u32, unit: u32) -> Result<(), &'static str> {
+        check_signature(clicks, unit)?;
+        self.signature = (clicks, unit);
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Length of one click of the time signature in beats, which are quarter notes
+    pub fn click_beats(&self) -> f64 {
+        4.0 / self.signature.1 as f64
+    }
+
+    /// Bar and click of the time signature a tick is in, both counting from 0
+    pub fn bar_at(&self, tick: u128) -> (u128, u128) {
+        let click = (self.beat_at(tick) / self.click_beats() + 1e-6).floor() as u128;
+        (click / self.signature.0 as u128, click % self.signature.0 as u128)
+    }
+
+    pub fn changes(&self) -> &Lane {
+        &self.changes
+    }
+
+    pub fn insert(&mut self, time: u128, change: Breakpoint) -> Option<Breakpoint> {
+        let before = self.changes.insert(time, change);
+        self.rebuild();
+        before
+    }
+
+    pub fn remove(&mut self, time: u128) -> Option<Breakpoint> {
+        let before = self.changes.remove(time);
+        self.rebuild();
+        before
+    }
+
+    /// Tempo in BPM at a tick
+    pub fn bpm_at(&self, tick: u128) -> f32 {
+        self.changes.value_at(tick).unwrap_or(self.bpm)
+    }
+
+    fn rebuild(&mut self) {
+        self.version += 1;
+        let points = self.changes.points().collect::<Vec<_>>();
+        let mut segments = vec![Segment {
+            start: 0,
+            beats_before: 0.0,
+            end: Some(points.first().map_or(u128::MAX, |(time, _)| *time)),
+            from: self.bpm,
+            to: self.bpm,
+            curve: Curve::Step,
+        }];
+        for (i, (time, point)) in points.iter().enumerate() {
+            let next = points.get(i + 1);
+            segments.push(Segment {
+                start: *time,
+                beats_before: 0.0,
+                end: next.map(|(time, _)| *time),
+                from: point.value,
+                to: next.map_or(point.value, |(_, next)| next.value),
+                curve: point.curve,
+            });
+        }
+        if points.is_empty() {
+            segments[0].end = None;
+        }
+        if self.sample_rate > 0.0 {
+            let sample_rate = self.sample_rate as f64;
+            for i in 1..segments.len() {
+                let before = &segments[i - 1];
+                let length = before.seconds(sample_rate).expect("only the last segment never ends");
+                segments[i].beats_before = before.beats_before + before.beats(length, sample_rate);
+            }
+        }
+        self.segments = segments;
+    }
+
+    /// Beats played from the start of the song until a tick
+    pub fn beat_at(&self, tick: u128) -> f64 {
+        if self.sample_rate <= 0.0 || tick == 0 {
+            return 0.0;
+        }
+        let sample_rate = self.sample_rate as f64;
+        let segment = &self.segments[self.segments.partition_point(|s| s.start < tick) - 1];
+        let end = segment.end.map_or(tick, |end| end.min(tick));
+        segment.beats_before + segment.beats((end - segment.start) as f64 / sample_rate, sample_rate)
+    }
+
+    /// The tick a beat falls on, the inverse of [`TempoMap::beat_at`]
+    pub fn tick_at(&self, beat: f64) -> u128 {
+        if self.sample_rate <= 0.0 || beat <= 0.0 {
+            return 0;
+        }
+        let sample_rate = self.sample_rate as f64;
+        let segment = &self.segments[self.segments.partition_point(|s| s.beats_before < beat) - 1];
+        let remaining = beat - segment.beats_before;
+        let elapsed = match (segment.curve, segment.seconds(sample_rate)) {
+            (Curve::Linear | Curve::Exponential, Some(length)) => {
+                // Beats only ever grow with time, so halving the interval always converges
+                let (mut low, mut high) = (0.0, length);
+                for _ in 0..64 {
+                    let middle = (low + high) / 2.0;
+                    match segment.beats(middle, sample_rate) < remaining {
+                        true => low = middle,
+                        false => high = middle,
+                    }
+                }
+                (low + high) / 2.0
+            }
+            _ => remaining * 60.0 / segment.from as f64,
+        };
+        segment.start + (elapsed * sample_rate).round() as u128
+    }
+
+    /// The row of the pattern a tick is in
+    pub fn row_at(&self, tick: u128) -> u128 {
+        // A little leeway so rows starting on rounded ticks still count as reached
+        (self.beat_at(tick) * ROWS_PER_BEAT as f64 + 1e-6).floor() as u128
+    }
+
+    pub fn row_tick(&self, row: u128) -> u128 {
+        self.tick_at(row as f64 / ROWS_PER_BEAT as f64)
+    }
+}
+
+pub fn check_signature(clicks: u32, unit: u32) -> Result<(), &'static str> {
+    if !SIGNATURE_UNITS.contains(&unit) {
+        return Err("Time signatures count in whole, half, quarter, eighth or sixteenth notes");
+    }
+    if !(1..=64).contains(&clicks) {
+        return Err("Bars must have from 1 to 64 clicks");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::automation::{Breakpoint, Curve};
+    use crate::tempo::TempoMap;
+
+    #[test]
+    fn converts_at_a_steady_tempo() {
+        let mut tempo = TempoMap::new();
+        tempo.set_sample_rate(1000.0);
+        assert_eq!(tempo.beat_at(1500), 3.0);
+        assert_eq!(tempo.tick_at(3.0), 1500);
+        assert_eq!(tempo.row_tick(1), 125);
+        assert_eq!(tempo.row_at(249), 1);
+    }
+
+    #[test]
+    fn follows_changes_and_ramps() {
+        let mut tempo = TempoMap::new();
+        tempo.set_sample_rate(1000.0);
+        // 120 BPM for a second, 60 BPM for a second, then speeding up to 180 BPM over two
+        tempo.insert(1000, Breakpoint { value: 60.0, curve: Curve::Step });
+        tempo.insert(2000, Breakpoint { value: 60.0, curve: Curve::Linear });
+        tempo.insert(4000, Breakpoint { value: 180.0, curve: Curve::Step });
+        assert_eq!(tempo.bpm_at(1500), 60.0);
+        assert_eq!(tempo.bpm_at(3000), 120.0);
+        assert_eq!(tempo.beat_at(2000), 3.0);
+        assert!((tempo.beat_at(4000) - 7.0).abs() < 1e-9);
+        assert!((tempo.beat_at(5000) - 10.0).abs() < 1e-9);
+        for beat in [0.5, 2.5, 4.0, 6.0, 9.0] {
+            assert!((tempo.beat_at(tempo.tick_at(beat)) - beat).abs() < 0.01, "beat {}", beat);
+        }
+
+        tempo.insert(2000, Breakpoint { value: 60.0, curve: Curve::Exponential });
+        let middle = tempo.tick_at(tempo.beat_at(3000));
+        assert_eq!(middle, 3000);
+        assert!(tempo.bpm_at(3000) < 120.0);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::app::App;
+use crate::automation::{Breakpoint, Curve};
+use crate::instruction::{InstructionKind, Status, MAX_VELOCITY};
+use crate::instrument::sampler::{Sample, Sampler};
+use crate::tempo::{MAX_BPM, MIN_BPM};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+mod protracker;
+mod xm;
+
+/// Rate samples are played at for C-4 in XM terms, which is middle C
+const BASE_RATE: f32 = 8363.0;
+/// Notes of modules are numbered from 1, C-0, to 96, with this one letting go of the note
+const KEY_OFF: u8 = 97;
+/// What the notes of modules are moved by to be MIDI notes, making C-4 middle C
+const NOTE_OFFSET: u16 = 11;
+
+/// What a channel plays on a row, everything 0 when there's nothing
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct Cell {
+    pub note: u8,
+    /// Counting from 1
+    pub instrument: u8,
+    /// Volume column of XM modules
+    pub volume: u8,
+    pub effect: u8,
+    pub param: u8,
+}
+
+pub struct ModuleSample {
+    pub sample: Arc<Sample>,
+    /// From 0 to 64
+    pub volume: u8,
+    /// From -1 to 1, or `None` to leave it to the channel
+    pub pan: Option<f32>,
+}
+
+pub struct ModuleInstrument {
+    pub samples: Vec<ModuleSample>,
+    /// Which sample each note plays. Empty when there's only one
+    pub keymap: Vec<u8>,
+}
+
+/// A tracker module, read from a MOD or an XM file
+pub struct Module {
+    pub name: String,
+    /// Where each channel is heard, from -1 to 1
+    pub pans: Vec<f32>,
+    /// Ticks of the module's own clock a row lasts
+    pub speed: u8,
+    pub bpm: u8,
+    /// Patterns in the order they're played
+    pub order: Vec<usize>,
+    /// Rows of cells, one for each channel
+    pub patterns: Vec<Vec<Vec<Cell>>>,
+    pub instruments: Vec<ModuleInstrument>,
+}
+
+fn bytes(data: &[u8], start: usize, length: usize) -> Result<&[u8], String> {
+    data.get(start..start + length).ok_or(String::from("The module ends early"))
+}
+
+fn text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim_end_matches(['\0', ' ']).to_string()
+}
+
+/// Tempo of rows `speed` ticks long at the module's BPM, with rows as sixteenth notes. Six
+/// ticks a row is where the module's BPM means what it says
+fn bpm(speed: u8, bpm: u8) -> f32 {
+    bpm as f32 * 6.0 / speed as f32
+}
+
+/// How an effect is written in trackers, with the extended ones by their first digit
+fn effect_name(effect: u8, param: u8) -> String {
+    match effect {
+        0x0E => format!("E{:X}", param >> 4),
+        0..=9 => effect.to_string(),
+        _ => ((b'A' + effect - 10) as char).to_string(),
+    }
+}
+
+/// What a module channel has going on, across rows
+#[derive(Default)]
+struct Channel {
+    instrument: u8,
+    target: Option<u128>,
+    sounding: bool,
+    volume: u8,
+}
+
+/// Turns the cells of a module into instructions, on a sampler for each sample every channel
+/// plays, and keeps count of what had to be left out
+struct Importer<'a> {
+    module: &'a Module,
+    targets: HashMap<(usize, usize, usize), u128>,
+    /// Last volume and start sent to each target, so they're only sent when they change
+    volumes: HashMap<u128, u8>,
+    starts: HashMap<u128, f32>,
+    /// Ticks of the module's clock in the row being played
+    speed: u8,
+    notes: usize,
+    skipped: BTreeMap<String, usize>,
+}
+
+impl Importer<'_> {
+    fn target(&mut self, app: &mut App, channel: usize, instrument: usize, sample: usize) -> u128 {
+        if let Some(target) = self.targets.get(&(channel, instrument, sample)) {
+            return *target;
+        }
+        let target = app.instruments.len() as u128;
+        app.add_instrument(Box::new(Sampler::new(self.module.instruments[instrument].samples[sample].sample.clone())));
+        app.insert_instruction(target, 0, InstructionKind::Param { name: "pan", value: self.module.pans[channel] });
+        self.targets.insert((channel, instrument, sample), target);
+        target
+    }
+
+    fn release(&mut self, app: &mut App, channel: &mut Channel, tick: u128) {
+        if let (Some(target), true) = (channel.target, channel.sounding) {
+            app.insert_instruction(target, tick, InstructionKind::State { status: Status::Off, velocity: 0 });
+        }
+        channel.sounding = false;
+    }
+
+    fn skip(&mut self, what: String) {
+        *self.skipped.entry(what).or_insert(0) += 1;
+    }
+
+    /// Plays a cell of a row starting at `tick` and lasting `length` ticks
+    fn cell(&mut self, app: &mut App, index: usize, channel: &mut Channel, cell: Cell, tick: u128, length: u128) {
+        let (effect, param, speed) = (cell.effect, cell.param, self.speed);
+        let sub_tick = |ticks: u8| tick + length * ticks as u128 / speed as u128;
+        let at = match (effect, param >> 4) {
+            (0x0E, 0x0D) => sub_tick(param & 0x0F),
+            _ => tick,
+        };
+        if cell.instrument != 0 {
+            channel.instrument = cell.instrument;
+        }
+
+        if cell.note == KEY_OFF || effect == 0x14 {
+            self.release(app, channel, at);
+        } else if cell.note > 0 {
+            let instrument = channel.instrument as usize;
+            let found = self.module.instruments.get(instrument.wrapping_sub(1)).and_then(|inst| {
+                let sample = inst.keymap.get(cell.note as usize - 1).copied().unwrap_or(0) as usize;
+                inst.samples.get(sample).map(|s| (sample, s.volume, s.pan))
+            });
+            if let Some((sample, volume, pan)) = found {
+                let target = self.target(app, index, instrument - 1, sample);
+                if channel.target != Some(target) {
+                    self.release(app, channel, at);
+                }
+                if cell.instrument != 0 {
+                    channel.volume = volume;
+                    if let Some(pan) = pan {
+                        app.insert_instruction(target, at, InstructionKind::Param { name: "pan", value: pan });
+                    }
+                }
+                let start = match effect {
+                    0x09 => (param as f32 * 256.0 / self.module.instruments[instrument - 1].samples[sample].sample.data.len().max(1) as f32).min(1.0),
+                    _ => 0.0,
+                };
+                if self.starts.get(&target).copied().unwrap_or(0.0) != start {
+                    app.insert_instruction(target, at, InstructionKind::Param { name: "start", value: start });
+                    self.starts.insert(target, start);
+                }
+                app.insert_instruction(target, at, InstructionKind::Note(cell.note as u16 + NOTE_OFFSET));
+                app.insert_instruction(target, at, InstructionKind::State { status: Status::On, velocity: MAX_VELOCITY });
+                channel.target = Some(target);
+                channel.sounding = true;
+                self.notes += 1;
+            }
+        }
+
+        // Slides are made in steps on every tick of the module's clock but the first, so here
+        // they're made all at once at the start of the row
+        let slides = speed.saturating_sub(1);
+        match cell.volume {
+            0 => {}
+            0x10..=0x50 => channel.volume = cell.volume - 0x10,
+            0x60..=0x6F => channel.volume = channel.volume.saturating_sub((cell.volume & 0x0F).saturating_mul(slides)),
+            0x70..=0x7F => channel.volume = channel.volume.saturating_add((cell.volume & 0x0F).saturating_mul(slides)).min(64),
+            0x80..=0x8F => channel.volume = channel.volume.saturating_sub(cell.volume & 0x0F),
+            0x90..=0x9F => channel.volume = (channel.volume + (cell.volume & 0x0F)).min(64),
+            0xC0..=0xCF => {
+                if let Some(target) = channel.target {
+                    let pan = (cell.volume & 0x0F) as f32 / 15.0 * 2.0 - 1.0;
+                    app.insert_instruction(target, at, InstructionKind::Param { name: "pan", value: pan });
+                }
+            }
+            volume => self.skip(format!("volume column {:X}x", volume >> 4)),
+        }
+        match (effect, param) {
+            (0x00, 0) => {}
+            (0x0C, volume) => channel.volume = volume.min(64),
+            (0x0A, slide) => match (slide >> 4, slide & 0x0F) {
+                (0, down) => channel.volume = channel.volume.saturating_sub(down.saturating_mul(slides)),
+                (up, _) => channel.volume = channel.volume.saturating_add(up.saturating_mul(slides)).min(64),
+            },
+            (0x08, pan) => {
+                if let Some(target) = channel.target {
+                    app.insert_instruction(target, at, InstructionKind::Param { name: "pan", value: pan as f32 / 255.0 * 2.0 - 1.0 });
+                }
+            }
+            // Played from the sample offset above, and the song order and tempo are followed
+            // before the row is played
+            (0x09 | 0x0B | 0x0D | 0x0F | 0x14, _) => {}
+            (0x0E, param) => match param >> 4 {
+                0x0A => channel.volume = (channel.volume + (param & 0x0F)).min(64),
+                0x0B => channel.volume = channel.volume.saturating_sub(param & 0x0F),
+                0x0C => self.release(app, channel, sub_tick(param & 0x0F)),
+                0x0D => {}
+                _ => self.skip(effect_name(effect, param)),
+            },
+            _ => self.skip(effect_name(effect, param)),
+        }
+
+        if let Some(target) = channel.target {
+            if self.volumes.get(&target) != Some(&channel.volume) {
+                let value = channel.volume as f32 / 64.0;
+                app.insert_instruction(target, at, InstructionKind::Param { name: "volume", value });
+                self.volumes.insert(target, channel.volume);
+            }
+        }
+    }
+}
+
+/// Reads a MOD or XM module into the song as one undo step, playing its order list from the
+/// start of the song on new sampler instruments. Returns a summary, with the effects that
+/// couldn't be translated
+pub fn import(app: &mut App, data: &[u8]) -> Result<String, String> {
+    let module = match data.starts_with(xm::ID) {
+        true => xm::parse(data)?,
+        false => protracker::parse(data)?,
+    };
+    if app.get_sample_rate() <= 0.0 {
+        return Err(String::from("Notes can't be placed before there's a sample rate"));
+    }
+
+    let mut importer = Importer { module: &module, targets: HashMap::new(), volumes: HashMap::new(), starts: HashMap::new(), speed: 1, notes: 0, skipped: BTreeMap::new() };
+    let mut channels = module.pans.iter().map(|_| Channel::default()).collect::<Vec<_>>();
+    let (mut speed, mut tempo) = (module.speed.max(1), module.bpm);
+    let first = app.instruments.len();
+    app.begin_group();
+    app.set_initial_bpm(bpm(speed, tempo));
+
+    let mut row = 0;
+    let (mut position, mut pattern_row) = (0, 0);
+    // Jumps back would play forever, so the song ends the first time a row comes round again
+    let mut played = HashSet::new();
+    while let Some(pattern) = module.order.get(position).map(|p| module.patterns.get(*p)) {
+        let Some(cells) = pattern.and_then(|pattern| pattern.get(pattern_row)) else {
+            (position, pattern_row) = (position + 1, 0);
+            continue;
+        };
+        if !played.insert((position, pattern_row)) {
+            break;
+        }
+
+        let (mut jump, mut stop) = (None, None);
+        let before = bpm(speed, tempo);
+        for cell in cells {
+            match (cell.effect, cell.param) {
+                (0x0F, 0) => {}
+                (0x0F, param) if param < 0x20 => speed = param,
+                (0x0F, param) => tempo = param,
+                (0x0B, param) => jump = Some(param as usize),
+                // Rows to break to are written in decimal
+                (0x0D, param) => stop = Some((param >> 4) as usize * 10 + (param & 0x0F) as usize),
+                _ => {}
+            }
+        }
+        if bpm(speed, tempo) != before {
+            let value = bpm(speed, tempo);
+            match row {
+                0 => app.set_initial_bpm(value),
+                _ => {
+                    let change = Breakpoint { value: value.clamp(MIN_BPM, MAX_BPM), curve: Curve::Step };
+                    app.set_tempo(app.tempo.row_tick(row), change).expect("tempo is in range");
+                }
+            }
+        }
+
+        importer.speed = speed;
+        let tick = app.tempo.row_tick(row);
+        let length = app.tempo.row_tick(row + 1) - tick;
+        for (index, (cell, channel)) in cells.iter().zip(channels.iter_mut()).enumerate() {
+            importer.cell(app, index, channel, *cell, tick, length);
+        }
+        row += 1;
+
+        (position, pattern_row) = match (jump, stop) {
+            (None, None) => match pattern_row + 1 < pattern.map_or(0, Vec::len) {
+                true => (position, pattern_row + 1),
+                false => (position + 1, 0),
+            },
+            (jump, stop) => (jump.unwrap_or(position + 1), stop.unwrap_or(0)),
+        };
+        let next_length = module.order.get(position).and_then(|p| module.patterns.get(*p)).map_or(0, Vec::len);
+        if pattern_row >= next_length {
+            pattern_row = 0;
+        }
+    }
+    let end = app.tempo.row_tick(row);
+    for channel in channels.iter_mut() {
+        importer.release(app, channel, end);
+    }
+    app.end_group();
+
+    let mut summary = format!("Imported '{}': {} notes onto {} new instruments", module.name, importer.notes, app.instruments.len() - first);
+    if !importer.skipped.is_empty() {
+        let skipped = importer.skipped.iter().map(|(name, count)| format!("{} ×{}", name, count)).collect::<Vec<_>>();
+        summary += &format!("; couldn't translate effects {}", skipped.join(", "));
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::instruction::{InstructionKind, Status};
+    use crate::tracker::import;
+
+    #[test]
+    fn imports_a_protracker_module() {
+        let mut file = vec![0; 1084];
+        file[..4].copy_from_slice(b"test");
+        // A looped sample of eight points at full volume, and a song of one pattern
+        file[42..44].copy_from_slice(&4u16.to_be_bytes());
+        file[45] = 64;
+        file[48..50].copy_from_slice(&4u16.to_be_bytes());
+        file[950] = 1;
+        file[1080..1084].copy_from_slice(b"M.K.");
+        let mut pattern = vec![0; 64 * 16];
+        let mut cell = |row: usize, channel: usize, period: u16, sample: u8, effect: u8, param: u8| {
+            let at = (row * 4 + channel) * 4;
+            pattern[at] = (sample & 0xF0) | (period >> 8) as u8;
+            pattern[at + 1] = period as u8;
+            pattern[at + 2] = (sample << 4) | effect;
+            pattern[at + 3] = param;
+        };
+        cell(0, 0, 428, 1, 0x0C, 32);
+        cell(0, 1, 0, 0, 0x0F, 3);
+        cell(1, 0, 0, 0, 0x00, 0x37);
+        cell(2, 0, 214, 0, 0x00, 0);
+        cell(3, 1, 0, 0, 0x0D, 0);
+        file.extend(pattern);
+        file.extend([64, 127, 64, 0, 192, 128, 192, 0]);
+
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        let summary = import(&mut app, &file).unwrap();
+        assert_eq!(summary, "Imported 'test': 2 notes onto 1 new instruments; couldn't translate effects 0 ×1");
+        assert_eq!(app.instruments[2].name(), "sampler");
+        // Three ticks a row at 125 BPM go twice as fast as six, so rows last 60 ms
+        assert_eq!(app.get_bpm(), 250);
+        assert!(app.instructions.has(2, 0, InstructionKind::Note(60)));
+        assert!(app.instructions.has(2, 0, InstructionKind::Param { name: "volume", value: 0.5 }));
+        assert!(app.instructions.has(2, 0, InstructionKind::Param { name: "pan", value: -0.5 }));
+        assert!(app.instructions.has(2, 120, InstructionKind::Note(72)));
+        // The pattern breaks after its fourth row, ending the song
+        assert!(app.instructions.has(2, 240, InstructionKind::State { status: Status::Off, velocity: 0 }));
+
+        app.play();
+        let heard = (0..50).any(|_| app.tick_all() != (0.0, 0.0));
+        assert!(heard);
+
+        assert!(app.undo());
+        assert_eq!(app.instruments.len(), 2);
+        assert_eq!(app.get_bpm(), 120);
+    }
+}
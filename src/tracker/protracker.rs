@@ -0,0 +1,80 @@
+use crate::instrument::sampler::Sample;
+use crate::tracker::{bytes, text, Cell, Module, ModuleInstrument, ModuleSample, BASE_RATE};
+use std::sync::Arc;
+
+/// Period of the note played at a sample's own rate, C-2 in ProTracker's terms
+const BASE_PERIOD: f32 = 428.0;
+
+/// Channels of a module, going by the tag after the sample headers
+fn channels(tag: &[u8]) -> Option<usize> {
+    match tag {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"FLT8" => Some(8),
+        [n, b'C', b'H', b'N'] if n.is_ascii_digit() => Some((n - b'0') as usize),
+        [a, b, b'C', b'H'] if a.is_ascii_digit() && b.is_ascii_digit() => Some(((a - b'0') * 10 + b - b'0') as usize),
+        _ => None,
+    }
+}
+
+/// The note of an Amiga period, numbered like notes of XM modules
+fn note(period: u16) -> u8 {
+    match period {
+        0 => 0,
+        period => (49.0 + 12.0 * (BASE_PERIOD / period as f32).log2()).round().clamp(1.0, 96.0) as u8,
+    }
+}
+
+/// Reads a ProTracker module of 31 samples
+pub fn parse(data: &[u8]) -> Result<Module, String> {
+    let channels = bytes(data, 1080, 4).ok().and_then(channels).filter(|c| *c > 0).ok_or("Not a MOD or XM module")?;
+    let name = text(bytes(data, 0, 20)?);
+
+    let mut headers = vec![];
+    for i in 0..31 {
+        let header = bytes(data, 20 + i * 30, 30)?;
+        let word = |at: usize| u16::from_be_bytes([header[at], header[at + 1]]) as usize * 2;
+        // Finetune is a signed nibble, in eighths of a semitone
+        let finetune = ((header[24] & 0x0F) as i8) << 4 >> 4;
+        let (loop_start, loop_length) = (word(26), word(28));
+        headers.push((word(22), finetune, header[25].min(64), loop_start, loop_length));
+    }
+    let length = (bytes(data, 950, 1)?[0] as usize).clamp(1, 128);
+    let table = bytes(data, 952, 128)?;
+    let order = table[..length].iter().map(|p| *p as usize).collect::<Vec<_>>();
+
+    let pattern_count = *table.iter().max().unwrap() as usize + 1;
+    let mut patterns = vec![];
+    let mut offset = 1084;
+    for _ in 0..pattern_count {
+        let pattern = bytes(data, offset, 64 * channels * 4)?;
+        let rows = pattern.chunks(channels * 4).map(|row| {
+            row.chunks(4).map(|cell| Cell {
+                note: note(u16::from_be_bytes([cell[0] & 0x0F, cell[1]])),
+                instrument: (cell[0] & 0xF0) | (cell[2] >> 4),
+                volume: 0,
+                effect: cell[2] & 0x0F,
+                param: cell[3],
+            }).collect()
+        }).collect();
+        patterns.push(rows);
+        offset += 64 * channels * 4;
+    }
+
+    let mut instruments = vec![];
+    for (length, finetune, volume, loop_start, loop_length) in headers {
+        // Files cut short still load what's there of their last samples
+        let available = data.get(offset..).unwrap_or_default();
+        let raw = &available[..length.min(available.len())];
+        offset += length;
+        let data = raw.iter().map(|b| *b as i8 as f32 / 128.0).collect::<Vec<_>>();
+        let looped = Some((loop_start, (loop_start + loop_length).min(data.len())))
+            .filter(|(start, end)| loop_length > 2 && start < end);
+        let rate = BASE_RATE * 2f32.powf(finetune as f32 / 96.0);
+        let sample = Arc::new(Sample { data, rate, looped });
+        instruments.push(ModuleInstrument { samples: vec![ModuleSample { sample, volume, pan: None }], keymap: vec![] });
+    }
+
+    // Amiga channels go left, right, right, left, kept off the far sides to spare headphones
+    let pans = (0..channels).map(|c| if c % 4 == 0 || c % 4 == 3 { -0.5 } else { 0.5 }).collect();
+    Ok(Module { name, pans, speed: 6, bpm: 125, order, patterns, instruments })
+}
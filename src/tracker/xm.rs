@@ -0,0 +1,134 @@
+use crate::instrument::sampler::Sample;
+use crate::tracker::{bytes, text, Cell, Module, ModuleInstrument, ModuleSample, BASE_RATE};
+use std::sync::Arc;
+
+pub const ID: &[u8] = b"Extended Module: ";
+
+fn u16_at(data: &[u8], at: usize) -> Result<usize, String> {
+    let b = bytes(data, at, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]) as usize)
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<usize, String> {
+    let b = bytes(data, at, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+}
+
+/// Cells of a pattern, packed so that a byte with the top bit set says which of the five
+/// fields follow
+fn unpack(data: &[u8], rows: usize, channels: usize) -> Result<Vec<Vec<Cell>>, String> {
+    let mut at = 0;
+    let mut next = || -> Result<u8, String> {
+        at += 1;
+        Ok(bytes(data, at - 1, 1)?[0])
+    };
+    let mut pattern = vec![];
+    for _ in 0..rows {
+        let mut row = vec![];
+        for _ in 0..channels {
+            if data.is_empty() {
+                row.push(Cell::default());
+                continue;
+            }
+            let first = next()?;
+            let (flags, note) = match first & 0x80 {
+                0 => (0x1E, first),
+                _ => (first, if first & 1 != 0 { next()? } else { 0 }),
+            };
+            let mut field = |bit: u8| if flags & bit != 0 { next() } else { Ok(0) };
+            row.push(Cell { note, instrument: field(2)?, volume: field(4)?, effect: field(8)?, param: field(16)? });
+        }
+        pattern.push(row);
+    }
+    Ok(pattern)
+}
+
+/// Sample points stored as the difference from the one before
+fn decode(raw: &[u8], sixteen_bits: bool) -> Vec<f32> {
+    match sixteen_bits {
+        true => raw.chunks_exact(2).scan(0i16, |old, b| {
+            *old = old.wrapping_add(i16::from_le_bytes([b[0], b[1]]));
+            Some(*old as f32 / 32768.0)
+        }).collect(),
+        false => raw.iter().scan(0i8, |old, b| {
+            *old = old.wrapping_add(*b as i8);
+            Some(*old as f32 / 128.0)
+        }).collect(),
+    }
+}
+
+/// Reads a FastTracker 2 extended module
+pub fn parse(data: &[u8]) -> Result<Module, String> {
+    if !data.starts_with(ID) {
+        return Err(String::from("Not a MOD or XM module"));
+    }
+    let name = text(bytes(data, 17, 20)?);
+    let length = u16_at(data, 64)?.min(256);
+    let channels = u16_at(data, 68)?;
+    let pattern_count = u16_at(data, 70)?;
+    let instrument_count = u16_at(data, 72)?;
+    let speed = u16_at(data, 76)?.clamp(1, 31) as u8;
+    let bpm = u16_at(data, 78)?.clamp(32, 255) as u8;
+    let order = bytes(data, 80, length)?.iter().map(|p| *p as usize).collect();
+    if channels == 0 || channels > 64 {
+        return Err(String::from("The module has a bad number of channels"));
+    }
+
+    let mut at = 60 + u32_at(data, 60)?;
+    let mut patterns = vec![];
+    for _ in 0..pattern_count {
+        let rows = u16_at(data, at + 5)?;
+        let packed = u16_at(data, at + 7)?;
+        at += u32_at(data, at)?;
+        patterns.push(unpack(bytes(data, at, packed)?, rows, channels)?);
+        at += packed;
+    }
+
+    let mut instruments = vec![];
+    for _ in 0..instrument_count {
+        let size = u32_at(data, at)?;
+        let sample_count = u16_at(data, at + 27)?;
+        if sample_count == 0 {
+            instruments.push(ModuleInstrument { samples: vec![], keymap: vec![] });
+            at += size;
+            continue;
+        }
+        let header_size = u32_at(data, at + 29)?;
+        let keymap = bytes(data, at + 33, 96)?.to_vec();
+        at += size;
+
+        let mut headers = vec![];
+        for i in 0..sample_count {
+            let header = bytes(data, at + i * header_size, 40)?;
+            headers.push((
+                u32_at(header, 0)?,
+                u32_at(header, 4)?,
+                u32_at(header, 8)?,
+                header[12].min(64),
+                header[13] as i8,
+                header[14],
+                header[15],
+                header[16] as i8,
+            ));
+        }
+        at += sample_count * header_size;
+
+        let mut samples = vec![];
+        for (length, loop_start, loop_length, volume, finetune, kind, pan, relative) in headers {
+            let sixteen_bits = kind & 0x10 != 0;
+            let available = data.get(at..).unwrap_or_default();
+            let data = decode(&available[..length.min(available.len())], sixteen_bits);
+            at += length;
+            // Lengths count bytes, twice the points of sixteen bit samples
+            let width = if sixteen_bits { 2 } else { 1 };
+            let looped = Some((loop_start / width, ((loop_start + loop_length) / width).min(data.len())))
+                .filter(|(start, end)| kind & 3 != 0 && start < end);
+            let rate = BASE_RATE * 2f32.powf((relative as f32 + finetune as f32 / 128.0) / 12.0);
+            let sample = Arc::new(Sample { data, rate, looped });
+            samples.push(ModuleSample { sample, volume, pan: Some(pan as f32 / 255.0 * 2.0 - 1.0) });
+        }
+        instruments.push(ModuleInstrument { samples, keymap });
+    }
+
+    Ok(Module { name, pans: vec![0.0; channels], speed, bpm, order, patterns, instruments })
+}
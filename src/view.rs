@@ -2,25 +2,75 @@ use crate::app::App;
 use crate::view::tui_elements::TuiSplit;
 use crate::view::tui_elements::{TuiStructure, TuiStructureLink, TuiTiles};
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
 use crossterm::terminal::{self, Clear, ClearType, disable_raw_mode, enable_raw_mode};
 use crossterm::{cursor, QueueableCommand, style};
+use crossterm::style::Stylize;
 use std::io::{stdout, Write};
+use std::sync::mpsc::{self, Receiver};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+use crate::automation::Breakpoint;
+use crate::envelope::{Envelope, Point};
+use crate::instruction::{Status, MAX_VELOCITY};
 use crate::instruction::InstructionKind;
+use crate::command::args::Span;
+use crate::command::Command;
+use crate::view::command_line::{CommandLine, LineEvent};
+use crate::view::help::HelpPanel;
+use crate::view::lane::LanePanel;
+use crate::view::pattern::{PatternEvent, PatternPanel};
+use crate::view::record::Recorder;
+use crate::instrument;
+use crate::groove::{Groove, BUILT_IN};
+use crate::midi;
+use crate::mml;
+use crate::project::Project;
+use crate::render;
+use crate::song;
+use crate::looping::Loop;
+use crate::tempo::PATTERN_ROWS;
+use crate::tracker;
 
 mod tui_elements;
 mod grid_select;
+mod command_line;
+mod help;
+mod lane;
+mod pattern;
+mod record;
 
 enum LoopStatus {
     Continue,
     Break,
 }
 
+/// How often watched files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum FileKind {
+    Song,
+    Project,
+}
+
+/// A file loaded or saved last, read again when it changes on disk
+struct Watched {
+    path: String,
+    kind: FileKind,
+    modified: Option<SystemTime>,
+    /// What the song was last read from, to tell what changed
+    text: String,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 enum TuiMode {
     Command,
     Unfocused,
+    Help,
+    Lane,
+    Pattern,
+    Record,
 }
 
 struct TuiViewModel {
@@ -29,8 +79,23 @@ struct TuiViewModel {
     tiles: TuiTiles,
     target_instrument: Option<u128>,
     target_tick: Option<u128>,
-    cmd_buf: String,
+    cmd: CommandLine,
     status_buf: String,
+    // The command that failed to parse, and the part of it that was wrong
+    status_error: Option<(String, Span)>,
+    // Instructions with times relative to the start of the yanked block
+    clipboard: Vec<(u128, InstructionKind)>,
+    help: Option<HelpPanel>,
+    lane: Option<LanePanel>,
+    pattern: Option<PatternPanel>,
+    recorder: Option<Recorder>,
+    record_quantize: bool,
+    record_velocity: u8,
+    watched: Vec<Watched>,
+    watching: bool,
+    watch_checked: Instant,
+    /// Progress of the render running on its own thread, ending with its summary
+    render: Option<Receiver<Result<String, String>>>,
 }
 
 impl TuiViewModel {
@@ -55,22 +120,300 @@ impl TuiViewModel {
                     ]
                 }
             },
-            cmd_buf: String::new(),
+            cmd: CommandLine::new(CommandLine::default_history_file()),
             status_buf: String::new(),
+            status_error: None,
             target_tick: None,
-            target_instrument: None
+            target_instrument: None,
+            clipboard: vec![],
+            help: None,
+            lane: None,
+            pattern: None,
+            recorder: None,
+            record_quantize: true,
+            record_velocity: MAX_VELOCITY,
+            watched: vec![],
+            watching: true,
+            watch_checked: Instant::now(),
+            render: None,
         }
     }
 
     fn change_mode(&mut self, mode: TuiMode) {
         self.mode = mode;
         if mode == TuiMode::Unfocused {
-            self.cmd_buf.clear()
+            self.cmd.clear()
         }
     }
 
     fn draw(&mut self) -> std::io::Result<()> {
         self.tiles.draw()?;
+        if let Some(help) = &self.help {
+            help.draw()?;
+        }
+        if let Some(lane) = &self.lane {
+            lane.draw(&self.app.lock().unwrap())?;
+        }
+        if let Some(pattern) = self.pattern.as_mut() {
+            pattern.draw(&self.app.lock().unwrap())?;
+        }
+        Ok(())
+    }
+
+    fn open_params(&mut self) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let app = self.app.lock().unwrap();
+        let inst = app.instruments.get(target as usize).ok_or(String::from("No such instrument"))?;
+        let width = inst.params().iter().map(|p| p.name.len()).max().unwrap_or(0);
+        let lines = inst.params().iter().map(|p| {
+            let value = p.format(inst.get_param(p.name).unwrap_or(p.default));
+            let range = match p.labels.is_empty() {
+                true => format!("{}..{}{}", p.min, p.max, p.unit),
+                false => p.labels.join("|"),
+            };
+            format!("{:<width$}  {:<10}  {:<16}  {}", p.name, value, range, p.summary, width = width)
+        }).collect();
+        self.help = Some(HelpPanel::with_lines(format!("Parameters: {} {}", target, inst.name()), lines));
+        Ok(())
+    }
+
+    fn open_help(&mut self, topic: Option<String>) -> Result<(), String> {
+        match HelpPanel::new(topic.as_deref()) {
+            Some(panel) => {
+                self.help = Some(panel);
+                Ok(())
+            }
+            None => Err(format!("No help on '{}'", topic.unwrap_or_default()))
+        }
+    }
+
+    fn add_breakpoint(&mut self, name: &'static str, point: Breakpoint) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let tick = self.target_tick.ok_or(String::from("No target tick set"))?;
+        self.app.lock().unwrap().set_breakpoint(target, name, tick, point).map_err(String::from)
+    }
+
+    fn remove_breakpoint(&mut self, name: &'static str) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let tick = self.target_tick.ok_or(String::from("No target tick set"))?;
+        self.app.lock().unwrap().remove_breakpoint(target, name, tick);
+        Ok(())
+    }
+
+    fn open_lane(&mut self, name: &'static str) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let app = self.app.lock().unwrap();
+        let inst = app.instruments.get(target as usize).ok_or(String::from("No such instrument"))?;
+        if instrument::param::find(inst.params(), name).is_none() {
+            return Err(format!("'{}' has no parameter '{}'", inst.name(), name));
+        }
+        self.lane = Some(LanePanel::new(target, name));
+        Ok(())
+    }
+
+    fn open_pattern(&mut self) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let app = self.app.lock().unwrap();
+        app.instruments.get(target as usize).ok_or(String::from("No such instrument"))?;
+        // Starts at the row of the target time
+        let row = app.tempo.row_at(self.target_tick.unwrap_or(0));
+        self.pattern = Some(PatternPanel::new(target, row));
+        Ok(())
+    }
+
+    /// Changes the envelope of a parameter of the target instrument, starting a new one if needed
+    fn edit_envelope(&mut self, name: &'static str, change: impl FnOnce(&mut Envelope) -> Result<(), String>) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let mut app = self.app.lock().unwrap();
+        let mut envelope = app.envelopes.get(target, name).cloned().unwrap_or_default();
+        change(&mut envelope)?;
+        app.set_envelope(target, name, Some(envelope)).map_err(String::from)
+    }
+
+    fn remove_envelope(&mut self, name: &'static str, point: Option<u128>) -> Result<(), String> {
+        let Some(point) = point else {
+            let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+            return self.app.lock().unwrap().set_envelope(target, name, None).map_err(String::from);
+        };
+        self.edit_envelope(name, |env| match env.remove_point(point as usize) {
+            Some(_) => Ok(()),
+            None => Err(format!("No point {}", point)),
+        })
+    }
+
+    fn show_envelope(&mut self, name: &'static str) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let app = self.app.lock().unwrap();
+        let envelope = app.envelopes.get(target, name).ok_or(format!("No envelope on '{}'", name))?;
+        let unit = if envelope.synced { " beats" } else { "s" };
+        let mut lines = vec![];
+        for (i, point) in envelope.points().iter().enumerate() {
+            let mut marks = vec![];
+            if envelope.sustain == Some(i) {
+                marks.push("sustain");
+            }
+            match envelope.looped {
+                Some((start, _)) if start == i => marks.push("loop start"),
+                Some((_, end)) if end == i => marks.push("loop end"),
+                _ => {}
+            }
+            lines.push(format!("{:>3}  {:>8}{}  {:<10}  {:<4}  {}", i, point.time, unit, point.value, point.curve, marks.join(", ")));
+        }
+        self.help = Some(HelpPanel::with_lines(format!("Envelope: {} {}", target, name), lines));
+        Ok(())
+    }
+
+    fn open_tempos(&mut self) {
+        let app = self.app.lock().unwrap();
+        let sample_rate = app.get_sample_rate().max(1.0);
+        let mut lines = vec![format!("{:>9}  {:>8}  {:>7}  curve", "time", "beat", "bpm")];
+        lines.push(format!("{:>8}s  {:>8}  {:>7}  step", 0, 0, app.tempo.initial_bpm()));
+        for (time, change) in app.tempo.changes().points() {
+            let beat = app.tempo.beat_at(time);
+            lines.push(format!("{:>8.3}s  {:>8.2}  {:>7}  {}", time as f32 / sample_rate, beat, change.value, change.curve));
+        }
+        self.help = Some(HelpPanel::with_lines(String::from("Tempo map"), lines));
+    }
+
+    fn set_instrument_groove(&mut self, name: Option<String>) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let mut app = self.app.lock().unwrap();
+        app.instruments.get(target as usize).ok_or(String::from("No such instrument"))?;
+        let groove = match name {
+            Some(name) => Some(app.grooves.find(&name).ok_or(format!("No groove '{}'", name))?),
+            None => None,
+        };
+        app.set_groove(target, groove);
+        Ok(())
+    }
+
+    /// Reads a file into the song with one of the importers, showing its summary
+    fn import(&mut self, path: &str, importer: fn(&mut App, &[u8]) -> Result<String, String>) -> Result<(), String> {
+        let data = std::fs::read(path).map_err(|e| format!("Can't read '{}': {}", path, e))?;
+        self.status_buf = importer(&mut self.app.lock().unwrap(), &data)?;
+        Ok(())
+    }
+
+    /// Watches a file just loaded or saved in place of the last one of its kind, from how it
+    /// is now
+    fn watch(&mut self, path: &str, kind: FileKind) {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        self.watched.retain(|w| w.kind != kind);
+        self.watched.push(Watched { path: String::from(path), kind, modified, text });
+    }
+
+    /// Reads watched files again once they change on disk, without stopping playback
+    fn check_watched(&mut self) {
+        // Changes are picked up once a render is done with the song
+        if !self.watching || self.render.is_some() || self.watch_checked.elapsed() < WATCH_INTERVAL {
+            return;
+        }
+        self.watch_checked = Instant::now();
+        for i in 0..self.watched.len() {
+            let modified = std::fs::metadata(&self.watched[i].path).and_then(|m| m.modified()).ok();
+            if modified.is_none() || modified == self.watched[i].modified {
+                continue;
+            }
+            self.watched[i].modified = modified;
+            let Watched { path, kind, text, .. } = &mut self.watched[i];
+            let mut app = self.app.lock().unwrap();
+            let result = std::fs::read_to_string(&*path).map_err(|e| e.to_string()).and_then(|new| {
+                let summary = match kind {
+                    FileKind::Song => song::reload(&mut app, text, &new)?,
+                    FileKind::Project => Project::from_ron(&new)?.apply(&mut app).map(|_| String::from("grooves updated"))?,
+                };
+                *text = new;
+                Ok(summary)
+            });
+            self.status_buf = match result {
+                Ok(summary) => format!("Reloaded '{}': {}", path, summary),
+                Err(e) => format!("Can't reload '{}': {}", path, e),
+            };
+        }
+    }
+
+    /// Shows how far the render got, and its summary once it's done
+    fn check_render(&mut self) {
+        let Some(render) = self.render.as_ref() else {
+            return;
+        };
+        loop {
+            match render.try_recv() {
+                Ok(Ok(message) | Err(message)) => self.status_buf = message,
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        }
+        self.render = None;
+    }
+
+    fn open_grooves(&mut self) {
+        let app = self.app.lock().unwrap();
+        let built_in = BUILT_IN.iter().filter_map(|(name, _, _)| Groove::built_in(name));
+        let grooves = app.grooves.user.iter().cloned().chain(built_in).collect::<Vec<_>>();
+        let width = grooves.iter().map(|g| g.name.len()).max().unwrap_or(0);
+        let mut lines = vec![];
+        for groove in grooves {
+            let mut users = vec![];
+            if app.grooves.song.as_ref().is_some_and(|g| g.name == groove.name) {
+                users.push(String::from("song"));
+            }
+            for (target, _) in app.grooves.targets().filter(|(_, g)| g.name == groove.name) {
+                users.push(format!("inst {}", target));
+            }
+            lines.push(format!("{:<width$}  {}  {}", groove.name, groove.describe(), users.join(", "), width = width));
+        }
+        self.help = Some(HelpPanel::with_lines(String::from("Grooves"), lines));
+    }
+
+    /// Arms recording into the target instrument, starting playback if needed, or with
+    /// `record` off only plays what's played on the keyboard
+    fn start_recording(&mut self, record: bool) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let mut app = self.app.lock().unwrap();
+        app.instruments.get(target as usize).ok_or(String::from("No such instrument"))?;
+        // Key releases are only reported when asked for, and asking changes every other key
+        // press too, so they're only asked for while recording
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            stdout().queue(PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)).map_err(|e| e.to_string())?;
+        }
+        self.recorder = Some(match record {
+            true => {
+                // A take is undone as a whole
+                app.begin_group();
+                app.play();
+                Recorder::new(target, self.record_quantize, self.record_velocity, releases)
+            }
+            false => {
+                app.set_jamming(true);
+                Recorder::jam(target, self.record_velocity, releases)
+            }
+        });
+        Ok(())
+    }
+
+    fn stop_recording(&mut self) -> std::io::Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            let mut app = self.app.lock().unwrap();
+            match recorder.is_recording() {
+                true => app.end_group(),
+                false => app.set_jamming(false),
+            }
+            if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+                stdout().queue(PopKeyboardEnhancementFlags)?;
+            }
+        }
+        self.change_mode(TuiMode::Unfocused);
+        Ok(())
+    }
+
+    fn set_loop(&mut self, start: u128, end: u128) -> Result<(), String> {
+        if start >= end {
+            return Err(String::from("The loop must end after it starts"));
+        }
+        self.app.lock().unwrap().looping = Some(Loop::Region { start, end });
         Ok(())
     }
 
@@ -86,6 +429,16 @@ impl TuiViewModel {
         self.app.lock().unwrap().reset();
     }
 
+    fn seek(&mut self, tick: Option<u128>) -> Result<(), String> {
+        match tick.or(self.target_tick) {
+            Some(t) => {
+                self.app.lock().unwrap().seek(t);
+                Ok(())
+            }
+            None => Err(String::from("No target tick set"))
+        }
+    }
+
     fn add_instruction(&mut self, kind: InstructionKind) -> Result<(), String> {
         if self.target_instrument.is_none() {
             return Err(String::from("No target instrument set"))
@@ -93,11 +446,78 @@ impl TuiViewModel {
         if self.target_tick.is_none() {
             return Err(String::from("No target tick set"))
         }
-        // let inst = Instruction::new(kind, self.target_instrument.unwrap());
-        // self.app.lock().unwrap().instructions.asd();
-        self.app.lock().unwrap().instructions.insert(self.target_instrument.unwrap(), self.target_tick.unwrap(), kind);
+        self.app.lock().unwrap().insert_instruction(self.target_instrument.unwrap(), self.target_tick.unwrap(), kind);
+        Ok(())
+    }
+
+    fn remove_instruction(&mut self, kind: InstructionKind) -> Result<(), String> {
+        match (self.target_instrument, self.target_tick) {
+            (Some(i), Some(t)) => {
+                self.app.lock().unwrap().remove_instruction(i, t, kind);
+                Ok(())
+            }
+            (None, _) => Err(String::from("No target instrument set")),
+            (_, None) => Err(String::from("No target tick set"))
+        }
+    }
+
+    fn set_setting(&mut self, kind: InstructionKind) -> Result<(), String> {
+        match self.target_instrument {
+            Some(i) => self.app.lock().unwrap().apply_settings(i, kind).map_err(String::from),
+            None => Err(String::from("No target instrument set"))
+        }
+    }
+
+    fn yank(&mut self, start: u128, end: u128) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        if start >= end {
+            return Err(String::from("The yanked block must end after it starts"));
+        }
+        self.clipboard = self.app.lock().unwrap().instructions.range(target, start, end)
+            .into_iter()
+            .map(|(time, kind)| (time - start, kind))
+            .collect();
+        self.status_buf = format!("Yanked {} instructions", self.clipboard.len());
+        Ok(())
+    }
+
+    fn paste(&mut self) -> Result<(), String> {
+        let target = self.target_instrument.ok_or(String::from("No target instrument set"))?;
+        let tick = self.target_tick.ok_or(String::from("No target tick set"))?;
+        let mut app = self.app.lock().unwrap();
+        app.begin_group();
+        for (time, kind) in self.clipboard.iter() {
+            app.insert_instruction(target, tick + time, *kind);
+        }
+        app.end_group();
         Ok(())
     }
+
+    /// Refuses what would change the song or move the playhead while a render plays it
+    fn check_not_rendering(&mut self) -> bool {
+        if self.render.is_some() {
+            self.status_buf = String::from("Wait for the render to finish");
+        }
+        self.render.is_none()
+    }
+
+    fn undo(&mut self) {
+        if !self.check_not_rendering() {
+            return;
+        }
+        if !self.app.lock().unwrap().undo() {
+            self.status_buf = String::from("Nothing to undo");
+        }
+    }
+
+    fn redo(&mut self) {
+        if !self.check_not_rendering() {
+            return;
+        }
+        if !self.app.lock().unwrap().redo() {
+            self.status_buf = String::from("Nothing to redo");
+        }
+    }
 }
 
 pub fn tui(app: Arc<Mutex<App>>) -> std::io::Result<()> {
@@ -111,6 +531,8 @@ pub fn tui(app: Arc<Mutex<App>>) -> std::io::Result<()> {
 
 fn event_loop(mut viewmodel: TuiViewModel) -> std::io::Result<()> {
     loop {
+        viewmodel.check_watched();
+        viewmodel.check_render();
         viewmodel.draw()?;
         command_bar(&viewmodel)?;
         stdout().flush()?;
@@ -125,6 +547,8 @@ fn event_loop(mut viewmodel: TuiViewModel) -> std::io::Result<()> {
                 Event::Key(event) => match viewmodel.mode {
                     TuiMode::Unfocused => match event.code {
                         KeyCode::Char(':') => viewmodel.change_mode(TuiMode::Command),
+                        KeyCode::Char('u') => viewmodel.undo(),
+                        KeyCode::Char('r') if event.modifiers == KeyModifiers::CONTROL => viewmodel.redo(),
                         KeyCode::Char('c') | KeyCode::Char('d') => {
                             if event.modifiers == KeyModifiers::CONTROL {
                                 break;
@@ -135,6 +559,49 @@ fn event_loop(mut viewmodel: TuiViewModel) -> std::io::Result<()> {
                     },
                     // TODO: this code looks confusing, consider handling breaks another way?
                     TuiMode::Command => if let LoopStatus::Break = handle_command(&mut viewmodel, event)? { break; }
+                    TuiMode::Help => if let Some(help) = viewmodel.help.as_mut() {
+                        if !help.handle_key(event) {
+                            viewmodel.help = None;
+                            viewmodel.change_mode(TuiMode::Unfocused);
+                            stdout().queue(Clear(ClearType::All))?;
+                        }
+                    }
+                    TuiMode::Lane => if let Some(lane) = viewmodel.lane.as_mut() {
+                        if event.kind == KeyEventKind::Release {
+                            continue;
+                        }
+                        let open = lane.handle_key(event, &mut viewmodel.app.lock().unwrap());
+                        // The lane is redrawn from scratch, as points move around
+                        stdout().queue(Clear(ClearType::All))?;
+                        if !open {
+                            viewmodel.lane = None;
+                            viewmodel.change_mode(TuiMode::Unfocused);
+                        }
+                    }
+                    TuiMode::Record => if let Some(recorder) = viewmodel.recorder.as_mut() {
+                        if !recorder.handle_key(event, &mut viewmodel.app.lock().unwrap()) {
+                            viewmodel.stop_recording()?;
+                        }
+                    }
+                    TuiMode::Pattern => if let Some(pattern) = viewmodel.pattern.as_mut() {
+                        if event.kind == KeyEventKind::Release {
+                            continue;
+                        }
+                        let result = pattern.handle_key(event, &mut viewmodel.app.lock().unwrap());
+                        stdout().queue(Clear(ClearType::All))?;
+                        match result {
+                            PatternEvent::None => {}
+                            PatternEvent::Close => {
+                                viewmodel.pattern = None;
+                                viewmodel.change_mode(TuiMode::Unfocused);
+                            }
+                            PatternEvent::Target(tick) => {
+                                viewmodel.target_tick = Some(tick);
+                                viewmodel.pattern = None;
+                                viewmodel.change_mode(TuiMode::Unfocused);
+                            }
+                        }
+                    }
                 },
                 _ => {}
             }
@@ -143,49 +610,317 @@ fn event_loop(mut viewmodel: TuiViewModel) -> std::io::Result<()> {
     Ok(())
 }
 
-// TODO: Terrible parser, improve
 fn handle_command(viewmodel: &mut TuiViewModel, event: KeyEvent) -> std::io::Result<LoopStatus> {
     if let KeyEventKind::Release = event.kind {
         return Ok(LoopStatus::Continue)
     }
-    match event.code {
-        KeyCode::Esc => viewmodel.change_mode(TuiMode::Unfocused),
-        KeyCode::Char(c) => {
-            viewmodel.cmd_buf.push(c);
+    match viewmodel.cmd.handle_key(event) {
+        LineEvent::None => {}
+        LineEvent::Cancel => viewmodel.change_mode(TuiMode::Unfocused),
+        LineEvent::Submit(line) => {
+            viewmodel.status_buf.clear();
+            viewmodel.status_error = None;
+            let status = execute_line(viewmodel, &line)?;
+            let mode = match (&viewmodel.help, &viewmodel.lane, &viewmodel.pattern, &viewmodel.recorder) {
+                (Some(_), _, _, _) => TuiMode::Help,
+                (_, Some(_), _, _) => TuiMode::Lane,
+                (_, _, Some(_), _) => TuiMode::Pattern,
+                (_, _, _, Some(_)) => TuiMode::Record,
+                _ => TuiMode::Unfocused,
+            };
+            viewmodel.change_mode(mode);
+            return Ok(status);
         }
-        KeyCode::Backspace => {
-            viewmodel.cmd_buf.pop();
+    }
+
+    Ok(LoopStatus::Continue)
+}
+
+/// Parses and runs one command, reporting parse errors in the status bar
+fn execute_line(viewmodel: &mut TuiViewModel, line: &str) -> std::io::Result<LoopStatus> {
+    match Command::parse(line) {
+        Ok(command) => run_command(viewmodel, command),
+        Err(err) => {
+            viewmodel.status_buf = err.message.clone();
+            viewmodel.status_error = Some((line.to_string(), err.span));
+            Ok(LoopStatus::Continue)
         }
-        KeyCode::Enter => {
-            viewmodel.status_buf.clear();
-            let stuff: Vec<&str> = viewmodel.cmd_buf.split(" ").collect();
-            let command = *stuff.get(0).unwrap_or(&"");
-            match command {
-                "quit" | "q" => return Ok(LoopStatus::Break),
-                "clear" | "cls" => {
-                    stdout()
-                        .queue(Clear(ClearType::All))?
-                        .queue(Clear(ClearType::Purge))?;
-                },
-                "play" => viewmodel.play(),
-                "pause" | "stop" => viewmodel.pause(),
-                "reset" => viewmodel.reset(),
-                "time" => if let Ok(f) = stuff.get(1).unwrap_or(&"").parse::<f32>() {
-                    viewmodel.target_tick = Some((f * viewmodel.app.lock().unwrap().get_sample_rate()) as u128);
-                },
-                "inst" => if let Ok(u) = stuff.get(1).unwrap_or(&"").parse::<u128>() {
-                    viewmodel.target_instrument = Some(u);
-                },
-                _ => match InstructionKind::parse(viewmodel.cmd_buf.clone()) {
-                    Ok(inst) => if let Err(msg) = viewmodel.add_instruction(inst) { viewmodel.status_buf = msg; }
-                    Err(msg) => viewmodel.status_buf = msg
+    }
+}
+
+fn run_command(viewmodel: &mut TuiViewModel, command: Command) -> std::io::Result<LoopStatus> {
+    if !command.is_passive() && !viewmodel.check_not_rendering() {
+        return Ok(LoopStatus::Continue);
+    }
+    let sample_rate = viewmodel.app.lock().unwrap().get_sample_rate();
+    let to_tick = |seconds: f32| (seconds * sample_rate) as u128;
+    let result = match command {
+        Command::Quit => return Ok(LoopStatus::Break),
+        Command::Clear => {
+            stdout()
+                .queue(Clear(ClearType::All))?
+                .queue(Clear(ClearType::Purge))?;
+            Ok(())
+        }
+        Command::Play => {
+            viewmodel.play();
+            Ok(())
+        }
+        Command::Pause => {
+            viewmodel.pause();
+            Ok(())
+        }
+        Command::Reset => {
+            viewmodel.reset();
+            Ok(())
+        }
+        Command::Seek(seconds) => viewmodel.seek(seconds.map(to_tick)),
+        Command::Time(seconds) => {
+            viewmodel.target_tick = Some(to_tick(seconds));
+            Ok(())
+        }
+        Command::Inst(i) => {
+            viewmodel.target_instrument = Some(i);
+            Ok(())
+        }
+        Command::InstAdd(name) => match instrument::from_name(&name) {
+            Some(inst) => {
+                viewmodel.app.lock().unwrap().add_instrument(inst);
+                Ok(())
+            }
+            None => Err(format!("Unknown instrument '{}'", name))
+        },
+        Command::InstDel => match viewmodel.target_instrument {
+            Some(i) => {
+                viewmodel.app.lock().unwrap().remove_instrument(i as usize);
+                Ok(())
+            }
+            None => Err(String::from("No target instrument set"))
+        },
+        Command::Undo => {
+            viewmodel.undo();
+            Ok(())
+        }
+        Command::Redo => {
+            viewmodel.redo();
+            Ok(())
+        }
+        Command::Yank(start, end) => viewmodel.yank(to_tick(start), to_tick(end)),
+        Command::Paste => viewmodel.paste(),
+        Command::Remove(kind) => viewmodel.remove_instruction(kind),
+        Command::Set(kind) => viewmodel.set_setting(kind),
+        Command::Instruction(kind) => viewmodel.add_instruction(kind),
+        Command::Help(topic) => viewmodel.open_help(topic),
+        Command::Params => viewmodel.open_params(),
+        Command::Auto { name, value, curve } => viewmodel.add_breakpoint(name, Breakpoint { value, curve }),
+        Command::AutoDel(name) => viewmodel.remove_breakpoint(name),
+        Command::Lane(name) => viewmodel.open_lane(name),
+        Command::Pattern => viewmodel.open_pattern(),
+        Command::Env { name, time, value, curve } => viewmodel.edit_envelope(name, |env| {
+            env.set_point(Point { time, value, curve });
+            Ok(())
+        }),
+        Command::EnvDel(name, point) => viewmodel.remove_envelope(name, point),
+        Command::EnvSustain(name, point) => viewmodel.edit_envelope(name, |env| {
+            if point.is_some_and(|p| p as usize >= env.points().len()) {
+                return Err(String::from("No such point"));
+            }
+            env.sustain = point.map(|p| p as usize);
+            Ok(())
+        }),
+        Command::EnvLoop(name, points) => viewmodel.edit_envelope(name, |env| {
+            let last = env.points().len().saturating_sub(1);
+            env.looped = match points {
+                Some((start, end)) => {
+                    let end = end.map_or(last, |e| e as usize);
+                    if start as usize >= end || end > last {
+                        return Err(String::from("Loops go from a point to a later one"));
+                    }
+                    Some((start as usize, end))
+                }
+                None => None,
+            };
+            Ok(())
+        }),
+        Command::EnvSync(name, status) => viewmodel.edit_envelope(name, |env| {
+            env.synced = status == Status::On;
+            Ok(())
+        }),
+        Command::EnvShow(name) => viewmodel.show_envelope(name),
+        Command::Master(level) => {
+            let mut app = viewmodel.app.lock().unwrap();
+            match level {
+                Some(level) => app.set_master_volume(level),
+                None => viewmodel.status_buf = format!("Master level {}", app.master_volume()),
+            }
+            Ok(())
+        }
+        Command::Bpm(bpm) => {
+            let mut app = viewmodel.app.lock().unwrap();
+            match bpm {
+                Some(bpm) => app.set_bpm(bpm.min(u16::MAX as u128) as u16),
+                None => viewmodel.status_buf = format!("Starting tempo {} BPM", app.get_bpm()),
+            }
+            Ok(())
+        }
+        Command::Tempo { bpm, curve } => match viewmodel.target_tick {
+            Some(tick) => viewmodel.app.lock().unwrap().set_tempo(tick, Breakpoint { value: bpm, curve }).map_err(String::from),
+            None => Err(String::from("No target tick set")),
+        },
+        Command::TempoDel => match viewmodel.target_tick {
+            Some(tick) => {
+                viewmodel.app.lock().unwrap().remove_tempo(tick);
+                Ok(())
+            }
+            None => Err(String::from("No target tick set")),
+        },
+        Command::Tempos => {
+            viewmodel.open_tempos();
+            Ok(())
+        }
+        Command::Groove(Some(name)) => {
+            let mut app = viewmodel.app.lock().unwrap();
+            match app.grooves.find(&name) {
+                Some(groove) => {
+                    app.set_song_groove(Some(groove));
+                    Ok(())
+                }
+                None => Err(format!("No groove '{}'", name)),
+            }
+        }
+        Command::Groove(None) => {
+            let app = viewmodel.app.lock().unwrap();
+            viewmodel.status_buf = match &app.grooves.song {
+                Some(groove) => format!("Groove '{}': {}", groove.name, groove.describe()),
+                None => String::from("No groove"),
+            };
+            Ok(())
+        }
+        Command::GrooveInst(name) => viewmodel.set_instrument_groove(Some(name)),
+        Command::GrooveInstDel => viewmodel.set_instrument_groove(None),
+        Command::Swing(percent) => {
+            let groove = Groove::swing(&format!("swing {}%", percent), percent);
+            viewmodel.app.lock().unwrap().set_song_groove(Some(groove));
+            Ok(())
+        }
+        Command::Grooves => {
+            viewmodel.open_grooves();
+            Ok(())
+        }
+        Command::ProjectLoad(path) => Project::load(&path)
+            .and_then(|project| project.apply(&mut viewmodel.app.lock().unwrap()))
+            .map(|_| viewmodel.watch(&path, FileKind::Project)),
+        Command::ProjectSave(path) => {
+            let project = Project::from_app(&viewmodel.app.lock().unwrap());
+            project.save(&path).map(|_| viewmodel.watch(&path, FileKind::Project))
+        }
+        Command::SongLoad(path) => viewmodel.import(&path, |app, data| song::read(app, &String::from_utf8_lossy(data)))
+            .map(|_| viewmodel.watch(&path, FileKind::Song)),
+        Command::SongSave(path) => {
+            let text = song::write(&viewmodel.app.lock().unwrap());
+            text.and_then(|text| std::fs::write(&path, text).map_err(|e| format!("Can't write '{}': {}", path, e)))
+                .map(|_| viewmodel.watch(&path, FileKind::Song))
+        }
+        Command::Watch(state) => {
+            viewmodel.watching = state == Status::On;
+            Ok(())
+        }
+        Command::ImportMidi(path) => viewmodel.import(&path, midi::import),
+        Command::ExportMidi(path) => midi::export(&viewmodel.app.lock().unwrap())
+            .and_then(|data| std::fs::write(&path, data).map_err(|e| format!("Can't write '{}': {}", path, e))),
+        Command::ImportModule(path) => viewmodel.import(&path, tracker::import),
+        Command::ImportMml(path) => viewmodel.import(&path, |app, data| mml::import(app, &String::from_utf8_lossy(data))),
+        Command::Import(path) => {
+            let extension = path.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase()).unwrap_or_default();
+            match extension.as_str() {
+                "mid" | "midi" => viewmodel.import(&path, midi::import),
+                "mod" | "xm" => viewmodel.import(&path, tracker::import),
+                "mml" => viewmodel.import(&path, |app, data| mml::import(app, &String::from_utf8_lossy(data))),
+                _ => Err(String::from("Only .mid, .mod, .xm and .mml files can be imported")),
+            }
+        }
+        Command::Loop(start, end) => viewmodel.set_loop(to_tick(start), to_tick(end)),
+        Command::LoopBeats(start, end) => {
+            let (start, end) = {
+                let app = viewmodel.app.lock().unwrap();
+                (app.tempo.tick_at(start as f64), app.tempo.tick_at(end as f64))
+            };
+            viewmodel.set_loop(start, end)
+        }
+        Command::LoopStart | Command::LoopEnd => match viewmodel.target_tick {
+            Some(tick) => {
+                let bounds = match viewmodel.app.lock().unwrap().looping {
+                    Some(Loop::Region { start, end }) => (start, end),
+                    _ => (0, u128::MAX),
+                };
+                match command {
+                    Command::LoopStart => viewmodel.set_loop(tick, bounds.1),
+                    _ => viewmodel.set_loop(bounds.0, tick),
                 }
             }
-            viewmodel.change_mode(TuiMode::Unfocused)
+            None => Err(String::from("No target tick set")),
+        },
+        Command::LoopPattern(rows) => match rows.unwrap_or(PATTERN_ROWS) {
+            0 => Err(String::from("Patterns need at least one row")),
+            rows => {
+                viewmodel.app.lock().unwrap().looping = Some(Loop::Pattern { rows });
+                Ok(())
+            }
+        },
+        Command::LoopOff => {
+            viewmodel.app.lock().unwrap().looping = None;
+            Ok(())
+        }
+        Command::Record => viewmodel.start_recording(true),
+        Command::Jam => viewmodel.start_recording(false),
+        Command::RecordQuantize(state) => {
+            viewmodel.record_quantize = state == Status::On;
+            Ok(())
+        }
+        Command::RecordVelocity(velocity) => {
+            viewmodel.record_velocity = velocity;
+            Ok(())
+        }
+        Command::Metronome(state) => {
+            viewmodel.app.lock().unwrap().metronome.on = state == Status::On;
+            Ok(())
+        }
+        Command::MetronomeVolume(level) => {
+            viewmodel.app.lock().unwrap().metronome.volume = level.clamp(0.0, 1.0);
+            Ok(())
+        }
+        Command::Render { path, stems } => {
+            let (sender, receiver) = mpsc::channel();
+            let app = viewmodel.app.clone();
+            std::thread::spawn(move || {
+                let progress = |done: f32| drop(sender.send(Ok(format!("Rendering '{}': {:.0}%", path, done * 100.0))));
+                let result = render::export(&app, &path, stems, progress);
+                let _ = sender.send(result);
+            });
+            viewmodel.render = Some(receiver);
+            viewmodel.status_buf = String::from("Rendering...");
+            Ok(())
+        }
+        Command::MetronomeRender(state) => {
+            viewmodel.app.lock().unwrap().metronome.in_renders = state == Status::On;
+            Ok(())
+        }
+        Command::Signature(clicks, unit) => {
+            let mut app = viewmodel.app.lock().unwrap();
+            app.set_signature(clicks.min(u32::MAX as u128) as u32, unit.min(u32::MAX as u128) as u32).map_err(String::from)
+        }
+        Command::CountIn(state) => {
+            viewmodel.app.lock().unwrap().count_in = state == Status::On;
+            Ok(())
         }
-        _ => {}
+        Command::Beat(beat) => {
+            viewmodel.target_tick = Some(viewmodel.app.lock().unwrap().tempo.tick_at(beat as f64));
+            Ok(())
+        }
+    };
+    if let Err(msg) = result {
+        viewmodel.status_buf = msg;
     }
-
     Ok(LoopStatus::Continue)
 }
 
@@ -223,8 +958,60 @@ fn command_bar(vm: &TuiViewModel) -> std::io::Result<()> {
         .queue(cursor::MoveTo(0, h - 1))?
         .queue(style::Print(" ".repeat(w as usize)))?
         .queue(cursor::MoveTo(0, h - 1))?
-        .queue(style::Print(if vm.mode == TuiMode::Command { String::from(":") + vm.cmd_buf.as_str() } else { vm.status_buf.clone() }))?
     ;
+    match (&vm.status_error, vm.mode) {
+        (_, TuiMode::Command) => match vm.cmd.search_prompt() {
+            Some(prompt) => {
+                stdout().queue(style::Print(prompt))?;
+            }
+            None => {
+                // The terminal cursor is hidden, so the character under it is drawn reversed
+                let (text, cursor) = (vm.cmd.text(), vm.cmd.cursor());
+                let under = text[cursor..].chars().next().unwrap_or(' ');
+                stdout()
+                    .queue(style::Print(format!(":{}", &text[..cursor])))?
+                    .queue(style::PrintStyledContent(under.to_string().reverse()))?
+                    .queue(style::Print(&text[(cursor + under.len_utf8()).min(text.len())..]))?;
+                if let Some(candidates) = vm.cmd.completions() {
+                    stdout().queue(style::Print(format!("    {}", candidates.join(" "))))?;
+                }
+            }
+        },
+        // Show the failed command with the offending part highlighted
+        (Some((cmd, span)), _) => {
+            let padded = format!("{:<1$}", cmd, span.end);
+            stdout()
+                .queue(style::Print(format!(":{}", &padded[..span.start])))?
+                .queue(style::PrintStyledContent(padded[span.start..span.end].to_string().red().underlined()))?
+                .queue(style::Print(format!("{}  {}", &padded[span.end..], vm.status_buf)))?;
+        }
+        (None, TuiMode::Record) => {
+            let status = vm.recorder.as_ref().map(Recorder::status).unwrap_or_default();
+            stdout().queue(style::PrintStyledContent(status.red()))?;
+        }
+        (None, _) => {
+            stdout().queue(style::Print(vm.status_buf.clone()))?;
+        }
+    }
+    if vm.mode != TuiMode::Command {
+        // Playback position as bar and beat, counting from 1 like sequencers do
+        let app = vm.app.lock().unwrap();
+        let (bar, click) = app.tempo.bar_at(app.position());
+        let (clicks, unit) = app.tempo.signature();
+        let position = format!(
+            "{}{}{}.{}  {}/{}  {:.0} BPM ",
+            if app.is_counting_in() { "count in  " } else { "" },
+            if app.looping.is_some() { "loop  " } else { "" },
+            bar + 1,
+            click + 1,
+            clicks,
+            unit,
+            app.tempo.bpm_at(app.position()),
+        );
+        stdout()
+            .queue(cursor::MoveTo(w.saturating_sub(position.len() as u16), h - 1))?
+            .queue(style::Print(position))?;
+    }
     Ok(())
 }
 
@@ -0,0 +1,398 @@
+use crate::command::complete::{common_prefix, complete};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+const HISTORY_LIMIT: usize = 1000;
+
+/// What the command line wants done after a key press
+pub enum LineEvent {
+    None,
+    Submit(String),
+    Cancel,
+}
+
+/// An in-progress Ctrl-r search through the history
+struct Search {
+    query: String,
+    // Index into the history of the current match
+    found: Option<usize>,
+}
+
+/// Candidates from the last Tab press, cycled through on repeated presses
+struct Completion {
+    start: usize,
+    candidates: Vec<String>,
+    index: Option<usize>,
+}
+
+/// The editable line of the command bar, with history and completion
+pub struct CommandLine {
+    buf: String,
+    // Byte offset into `buf`, always on a char boundary
+    cursor: usize,
+    history: Vec<String>,
+    history_pos: Option<usize>,
+    // What was typed before browsing the history started
+    stash: String,
+    history_file: Option<PathBuf>,
+    // Lines in the history file, which is rewritten with only the kept ones when it gets long
+    file_lines: usize,
+    search: Option<Search>,
+    completion: Option<Completion>,
+}
+
+impl CommandLine {
+    pub fn new(history_file: Option<PathBuf>) -> Self {
+        let mut history = history_file
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|s| s.lines().filter(|l| !l.trim().is_empty()).map(String::from).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let file_lines = history.len();
+        let overflow = history.len().saturating_sub(HISTORY_LIMIT);
+        history.drain(..overflow);
+        let mut line = Self {
+            buf: String::new(),
+            cursor: 0,
+            history,
+            history_pos: None,
+            stash: String::new(),
+            history_file,
+            file_lines,
+            search: None,
+            completion: None,
+        };
+        if overflow > 0 {
+            line.rewrite_history();
+        }
+        line
+    }
+
+    /// Replaces the history file with the kept history
+    fn rewrite_history(&mut self) {
+        if let Some(path) = &self.history_file {
+            let mut text = self.history.join("\n");
+            text.push('\n');
+            if std::fs::write(path, text).is_ok() {
+                self.file_lines = self.history.len();
+            }
+        }
+    }
+
+    /// The history file in the home directory, if there is one
+    pub fn default_history_file() -> Option<PathBuf> {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".fmangroove_history"))
+    }
+
+    pub fn text(&self) -> &str {
+        &self.buf
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The search prompt while searching the history, like `(search 'pla'): play`
+    pub fn search_prompt(&self) -> Option<String> {
+        let search = self.search.as_ref()?;
+        let found = search.found.map(|i| self.history[i].as_str()).unwrap_or("");
+        Some(format!("(search '{}'): {}", search.query, found))
+    }
+
+    /// The candidates from the last completion, if there were several
+    pub fn completions(&self) -> Option<&[String]> {
+        self.completion.as_ref().map(|c| c.candidates.as_slice()).filter(|c| c.len() > 1)
+    }
+
+    pub fn clear(&mut self) {
+        self.set_text(String::new());
+        self.history_pos = None;
+        self.search = None;
+    }
+
+    fn set_text(&mut self, text: String) {
+        self.buf = text;
+        self.cursor = self.buf.len();
+        self.completion = None;
+    }
+
+    pub fn handle_key(&mut self, event: KeyEvent) -> LineEvent {
+        if self.search.is_some() {
+            return self.handle_search_key(event);
+        }
+        let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = event.modifiers.contains(KeyModifiers::ALT);
+        if event.code != KeyCode::Tab {
+            self.completion = None;
+        }
+        match event.code {
+            KeyCode::Esc => return LineEvent::Cancel,
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut self.buf);
+                self.clear();
+                self.push_history(&line);
+                return LineEvent::Submit(line);
+            }
+            KeyCode::Tab => self.complete(),
+            KeyCode::Char('r') if ctrl => {
+                self.search = Some(Search { query: String::new(), found: None });
+            }
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.buf.len(),
+            KeyCode::Char('b') if ctrl => self.cursor = self.prev_char(),
+            KeyCode::Char('f') if ctrl => self.cursor = self.next_char(),
+            KeyCode::Char('w') if ctrl => self.delete_to(self.prev_word()),
+            KeyCode::Char('u') if ctrl => self.delete_to(0),
+            KeyCode::Char('k') if ctrl => self.delete_to(self.buf.len()),
+            KeyCode::Char('b') if alt => self.cursor = self.prev_word(),
+            KeyCode::Char('f') if alt => self.cursor = self.next_word(),
+            KeyCode::Char('d') if alt => self.delete_to(self.next_word()),
+            KeyCode::Char(c) => {
+                self.buf.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            }
+            KeyCode::Backspace if ctrl || alt => self.delete_to(self.prev_word()),
+            KeyCode::Backspace => self.delete_to(self.prev_char()),
+            KeyCode::Delete => self.delete_to(self.next_char()),
+            KeyCode::Left if ctrl => self.cursor = self.prev_word(),
+            KeyCode::Right if ctrl => self.cursor = self.next_word(),
+            KeyCode::Left => self.cursor = self.prev_char(),
+            KeyCode::Right => self.cursor = self.next_char(),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.buf.len(),
+            KeyCode::Up => self.browse_history(true),
+            KeyCode::Down => self.browse_history(false),
+            _ => {}
+        }
+        LineEvent::None
+    }
+
+    fn handle_search_key(&mut self, event: KeyEvent) -> LineEvent {
+        let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+        let Some(search) = self.search.as_mut() else {
+            return LineEvent::None;
+        };
+        match event.code {
+            // Look for an older match of the same query
+            KeyCode::Char('r') if ctrl => {
+                let before = search.found.unwrap_or(self.history.len());
+                search.found = Self::find(&self.history, &search.query, before).or(search.found);
+            }
+            KeyCode::Char(c) => {
+                search.query.push(c);
+                search.found = Self::find(&self.history, &search.query, self.history.len());
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                search.found = Self::find(&self.history, &search.query, self.history.len());
+            }
+            KeyCode::Esc => self.search = None,
+            // Anything else accepts the match and is handled as usual
+            _ => {
+                let found = search.found.map(|i| self.history[i].clone());
+                self.search = None;
+                if let Some(line) = found {
+                    self.set_text(line);
+                }
+                return self.handle_key(event);
+            }
+        }
+        LineEvent::None
+    }
+
+    /// Finds the newest history entry before `before` containing `query`
+    fn find(history: &[String], query: &str, before: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        history[..before].iter().rposition(|line| line.contains(query))
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        let pos = match (self.history_pos, older) {
+            (None, true) if !self.history.is_empty() => {
+                self.stash = self.buf.clone();
+                Some(self.history.len() - 1)
+            }
+            (Some(p), true) => Some(p.saturating_sub(1)),
+            (Some(p), false) if p + 1 < self.history.len() => Some(p + 1),
+            (Some(_), false) => None,
+            (None, _) => return,
+        };
+        self.history_pos = pos;
+        let text = match pos {
+            Some(p) => self.history[p].clone(),
+            None => std::mem::take(&mut self.stash),
+        };
+        self.set_text(text);
+    }
+
+    fn push_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+        if let Some(path) = &self.history_file {
+            // Losing history is not worth interrupting anything over
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", line);
+                self.file_lines += 1;
+            }
+        }
+        // Appending is cheap, so the file is only cut back once it gets twice as long
+        if self.file_lines > 2 * HISTORY_LIMIT {
+            self.rewrite_history();
+        }
+    }
+
+    fn complete(&mut self) {
+        // Repeated presses cycle through the candidates of the first one
+        if let Some(completion) = self.completion.as_mut() {
+            if completion.candidates.len() > 1 {
+                let index = completion.index.map(|i| (i + 1) % completion.candidates.len()).unwrap_or(0);
+                completion.index = Some(index);
+                let replacement = completion.candidates[index].clone();
+                self.buf.replace_range(completion.start..self.cursor, &replacement);
+                self.cursor = completion.start + replacement.len();
+            }
+            return;
+        }
+        let (span, candidates) = complete(&self.buf, self.cursor);
+        let replacement = match candidates.as_slice() {
+            [] => return,
+            [only] if only.ends_with('/') => only.clone(),
+            [only] => format!("{} ", only),
+            _ => common_prefix(&candidates).to_string(),
+        };
+        self.buf.replace_range(span.start..self.cursor, &replacement);
+        self.cursor = span.start + replacement.len();
+        self.completion = Some(Completion { start: span.start, candidates, index: None });
+    }
+
+    fn delete_to(&mut self, to: usize) {
+        let range = self.cursor.min(to)..self.cursor.max(to);
+        self.cursor = range.start;
+        self.buf.replace_range(range, "");
+    }
+
+    fn prev_char(&self) -> usize {
+        self.buf[..self.cursor].char_indices().last().map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn next_char(&self) -> usize {
+        self.buf[self.cursor..].chars().next().map(|c| self.cursor + c.len_utf8()).unwrap_or(self.cursor)
+    }
+
+    /// Start of the word before the cursor, skipping whitespace first
+    fn prev_word(&self) -> usize {
+        let before = self.buf[..self.cursor].trim_end();
+        before.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0)
+    }
+
+    /// End of the word after the cursor, skipping whitespace first
+    fn next_word(&self) -> usize {
+        let after = &self.buf[self.cursor..];
+        let skipped = after.len() - after.trim_start().len();
+        let word = after[skipped..].find(char::is_whitespace).unwrap_or(after.len() - skipped);
+        self.cursor + skipped + word
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::view::command_line::{CommandLine, LineEvent};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    fn press(line: &mut CommandLine, code: KeyCode, modifiers: KeyModifiers) -> LineEvent {
+        line.handle_key(KeyEvent::new(code, modifiers))
+    }
+
+    fn type_str(line: &mut CommandLine, s: &str) {
+        for c in s.chars() {
+            press(line, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn editing() {
+        let mut line = CommandLine::new(None);
+        type_str(&mut line, "vib opt 6 0.1");
+        press(&mut line, KeyCode::Char('w'), KeyModifiers::CONTROL);
+        assert_eq!(line.text(), "vib opt 6 ");
+        press(&mut line, KeyCode::Home, KeyModifiers::NONE);
+        press(&mut line, KeyCode::Right, KeyModifiers::CONTROL);
+        type_str(&mut line, "x");
+        assert_eq!(line.text(), "vibx opt 6 ");
+        press(&mut line, KeyCode::Backspace, KeyModifiers::NONE);
+        press(&mut line, KeyCode::Delete, KeyModifiers::NONE);
+        assert_eq!(line.text(), "vibopt 6 ");
+        assert_eq!(line.cursor(), 3);
+    }
+
+    #[test]
+    fn history_and_search() {
+        let mut line = CommandLine::new(None);
+        for command in ["play", "time 1", "pause"] {
+            type_str(&mut line, command);
+            press(&mut line, KeyCode::Enter, KeyModifiers::NONE);
+        }
+        type_str(&mut line, "no");
+        press(&mut line, KeyCode::Up, KeyModifiers::NONE);
+        press(&mut line, KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(line.text(), "time 1");
+        press(&mut line, KeyCode::Down, KeyModifiers::NONE);
+        press(&mut line, KeyCode::Down, KeyModifiers::NONE);
+        assert_eq!(line.text(), "no");
+
+        line.clear();
+        press(&mut line, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        type_str(&mut line, "a");
+        assert_eq!(line.search_prompt().unwrap(), "(search 'a'): pause");
+        press(&mut line, KeyCode::Char('r'), KeyModifiers::CONTROL);
+        assert_eq!(line.search_prompt().unwrap(), "(search 'a'): play");
+        match press(&mut line, KeyCode::Enter, KeyModifiers::NONE) {
+            LineEvent::Submit(s) => assert_eq!(s, "play"),
+            _ => panic!("search should submit its match"),
+        }
+    }
+
+    #[test]
+    fn tab_completion() {
+        let mut line = CommandLine::new(None);
+        type_str(&mut line, "form sq");
+        press(&mut line, KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(line.text(), "form square ");
+
+        line.clear();
+        type_str(&mut line, "form s");
+        press(&mut line, KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(line.completions().unwrap().len(), 3);
+        press(&mut line, KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(line.text(), "form saw");
+        press(&mut line, KeyCode::Tab, KeyModifiers::NONE);
+        assert_eq!(line.text(), "form sine");
+    }
+
+    #[test]
+    fn history_file_is_cut_back() {
+        let path = std::env::temp_dir().join(format!("fmangroove_history_test_{}", std::process::id()));
+        let lines = (0..1500).map(|i| format!("seek {}", i)).collect::<Vec<_>>();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let mut line = CommandLine::new(Some(path.clone()));
+        let kept = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(kept.lines().count(), 1000);
+        assert_eq!(kept.lines().next(), Some("seek 500"));
+
+        for i in 0..1001 {
+            type_str(&mut line, &format!("time {}", i));
+            press(&mut line, KeyCode::Enter, KeyModifiers::NONE);
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1000);
+        std::fs::remove_file(path).unwrap();
+    }
+}
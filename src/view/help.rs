@@ -0,0 +1,91 @@
+use crate::command::registry;
+use crate::view::tui_elements::{BorderKind, TuiRect};
+use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::{cursor, style, QueueableCommand};
+use std::io::{stdout, Result};
+
+/// Keys that aren't commands, shown in the help overview
+const KEYS: [(&str, &str); 12] = [
+    (":", "Opens the command bar"),
+    ("u", "Undoes the last edit"),
+    ("Ctrl-r", "Redoes the last undone edit"),
+    ("Ctrl-c, Ctrl-d", "Quits"),
+    ("Tab", "Completes the word before the cursor in the command bar"),
+    ("Up, Down", "Browses command history"),
+    ("Ctrl-r", "Searches command history while in the command bar"),
+    ("Left, Right", "Moves the cursor, by word while holding Ctrl"),
+    ("Ctrl-a, Ctrl-e", "Moves the cursor to the start or end"),
+    ("Ctrl-w", "Deletes the word before the cursor"),
+    ("Ctrl-u, Ctrl-k", "Deletes to the start or end"),
+    ("Esc", "Leaves the command bar or closes this panel"),
+];
+
+/// Scrollable panel showing help on a topic
+pub struct HelpPanel {
+    title: String,
+    lines: Vec<String>,
+    scroll: usize,
+}
+
+impl HelpPanel {
+    /// Help on `topic`, or an overview of everything without one. `None` for unknown topics
+    pub fn new(topic: Option<&str>) -> Option<Self> {
+        let mut lines = registry::help(topic)?;
+        if topic.is_none() {
+            lines.push(String::from("Keys"));
+            let width = KEYS.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+            for (key, what) in KEYS {
+                lines.push(format!("  {:<width$}  {}", key, what, width = width));
+            }
+        }
+        Some(Self {
+            title: format!("Help: {}", topic.unwrap_or("overview")),
+            lines,
+            scroll: 0,
+        })
+    }
+
+    /// A panel showing any lines, like the parameters of an instrument
+    pub fn with_lines(title: String, lines: Vec<String>) -> Self {
+        Self { title, lines, scroll: 0 }
+    }
+
+    /// Scrolls on navigation keys. Returns `false` when the panel should close
+    pub fn handle_key(&mut self, event: KeyEvent) -> bool {
+        let page = crossterm::terminal::size().map(|(_, h)| h as usize / 2).unwrap_or(10);
+        let max = self.lines.len().saturating_sub(1);
+        match event.code {
+            KeyCode::Esc | KeyCode::Char('q') => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.scroll = (self.scroll + 1).min(max),
+            KeyCode::Up | KeyCode::Char('k') => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageDown | KeyCode::Char(' ') => self.scroll = (self.scroll + page).min(max),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(page),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll = 0,
+            KeyCode::End | KeyCode::Char('G') => self.scroll = max,
+            _ => {}
+        }
+        true
+    }
+
+    pub fn draw(&self) -> Result<()> {
+        let (w, h) = crossterm::terminal::size()?;
+        if w < 30 || h < 15 {
+            return Ok(());
+        }
+        let (left, top, right, bottom) = (2, 1, w - 3, h - 3);
+        TuiRect::draw_rect(self.title.clone(), BorderKind::Double, (left, top), (right, bottom))?;
+        let width = (right - left - 3) as usize;
+        let height = (bottom - top - 1) as usize;
+        for (i, line) in self.lines.iter().skip(self.scroll).take(height).enumerate() {
+            stdout()
+                .queue(cursor::MoveTo(left + 2, top + 1 + i as u16))?
+                .queue(style::Print(line.chars().take(width).collect::<String>()))?;
+        }
+        if self.scroll + height < self.lines.len() {
+            stdout()
+                .queue(cursor::MoveTo(right - 12, bottom))?
+                .queue(style::Print(" more (j/k) "))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use crate::app::App;
+use crate::automation::{Breakpoint, Curve, Lane};
+use crate::instrument::param::{self, ParamDescriptor};
+use crate::view::tui_elements::{BorderKind, TuiRect};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::Stylize;
+use crossterm::{cursor, style, QueueableCommand};
+use std::io::{stdout, Result};
+
+const HINT: &str = " j/k select  h/l move  +/- value  c curve  a add  d delete  q close ";
+
+/// Panel for viewing and editing the automation lane of one parameter
+pub struct LanePanel {
+    target: u128,
+    name: &'static str,
+    selected: usize,
+}
+
+impl LanePanel {
+    pub fn new(target: u128, name: &'static str) -> Self {
+        Self { target, name, selected: 0 }
+    }
+
+    fn descriptor(&self, app: &App) -> Option<&'static ParamDescriptor> {
+        param::find(app.instruments.get(self.target as usize)?.params(), self.name)
+    }
+
+    fn points(&self, app: &App) -> Vec<(u128, Breakpoint)> {
+        app.automation.lane(self.target, self.name).map(|lane| lane.points().collect()).unwrap_or_default()
+    }
+
+    /// Edits the lane on key presses. Returns `false` when the panel should close
+    pub fn handle_key(&mut self, event: KeyEvent, app: &mut App) -> bool {
+        let Some(desc) = self.descriptor(app) else {
+            return false;
+        };
+        // Moves are 10ms, or 100ms in capitals, and value changes are 1% of the range, or 10%
+        let step = ((app.get_sample_rate() / 100.0) as u128).max(1);
+        let nudge = match desc.labels.is_empty() {
+            true => (desc.max - desc.min) / 100.0,
+            false => 1.0,
+        };
+        let points = self.points(app);
+        let selected = points.get(self.selected).copied();
+        match (event.code, selected) {
+            (KeyCode::Esc | KeyCode::Char('q'), _) => return false,
+            (KeyCode::Char('u'), _) => {
+                app.undo();
+            }
+            (KeyCode::Char('r'), _) if event.modifiers == KeyModifiers::CONTROL => {
+                app.redo();
+            }
+            (KeyCode::Down | KeyCode::Char('j'), _) => self.selected += 1,
+            (KeyCode::Up | KeyCode::Char('k'), _) => self.selected = self.selected.saturating_sub(1),
+            (KeyCode::Char('h'), Some((time, point))) => self.move_point(app, time, point, time.saturating_sub(step)),
+            (KeyCode::Char('H'), Some((time, point))) => self.move_point(app, time, point, time.saturating_sub(step * 10)),
+            (KeyCode::Char('l'), Some((time, point))) => self.move_point(app, time, point, time + step),
+            (KeyCode::Char('L'), Some((time, point))) => self.move_point(app, time, point, time + step * 10),
+            (KeyCode::Char('+' | '=' | '>' | '-' | '<'), Some((time, mut point))) => {
+                point.value += match event.code {
+                    KeyCode::Char('+' | '=') => nudge,
+                    KeyCode::Char('>') => nudge * 10.0,
+                    KeyCode::Char('-') => -nudge,
+                    _ => -nudge * 10.0,
+                };
+                let _ = app.set_breakpoint(self.target, self.name, time, point);
+            }
+            (KeyCode::Char('c'), Some((time, mut point))) => {
+                point.curve = point.curve.next();
+                let _ = app.set_breakpoint(self.target, self.name, time, point);
+            }
+            (KeyCode::Char('d' | 'x'), Some((time, _))) => app.remove_breakpoint(self.target, self.name, time),
+            (KeyCode::Char('a'), Some((time, point))) => {
+                // Halfway to the next breakpoint, or a second after the last one
+                let new_time = match points.get(self.selected + 1) {
+                    Some((next, _)) if next - time > 1 => time + (next - time) / 2,
+                    Some(_) => return true,
+                    None => time + step * 100,
+                };
+                let value = app.automation.lane(self.target, self.name).and_then(|lane| lane.value_at(new_time));
+                let new_point = Breakpoint { value: value.unwrap_or(point.value), curve: point.curve };
+                let _ = app.set_breakpoint(self.target, self.name, new_time, new_point);
+                self.selected += 1;
+            }
+            (KeyCode::Char('a'), None) => {
+                let value = app.instruments[self.target as usize].get_param(self.name).unwrap_or(desc.default);
+                let point = Breakpoint { value, curve: Curve::Linear };
+                let _ = app.set_breakpoint(self.target, self.name, 0, point);
+            }
+            _ => {}
+        }
+        self.selected = self.selected.min(self.points(app).len().saturating_sub(1));
+        true
+    }
+
+    /// Moves a breakpoint to a free tick, as one undo step
+    fn move_point(&mut self, app: &mut App, time: u128, point: Breakpoint, to: u128) {
+        let lane = app.automation.lane(self.target, self.name);
+        if to == time || lane.and_then(|lane| lane.get(to)).is_some() {
+            return;
+        }
+        app.begin_group();
+        app.remove_breakpoint(self.target, self.name, time);
+        let _ = app.set_breakpoint(self.target, self.name, to, point);
+        app.end_group();
+        self.selected = self.points(app).iter().position(|(t, _)| *t == to).unwrap_or(0);
+    }
+
+    pub fn draw(&self, app: &App) -> Result<()> {
+        let (w, h) = crossterm::terminal::size()?;
+        let Some(desc) = self.descriptor(app) else {
+            return Ok(());
+        };
+        if w < 30 || h < 15 {
+            return Ok(());
+        }
+        let (left, top, right, bottom) = (2, 1, w - 3, h - 3);
+        let inst = app.instruments[self.target as usize].name();
+        let title = format!("Lane: {} {} {}", self.target, inst, self.name);
+        TuiRect::draw_rect(title, BorderKind::Double, (left, top), (right, bottom))?;
+        stdout()
+            .queue(cursor::MoveTo(right.saturating_sub(HINT.len() as u16 + 1), bottom))?
+            .queue(style::Print(HINT))?;
+
+        let empty = Lane::default();
+        let lane = app.automation.lane(self.target, self.name).unwrap_or(&empty);
+        let points = self.points(app);
+        let sample_rate = app.get_sample_rate().max(1.0);
+        let width = (right - left - 3) as usize;
+        let graph_height = ((bottom - top - 1) / 2).max(3) as usize;
+
+        // The graph spans from the first to the last breakpoint, and at least a second
+        let start = points.first().map_or(0, |(t, _)| *t);
+        let end = points.last().map_or(0, |(t, _)| *t).max(start + sample_rate as u128);
+        let row_of = |value: f32| {
+            let x = ((desc.max - value) / (desc.max - desc.min)).clamp(0.0, 1.0);
+            (x * (graph_height - 1) as f32).round() as u16
+        };
+        let column_of = |tick: u128| ((tick - start) as f64 / (end - start) as f64 * (width - 1) as f64).round() as u16;
+        for column in 0..width {
+            let tick = start + ((end - start) as f64 * column as f64 / (width - 1) as f64) as u128;
+            if let Some(value) = lane.value_at(tick) {
+                stdout()
+                    .queue(cursor::MoveTo(left + 2 + column as u16, top + 1 + row_of(value)))?
+                    .queue(style::Print("·"))?;
+            }
+        }
+        for (i, (time, point)) in points.iter().enumerate() {
+            let mark = match i == self.selected {
+                true => "●".reverse(),
+                false => "●".stylize(),
+            };
+            stdout()
+                .queue(cursor::MoveTo(left + 2 + column_of(*time), top + 1 + row_of(point.value)))?
+                .queue(style::PrintStyledContent(mark))?;
+        }
+
+        // Breakpoints are listed under the graph, scrolled to keep the selected one in view
+        let list_top = top + 2 + graph_height as u16;
+        let list_height = (bottom - list_top) as usize;
+        if points.is_empty() {
+            stdout()
+                .queue(cursor::MoveTo(left + 2, list_top))?
+                .queue(style::Print("No breakpoints, press a to add one at the start"))?;
+        }
+        let skip = (self.selected + 1).saturating_sub(list_height);
+        for (i, (time, point)) in points.iter().enumerate().skip(skip).take(list_height) {
+            let line = format!(
+                "{} {:>10.3}s  {:<12}  {}",
+                if i == self.selected { ">" } else { " " },
+                *time as f64 / sample_rate as f64,
+                desc.format(point.value),
+                point.curve,
+            );
+            stdout()
+                .queue(cursor::MoveTo(left + 2, list_top + (i - skip) as u16))?
+                .queue(style::Print(line.chars().take(width).collect::<String>()))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use crate::app::App;
+use crate::command::args::note_name;
+use crate::instruction::{InstructionKind, Status, MAX_VELOCITY};
+use crate::view::tui_elements::{BorderKind, TuiRect};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::Stylize;
+use crossterm::{cursor, style, QueueableCommand};
+use std::io::{stdout, Result};
+
+const HINT: &str = " j/k row  h/l column  +/- change  </> more  n note  x clear  Enter target  q close ";
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Column {
+    Note,
+    Velocity,
+}
+
+/// What the view model should do after a key press in the pattern
+pub enum PatternEvent {
+    None,
+    Close,
+    /// Sets the target time to a tick and closes
+    Target(u128),
+}
+
+/// What is played in one row of the pattern
+#[derive(Default)]
+struct Row {
+    note: Option<(u128, InstructionKind)>,
+    state: Option<(u128, Status, u8)>,
+    others: usize,
+}
+
+/// Tracker style view of the instructions of one instrument, a row per sixteenth note of the
+/// tempo map
+pub struct PatternPanel {
+    target: u128,
+    row: u128,
+    scroll: u128,
+    column: Column,
+}
+
+impl PatternPanel {
+    pub fn new(target: u128, row: u128) -> Self {
+        Self { target, row, scroll: row.saturating_sub(4), column: Column::Note }
+    }
+
+    fn read_row(&self, app: &App, row: u128) -> Row {
+        let (start, end) = (app.tempo.row_tick(row), app.tempo.row_tick(row + 1));
+        let mut contents = Row::default();
+        for (time, kind) in app.instructions.range(self.target, start, end) {
+            match kind {
+                InstructionKind::Note(_) | InstructionKind::Frequency(_) if contents.note.is_none() => contents.note = Some((time, kind)),
+                InstructionKind::State { status, velocity } if contents.state.is_none() => contents.state = Some((time, status, velocity)),
+                _ => contents.others += 1,
+            }
+        }
+        contents
+    }
+
+    pub fn handle_key(&mut self, event: KeyEvent, app: &mut App) -> PatternEvent {
+        if app.instruments.get(self.target as usize).is_none() {
+            return PatternEvent::Close;
+        }
+        let page = crossterm::terminal::size().map(|(_, h)| h as u128 / 2).unwrap_or(10);
+        let row = self.read_row(app, self.row);
+        let change = match event.code {
+            KeyCode::Char('+' | '=') => 1,
+            KeyCode::Char('-') => -1,
+            KeyCode::Char('>') => if self.column == Column::Note { 12 } else { 10 },
+            KeyCode::Char('<') => if self.column == Column::Note { -12 } else { -10 },
+            _ => 0,
+        };
+        match event.code {
+            KeyCode::Esc | KeyCode::Char('q') => return PatternEvent::Close,
+            KeyCode::Enter => return PatternEvent::Target(app.tempo.row_tick(self.row)),
+            KeyCode::Char('u') => {
+                app.undo();
+            }
+            KeyCode::Char('r') if event.modifiers == KeyModifiers::CONTROL => {
+                app.redo();
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.row += 1,
+            KeyCode::Up | KeyCode::Char('k') => self.row = self.row.saturating_sub(1),
+            KeyCode::PageDown => self.row += page,
+            KeyCode::PageUp => self.row = self.row.saturating_sub(page),
+            KeyCode::Left | KeyCode::Char('h') => self.column = Column::Note,
+            KeyCode::Right | KeyCode::Char('l') => self.column = Column::Velocity,
+            KeyCode::Char('n') => {
+                // Plays the note from the closest row above that has one, or C-4
+                let note = (0..self.row)
+                    .rev()
+                    .find_map(|r| match self.read_row(app, r).note {
+                        Some((_, InstructionKind::Note(n))) => Some(n),
+                        _ => None,
+                    })
+                    .unwrap_or(60);
+                let time = app.tempo.row_tick(self.row);
+                app.begin_group();
+                app.insert_instruction(self.target, time, InstructionKind::Note(note));
+                app.insert_instruction(self.target, time, InstructionKind::State { status: Status::On, velocity: MAX_VELOCITY });
+                app.end_group();
+            }
+            KeyCode::Char('x') => match (self.column, row.note, row.state) {
+                (Column::Note, Some((time, kind)), _) => app.remove_instruction(self.target, time, kind),
+                (Column::Velocity, _, Some((time, status, _))) => {
+                    app.insert_instruction(self.target, time, InstructionKind::State { status, velocity: MAX_VELOCITY })
+                }
+                _ => {}
+            },
+            _ if change != 0 => match (self.column, row.note, row.state) {
+                (Column::Note, Some((time, InstructionKind::Note(n))), _) => {
+                    let note = (n as i32 + change).clamp(0, 127) as u16;
+                    app.insert_instruction(self.target, time, InstructionKind::Note(note));
+                }
+                (Column::Velocity, _, Some((time, Status::On, velocity))) => {
+                    let velocity = (velocity as i32 + change).clamp(0, MAX_VELOCITY as i32) as u8;
+                    app.insert_instruction(self.target, time, InstructionKind::State { status: Status::On, velocity });
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        PatternEvent::None
+    }
+
+    pub fn draw(&mut self, app: &App) -> Result<()> {
+        let (w, h) = crossterm::terminal::size()?;
+        let Some(inst) = app.instruments.get(self.target as usize) else {
+            return Ok(());
+        };
+        if w < 50 || h < 15 {
+            return Ok(());
+        }
+        let (left, top, right, bottom) = (2, 1, w - 3, h - 3);
+        TuiRect::draw_rect(format!("Pattern: {} {}", self.target, inst.name()), BorderKind::Double, (left, top), (right, bottom))?;
+        stdout()
+            .queue(cursor::MoveTo(right.saturating_sub(HINT.len() as u16 + 1), bottom))?
+            .queue(style::Print(HINT))?;
+
+        // Keep the cursor in view
+        let height = (bottom - top - 2) as u128;
+        if self.row < self.scroll {
+            self.scroll = self.row;
+        } else if self.row >= self.scroll + height {
+            self.scroll = self.row + 1 - height;
+        }
+
+        stdout()
+            .queue(cursor::MoveTo(left + 2, top + 1))?
+            .queue(style::Print(format!("{:>5}  {:>9}  {:<8}  {:>3}  {}", "row", "time", "note", "vel", "more").bold()))?;
+        let sample_rate = app.get_sample_rate().max(1.0);
+        for (line, r) in (self.scroll..self.scroll + height).enumerate() {
+            let row = self.read_row(app, r);
+            let note = match row.note {
+                Some((_, InstructionKind::Note(n))) => note_name(n),
+                Some((_, InstructionKind::Frequency(hz))) => format!("{:.0}Hz", hz),
+                _ => String::from("..."),
+            };
+            let (note, velocity) = match row.state {
+                Some((_, Status::On, velocity)) => (note, velocity.to_string()),
+                // Released notes are shown the way trackers do
+                Some((_, Status::Off, _)) => (String::from("==="), String::from("..")),
+                None => (note, String::from("..")),
+            };
+            let more = match row.others {
+                0 => String::new(),
+                n => format!("+{}", n),
+            };
+            let time = app.tempo.row_tick(r) as f32 / sample_rate;
+            let selected = |column: Column, text: String| match r == self.row && column == self.column {
+                true => text.reverse(),
+                false => text.stylize(),
+            };
+            stdout()
+                .queue(cursor::MoveTo(left + 2, top + 2 + line as u16))?
+                .queue(style::Print(format!("{:>5}  {:>8.3}s  ", r, time)))?
+                .queue(style::PrintStyledContent(selected(Column::Note, format!("{:<8}", note))))?
+                .queue(style::Print("  "))?
+                .queue(style::PrintStyledContent(selected(Column::Velocity, format!("{:>3}", velocity))))?
+                .queue(style::Print(format!("  {}", more)))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use crate::app::App;
+use crate::command::args::note_name;
+use crate::instruction::{InstructionKind, Status, MIDI_OFFSET};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+
+/// Keys of the computer keyboard laid out like a piano, two octaves up from the bottom row
+const KEYS: [(char, u16); 24] = [
+    ('z', 0), ('s', 1), ('x', 2), ('d', 3), ('c', 4), ('v', 5), ('g', 6), ('b', 7), ('h', 8), ('n', 9), ('j', 10), ('m', 11),
+    ('q', 12), ('2', 13), ('w', 14), ('3', 15), ('e', 16), ('r', 17), ('5', 18), ('t', 19), ('6', 20), ('y', 21), ('7', 22), ('u', 23),
+];
+
+/// Plays notes on the computer keyboard, writing them into the song at the playhead when
+/// recording or only letting them be heard when jamming
+pub struct Recorder {
+    target: u128,
+    recording: bool,
+    quantize: bool,
+    velocity: u8,
+    octave: u16,
+    /// Key and recorded tick of the note being held
+    held: Option<(char, u128)>,
+    /// Whether the terminal tells when keys are let go. Otherwise notes are ended with space
+    /// or by the next note
+    releases: bool,
+}
+
+impl Recorder {
+    pub fn new(target: u128, quantize: bool, velocity: u8, releases: bool) -> Self {
+        Self { target, recording: true, quantize, velocity, octave: 4, held: None, releases }
+    }
+
+    pub fn jam(target: u128, velocity: u8, releases: bool) -> Self {
+        Self { recording: false, ..Self::new(target, false, velocity, releases) }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    fn note_on(&mut self, key: char, note: u16, app: &mut App) {
+        let on = InstructionKind::State { status: Status::On, velocity: self.velocity };
+        if !self.recording {
+            app.audition(self.target, &[InstructionKind::Note(note), on]);
+            self.held = Some((key, 0));
+            return;
+        }
+        let tick = app.record_tick(self.target, self.quantize);
+        app.record(self.target, tick, &[InstructionKind::Note(note), on]);
+        self.held = Some((key, tick));
+    }
+
+    fn note_off(&mut self, app: &mut App) {
+        let Some((_, on)) = self.held.take() else {
+            return;
+        };
+        let off = InstructionKind::State { status: Status::Off, velocity: 0 };
+        if !self.recording {
+            app.audition(self.target, &[off]);
+            return;
+        }
+        // A note ends at least a row, or a tick, after it started so the two don't share a slot
+        let mut tick = app.record_tick(self.target, self.quantize);
+        if tick <= on {
+            tick = match self.quantize {
+                true => app.tempo.row_tick(app.tempo.row_at(on) + 1),
+                false => on + 1,
+            };
+        }
+        app.record(self.target, tick, &[off]);
+    }
+
+    /// Records key presses and releases. Returns `false` when recording should stop
+    pub fn handle_key(&mut self, event: KeyEvent, app: &mut App) -> bool {
+        let key = match event.code {
+            KeyCode::Char(c) => c.to_ascii_lowercase(),
+            KeyCode::Esc => {
+                self.note_off(app);
+                return false;
+            }
+            _ => return true,
+        };
+        let note = KEYS.iter().find(|(k, _)| *k == key).map(|(_, offset)| self.octave_note() + offset);
+        match (event.kind, note) {
+            (KeyEventKind::Repeat, _) => {}
+            (KeyEventKind::Release, Some(_)) => {
+                if self.held.is_some_and(|(held, _)| held == key) {
+                    self.note_off(app);
+                }
+            }
+            (KeyEventKind::Release, None) => {}
+            // Instruments play one note at a time, so a new note takes over from the held one
+            (KeyEventKind::Press, Some(note)) => self.note_on(key, note.min(127), app),
+            (KeyEventKind::Press, None) => match key {
+                ' ' => self.note_off(app),
+                '[' => self.octave = self.octave.saturating_sub(1),
+                ']' => self.octave = (self.octave + 1).min(8),
+                _ => {}
+            },
+        }
+        true
+    }
+
+    /// The C the bottom row starts on
+    fn octave_note(&self) -> u16 {
+        (self.octave + 1) * 12 - MIDI_OFFSET
+    }
+
+    pub fn status(&self) -> String {
+        let ends = if self.releases { "release or space" } else { "space" };
+        let what = match self.recording {
+            true => format!("Recording  quantize {}", if self.quantize { "on" } else { "off" }),
+            false => String::from("Jamming"),
+        };
+        format!(
+            "{}  octave {} ({})  velocity {}  [ ] octave  {} ends a note  Esc stops",
+            what,
+            self.octave,
+            note_name(self.octave_note()),
+            self.velocity,
+            ends,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::App;
+    use crate::instruction::{InstructionKind, Status};
+    use crate::view::record::Recorder;
+    use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+    #[test]
+    fn records_notes_at_the_playhead() {
+        let mut app = App::new();
+        // Rows are 125 ticks long at 120 BPM
+        app.set_sample_rates(1000.0);
+        app.play();
+        let mut recorder = Recorder::new(0, true, 90, true);
+        let key = |c, kind| KeyEvent::new_with_kind(KeyCode::Char(c), KeyModifiers::NONE, kind);
+        for _ in 0..140 {
+            app.tick_all();
+        }
+        recorder.handle_key(key('z', KeyEventKind::Press), &mut app);
+        for _ in 0..20 {
+            app.tick_all();
+        }
+        recorder.handle_key(key('z', KeyEventKind::Release), &mut app);
+        // The bottom row starts on C-4
+        assert!(app.instructions.has(0, 125, InstructionKind::Note(51)));
+        assert!(app.instructions.has(0, 125, InstructionKind::State { status: Status::On, velocity: 90 }));
+        // Let go in the same row, so the note lasts the row
+        assert!(app.instructions.has(0, 250, InstructionKind::State { status: Status::Off, velocity: 0 }));
+    }
+
+    #[test]
+    fn jamming_plays_while_paused_without_recording() {
+        let mut app = App::new();
+        app.set_sample_rates(1000.0);
+        let mut recorder = Recorder::jam(0, 100, false);
+        recorder.handle_key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE), &mut app);
+        let silent = (0..100).all(|_| app.tick_all() == (0.0, 0.0));
+        assert!(silent);
+
+        app.set_jamming(true);
+        recorder.handle_key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE), &mut app);
+        let heard = (0..100).any(|_| app.tick_all() != (0.0, 0.0));
+        assert!(heard);
+        assert_eq!(app.position(), 0);
+        assert!(app.instructions.range(0, 0, u128::MAX).is_empty());
+    }
+}